@@ -1,3 +1,13 @@
+#include <algorithm>
+#include <cerrno>
+#include <charconv>
+#include <cmath>
+#include <cstdint>
+#include <cstdlib>
+#include <exception>
+#include <optional>
+#include <span>
+#include <sstream>
 #include <string>
 #include <variant>
 #include <iostream>
@@ -6,25 +16,54 @@
 #include <memory>
 #include <type_traits>
 
+#include "simplelisp-case.h"
+
 using namespace std::string_literals;
 
 struct ValueImpl;
 class SimpleListObject;
+struct ValueMap;
+
+// Errors raised by the runtime itself, a `catch` sees them as a map with a
+// "kind" and a "message", like in the interpreter.
+struct RuntimeError
+{
+    std::string kind;
+    std::string message;
+};
+
+[[noreturn]] inline void raise_error(const std::string& kind, const std::string& message)
+{
+    throw RuntimeError { kind, message };
+}
+
+// Thrown by `break` and `continue`, and caught by the loop around them.
+struct BreakLoop {};
+struct ContinueLoop {};
+
+// `:name` at a call site, binding the next argument by name.
+struct Keyword
+{
+    std::string name;
+};
 
 #define IS_NULL(x) std::holds_alternative<std::monostate>(x)
-#define IS_INT(x) std::holds_alternative<int>(x)
-#define IS_FLOAT(x) std::holds_alternative<float>(x)
+#define IS_INT(x) std::holds_alternative<int64_t>(x)
+#define IS_FLOAT(x) std::holds_alternative<double>(x)
 #define IS_STR(x) std::holds_alternative<std::string>(x)
 #define IS_BOOL(x) std::holds_alternative<bool>(x)
 #define IS_VEC(x) std::holds_alternative<std::vector<Value>>(x)
 #define IS_FUNC(x) std::holds_alternative<Value::Function>(x)
+#define IS_MAP(x) std::holds_alternative<std::shared_ptr<ValueMap>>(x)
 #define IS_INSTANCE(x) std::holds_alternative<std::shared_ptr<SimpleListObject>>(x)
+#define IS_KEYWORD(x) std::holds_alternative<Keyword>(x)
 
-#define AS_INT(x) std::get<int>(x)
-#define AS_FLOAT(x) std::get<float>(x)
+#define AS_INT(x) std::get<int64_t>(x)
+#define AS_FLOAT(x) std::get<double>(x)
 #define AS_STR(x) std::get<std::string>(x)
 #define AS_BOOL(x) std::get<bool>(x)
 #define AS_VEC(x) std::get<std::vector<Value>>(x)
+#define AS_MAP(x) (*std::get<std::shared_ptr<ValueMap>>(x))
 
 class Value
 {
@@ -32,10 +71,17 @@ public:
     using Function = std::function<Value(std::vector<Value>)>;
 
     Value() : inner {} {}
-    Value(int i) : inner { i } {}
-    Value(float f) : inner { f } {}
+    // Only bools, as pointers would otherwise convert to them.
+    template <typename T, std::enable_if_t<std::is_same_v<T, bool>, int> = 0>
+    Value(T b) : inner { b } {}
+    Value(int i) : inner { int64_t { i } } {}
+    Value(int64_t i) : inner { i } {}
+    Value(float f) : inner { double { f } } {}
+    Value(double f) : inner { f } {}
     Value(std::string s) : inner { s } {}
     Value(std::vector<Value> v) : inner { v } {}
+    Value(std::shared_ptr<ValueMap> m) : inner { m } {}
+    Value(Keyword k) : inner { k } {}
     Value(std::string name, Function&& f) : name { name }, inner { f } {}
     Value(Function&& f) : inner { f } {}
     Value(SimpleListObject* obj) : inner { std::shared_ptr<SimpleListObject>(obj) } {}
@@ -51,6 +97,36 @@ public:
         return as_func()(args);
     }
 
+    // Integers are also accepted as conditions, 0 being false.
+    explicit operator bool() const
+    {
+        if (IS_BOOL(inner))
+        {
+            return AS_BOOL(inner);
+        }
+        return IS_INT(inner) ? AS_INT(inner) != 0 : !IS_NULL(inner);
+    }
+
+    int64_t as_int() const
+    {
+        if (IS_INT(inner))
+        {
+            return AS_INT(inner);
+        }
+
+        raise_error("type error", "value is not an int but " + get_type());
+    }
+
+    double as_float() const
+    {
+        if (IS_FLOAT(inner))
+        {
+            return AS_FLOAT(inner);
+        }
+
+        raise_error("type error", "value is not a float but " + get_type());
+    }
+
     Function& as_func()
     {
         if (IS_FUNC(inner))
@@ -58,8 +134,32 @@ public:
             return std::get<Function>(inner);
         }
 
-        std::cerr << "value is not a function but " << get_type() << '\n';
-        std::exit(1);
+        raise_error("type error", "value is not a function but " + get_type());
+    }
+
+    std::vector<Value>& as_list()
+    {
+        if (IS_VEC(inner))
+        {
+            return AS_VEC(inner);
+        }
+
+        raise_error("type error", "value is not a list but " + get_type());
+    }
+
+    const std::vector<Value>& as_list() const
+    {
+        return const_cast<Value*>(this)->as_list();
+    }
+
+    ValueMap& as_map() const
+    {
+        if (IS_MAP(inner))
+        {
+            return AS_MAP(inner);
+        }
+
+        raise_error("type error", "value is not a map but " + get_type());
     }
 
     SimpleListObject* as_instance() const
@@ -69,8 +169,7 @@ public:
             return std::get<std::shared_ptr<SimpleListObject>>(inner).get();
         }
 
-        std::cerr << "value is not an instance but " << get_type() << '\n';
-        std::exit(1);
+        raise_error("type error", "value is not an instance but " + get_type());
     }
 
     std::string as_string() const
@@ -80,8 +179,7 @@ public:
             return AS_STR(inner);
         }
         
-        std::cerr << "value is not an string nor convertible to a string but " << get_type() << '\n';
-        std::exit(1);
+        raise_error("type error", "value is not an string nor convertible to a string but " + get_type());
     }
 
     bool is_instance() const
@@ -89,12 +187,36 @@ public:
         return IS_INSTANCE(inner);
     }
 
+    bool is_list() const
+    {
+        return IS_VEC(inner);
+    }
+
+    bool is_map() const
+    {
+        return IS_MAP(inner);
+    }
+
+    bool is_function() const
+    {
+        return IS_FUNC(inner);
+    }
+
+    const Keyword* as_keyword() const
+    {
+        return std::get_if<Keyword>(&inner);
+    }
+
     std::string get_type() const
     {
         if (IS_NULL(inner))
         {
             return "NULL";
         }
+        else if (IS_BOOL(inner))
+        {
+            return "bool";
+        }
         else if (IS_INT(inner))
         {
             return "int";
@@ -117,6 +239,10 @@ public:
             str += "]";
             return str;
         }
+        else if (IS_MAP(inner))
+        {
+            return "map";
+        }
         else if (IS_FUNC(inner))
         {
             return "function";
@@ -125,6 +251,10 @@ public:
         {
             return "instance";
         }
+        else if (IS_KEYWORD(inner))
+        {
+            return "keyword";
+        }
 
         return "unknown";
     }
@@ -139,9 +269,99 @@ private:
     friend bool operator==(const Value& lhs, const Value& rhs);
 
     std::string name;
-    std::variant<std::monostate, int, float, std::string, std::vector<Value>, Function, std::shared_ptr<SimpleListObject>> inner;
+    std::variant<std::monostate, bool, int64_t, double, std::string, std::vector<Value>, std::shared_ptr<ValueMap>, Function, std::shared_ptr<SimpleListObject>, Keyword> inner;
+};
+
+struct ValueMap
+{
+    std::vector<std::pair<Value, Value>> entries;
+};
+
+inline bool operator==(const Value& lhs, const Value& rhs);
+
+inline std::vector<std::pair<Value, Value>>::iterator map_find(ValueMap& map, const Value& key)
+{
+    return std::find_if(map.entries.begin(), map.entries.end(), [&](auto& entry) { return entry.first == key; });
+}
+
+// A key given twice keeps its first position and its last value.
+inline void map_insert(ValueMap& map, Value key, Value value)
+{
+    auto found = map_find(map, key);
+    if (found != map.entries.end())
+    {
+        found->second = std::move(value);
+    }
+    else
+    {
+        map.entries.emplace_back(std::move(key), std::move(value));
+    }
+}
+
+inline Value make_map(std::vector<std::pair<Value, Value>> entries)
+{
+    auto map = std::make_shared<ValueMap>();
+    for (auto& [key, value] : entries)
+    {
+        map_insert(*map, key, value);
+    }
+    return map;
+}
+
+inline Value throw_value(Value value)
+{
+    throw value;
+}
+
+// Thrown by `return` inside a loop, and caught by the function around it.
+struct ReturnValue
+{
+    Value value;
 };
 
+[[noreturn]] inline Value return_value(Value value)
+{
+    throw ReturnValue { value };
+}
+
+// Gives the list a `let` pattern of `count` elements destructures.
+inline std::vector<Value>& destructure(Value& value, size_t count)
+{
+    auto& list = value.as_list();
+    if (list.size() < count)
+    {
+        raise_error("index error", "Can't destructure a list of " + std::to_string(list.size()) + " elements into " + std::to_string(count) + " patterns.");
+    }
+    return list;
+}
+
+[[noreturn]] inline Value break_loop()
+{
+    throw BreakLoop {};
+}
+
+[[noreturn]] inline Value continue_loop()
+{
+    throw ContinueLoop {};
+}
+
+// The value a `catch` gets for the exception being handled.
+inline Value caught_value(std::exception_ptr exception)
+{
+    try
+    {
+        std::rethrow_exception(exception);
+    }
+    catch (const Value& value)
+    {
+        return value;
+    }
+    catch (const RuntimeError& error)
+    {
+        return make_map({ { "kind"s, error.kind }, { "message"s, error.message } });
+    }
+}
+
 std::ostream& operator<<(std::ostream& os, const Value& obj)
 {
     auto & inner = obj.inner;
@@ -149,6 +369,10 @@ std::ostream& operator<<(std::ostream& os, const Value& obj)
     {
         os << "NULL";
     }
+    else if (IS_BOOL(inner))
+    {
+        os << AS_BOOL(inner);
+    }
     else if (IS_INT(inner))
     {
         os << AS_INT(inner);
@@ -170,14 +394,90 @@ std::ostream& operator<<(std::ostream& os, const Value& obj)
         }
         os << "]";
     }
+    else if (IS_MAP(inner))
+    {
+        os << "{ ";
+        for (auto & [key, value] : AS_MAP(inner).entries)
+        {
+            os << key << " " << value << " ";
+        }
+        os << "}";
+    }
     else if (IS_FUNC(inner))
     {
         os << "<lambda#1>";
     }
+    else if (IS_KEYWORD(inner))
+    {
+        os << ':' << std::get<Keyword>(inner).name;
+    }
 
     return os;
 }
 
+[[noreturn]] inline void arity_error(const std::string& message)
+{
+    raise_error("arity error", message);
+}
+
+// Binds the arguments of a call to the parameters `names`, by position or
+// by name after a `:name` keyword. Extra positional arguments go to `rest`
+// when the function has a `&rest` parameter.
+inline std::vector<std::optional<Value>> bind_arguments(const std::vector<Value>& args, const std::vector<std::string>& names, const std::string& function, std::vector<Value>* rest)
+{
+    std::vector<std::optional<Value>> values(names.size());
+    size_t position = 0;
+
+    for (size_t i = 0; i < args.size(); ++i)
+    {
+        size_t index;
+        if (auto keyword = args[i].as_keyword())
+        {
+            auto found = std::find(names.begin(), names.end(), keyword->name);
+            if (found == names.end())
+            {
+                raise_error("name error", "'" + function + "' has no parameter named '" + keyword->name + "'.");
+            }
+            if (++i == args.size())
+            {
+                arity_error("Keyword ':" + keyword->name + "' given to '" + function + "' has no value.");
+            }
+            index = found - names.begin();
+        }
+        else if (position < names.size())
+        {
+            index = position++;
+        }
+        else if (rest)
+        {
+            rest->push_back(args[i]);
+            continue;
+        }
+        else
+        {
+            arity_error("Too much arguments given to '" + function + "'.");
+        }
+
+        if (values[index])
+        {
+            arity_error("Parameter '" + names[index] + "' of '" + function + "' is given more than once.");
+        }
+        values[index] = args[i];
+    }
+
+    return values;
+}
+
+inline Value required_argument(const std::optional<Value>& value, const std::string& name)
+{
+    if (!value)
+    {
+        arity_error("Parameter '" + name + "' isn't set and has no default value.");
+    }
+
+    return *value;
+}
+
 inline bool operator< (const Value& lhs, const Value& rhs) {
     const auto & l = lhs.inner;
     const auto & r = rhs.inner;
@@ -254,6 +554,34 @@ inline bool operator==(const Value& lhs, const Value& rhs)
             return AS_STR(l) == AS_STR(r);
         }
     }
+    else if (IS_NULL(l))
+    {
+        return IS_NULL(r);
+    }
+    else if (IS_BOOL(l))
+    {
+        return IS_BOOL(r) && AS_BOOL(l) == AS_BOOL(r);
+    }
+    else if (IS_VEC(l))
+    {
+        if (IS_VEC(r))
+        {
+            return AS_VEC(l) == AS_VEC(r);
+        }
+    }
+    else if (IS_MAP(l))
+    {
+        if (IS_MAP(r))
+        {
+            auto& lm = AS_MAP(l);
+            auto& rm = AS_MAP(r);
+            return lm.entries.size() == rm.entries.size()
+                && std::all_of(lm.entries.begin(), lm.entries.end(), [&](auto& entry) {
+                    auto found = map_find(rm, entry.first);
+                    return found != rm.entries.end() && found->second == entry.second;
+                });
+        }
+    }
 
     return false;
 }
@@ -385,7 +713,7 @@ Value operator*(Value lhs, const Value& rhs)
         {
             std::string tmp;
             auto sss = AS_STR(l);
-            for (int i = 0; i < AS_INT(r); ++i)
+            for (int64_t i = 0; i < AS_INT(r); ++i)
             {
                 tmp += sss;
             }
@@ -439,6 +767,39 @@ inline Value func_print(std::vector<Value> args)
     return Value();
 }
 
+inline Value func_range(std::vector<Value> args)
+{
+    int64_t start = 0;
+    int64_t end = 0;
+    int64_t step = 1;
+    switch (args.size())
+    {
+    case 1:
+        end = args[0].as_int();
+        break;
+    case 3:
+        step = args[2].as_int();
+        [[fallthrough]];
+    case 2:
+        start = args[0].as_int();
+        end = args[1].as_int();
+        break;
+    default:
+        raise_error("arity error", "range expects 1 to 3 arguments. Got " + std::to_string(args.size()) + ".");
+    }
+    if (step == 0)
+    {
+        raise_error("value error", "range step can't be 0.");
+    }
+
+    std::vector<Value> list;
+    for (int64_t i = start; step > 0 ? i < end : i > end; i += step)
+    {
+        list.emplace_back(i);
+    }
+    return list;
+}
+
 inline Value func_write(std::vector<Value> args)
 {
     for (auto & arg : args)
@@ -447,3 +808,1086 @@ inline Value func_write(std::vector<Value> args)
     }
     return Value();
 }
+
+Value func_map_function(std::vector<Value> args);
+
+// `(map function list)` when the first argument is a function,
+// `(map key value ...)` otherwise, as in the interpreter's `functional_map`.
+inline Value func_map(std::vector<Value> args)
+{
+    if (!args.empty() && args[0].is_function())
+    {
+        return func_map_function(args);
+    }
+    if (args.size() % 2 != 0)
+    {
+        raise_error("arity error", "map expects pairs of key and value. Got " + std::to_string(args.size()) + " argument(s).");
+    }
+
+    auto map = std::make_shared<ValueMap>();
+    for (size_t i = 0; i < args.size(); i += 2)
+    {
+        map_insert(*map, args[i], args[i + 1]);
+    }
+    return map;
+}
+
+inline void expect_arity(const std::string& name, const std::vector<Value>& args, size_t count)
+{
+    if (args.size() != count)
+    {
+        raise_error("arity error", name + " expects " + std::to_string(count) + " argument(s). Got " + std::to_string(args.size()) + ".");
+    }
+}
+
+inline Value func_map_get(std::vector<Value> args)
+{
+    if (args.size() != 2 && args.size() != 3)
+    {
+        raise_error("arity error", "map-get expects 2 or 3 arguments. Got " + std::to_string(args.size()) + ".");
+    }
+
+    auto& map = args[0].as_map();
+    auto found = map_find(map, args[1]);
+    if (found != map.entries.end())
+    {
+        return found->second;
+    }
+    return args.size() == 3 ? args[2] : Value();
+}
+
+// Like in the interpreter, `map-set` and `map-remove` return a copy.
+inline Value func_map_set(std::vector<Value> args)
+{
+    expect_arity("map-set", args, 3);
+    auto map = std::make_shared<ValueMap>(args[0].as_map());
+    map_insert(*map, args[1], args[2]);
+    return map;
+}
+
+inline Value func_map_remove(std::vector<Value> args)
+{
+    expect_arity("map-remove", args, 2);
+    auto map = std::make_shared<ValueMap>(args[0].as_map());
+    auto found = map_find(*map, args[1]);
+    if (found != map->entries.end())
+    {
+        map->entries.erase(found);
+    }
+    return map;
+}
+
+inline Value func_has_key(std::vector<Value> args)
+{
+    expect_arity("has-key", args, 2);
+    auto& map = args[0].as_map();
+    return Value(map_find(map, args[1]) != map.entries.end());
+}
+
+inline Value func_keys(std::vector<Value> args)
+{
+    expect_arity("keys", args, 1);
+    std::vector<Value> keys;
+    for (auto& [key, value] : args[0].as_map().entries)
+    {
+        keys.push_back(key);
+    }
+    return keys;
+}
+
+inline Value func_values(std::vector<Value> args)
+{
+    expect_arity("values", args, 1);
+    std::vector<Value> values;
+    for (auto& [key, value] : args[0].as_map().entries)
+    {
+        values.push_back(value);
+    }
+    return values;
+}
+
+inline Value func_map_size(std::vector<Value> args)
+{
+    expect_arity("map-size", args, 1);
+    return Value(static_cast<int64_t>(args[0].as_map().entries.size()));
+}
+
+inline Value func_is_map(std::vector<Value> args)
+{
+    return Value(!args.empty() && std::all_of(args.begin(), args.end(), [](auto& arg) { return arg.is_map(); }));
+}
+
+inline Value func_size(std::vector<Value> args)
+{
+    expect_arity("size", args, 1);
+    if (args[0].is_list())
+    {
+        return Value(static_cast<int64_t>(args[0].as_list().size()));
+    }
+    return Value(args[0] == Value() ? 0 : 1);
+}
+
+// An index in `[0, size)`, or in `[0, size]` when `end` is allowed.
+inline size_t list_index(const std::string& name, const Value& index, size_t size, bool end)
+{
+    auto i = index.as_int();
+    if (i < 0 || static_cast<size_t>(i) > size || (!end && static_cast<size_t>(i) == size))
+    {
+        raise_error("index error", name + " out-of-bound access. list size: " + std::to_string(size) + ", index provided: " + std::to_string(i));
+    }
+    return static_cast<size_t>(i);
+}
+
+// Changes `list` in place and returns the element added, replaced or
+// removed. `args` are the arguments after the list.
+inline Value list_mutation(const std::string& name, std::vector<Value>& list, const std::vector<Value>& args)
+{
+    size_t expected = name == "pop" ? 0 : (name == "push" || name == "remove-at") ? 1 : 2;
+    if (args.size() != expected)
+    {
+        raise_error("arity error", name + " expects " + std::to_string(expected + 1) + " argument(s). Got " + std::to_string(args.size() + 1) + ".");
+    }
+
+    if (name == "push")
+    {
+        list.push_back(args[0]);
+        return args[0];
+    }
+    else if (name == "pop")
+    {
+        if (list.empty())
+        {
+            raise_error("index error", "pop on an empty list.");
+        }
+        Value last = list.back();
+        list.pop_back();
+        return last;
+    }
+    else if (name == "insert")
+    {
+        auto index = list_index(name, args[0], list.size(), true);
+        list.insert(list.begin() + index, args[1]);
+        return args[1];
+    }
+    else if (name == "remove-at")
+    {
+        auto index = list_index(name, args[0], list.size(), false);
+        Value removed = list[index];
+        list.erase(list.begin() + index);
+        return removed;
+    }
+
+    list[list_index(name, args[0], list.size(), false)] = args[1];
+    return args[1];
+}
+
+// A list mutation on a variable, which `list` is.
+inline Value update_list(Value& list, const std::string& name, std::vector<Value> args)
+{
+    return list_mutation(name, list.as_list(), args);
+}
+
+// A list mutation on a value rather than on a variable. Lists are values,
+// so the change would be lost.
+inline Value list_mutation_value(const std::string& name, std::vector<Value> args)
+{
+    if (args.empty())
+    {
+        raise_error("arity error", name + " expects a list. Got no argument.");
+    }
+    args[0].as_list();
+    raise_error("value error", name + " changes a list held in a variable, not a value.");
+}
+
+inline Value func_nth(std::vector<Value> args)
+{
+    if (args.size() == 3)
+    {
+        return list_mutation_value("nth", args);
+    }
+    expect_arity("nth", args, 2);
+    auto& list = args[0].as_list();
+    return list[list_index("nth", args[1], list.size(), false)];
+}
+
+inline Value func_push(std::vector<Value> args) { return list_mutation_value("push", args); }
+inline Value func_pop(std::vector<Value> args) { return list_mutation_value("pop", args); }
+inline Value func_set_nth(std::vector<Value> args) { return list_mutation_value("set-nth", args); }
+inline Value func_insert(std::vector<Value> args) { return list_mutation_value("insert", args); }
+inline Value func_remove_at(std::vector<Value> args) { return list_mutation_value("remove-at", args); }
+
+inline Value func_slice(std::vector<Value> args)
+{
+    if (args.size() != 2 && args.size() != 3)
+    {
+        raise_error("arity error", "slice expects 2 or 3 arguments. Got " + std::to_string(args.size()) + ".");
+    }
+
+    auto& list = args[0].as_list();
+    auto start = list_index("slice", args[1], list.size(), true);
+    auto end = args.size() == 3 ? list_index("slice", args[2], list.size(), true) : list.size();
+    if (start > end)
+    {
+        raise_error("index error", "slice starts after its end. start: " + std::to_string(start) + ", end: " + std::to_string(end));
+    }
+    return std::vector<Value>(list.begin() + start, list.begin() + end);
+}
+
+inline Value func_concat(std::vector<Value> args)
+{
+    std::vector<Value> result;
+    for (auto& arg : args)
+    {
+        auto& list = arg.as_list();
+        result.insert(result.end(), list.begin(), list.end());
+    }
+    return result;
+}
+
+inline Value func_reverse(std::vector<Value> args)
+{
+    expect_arity("reverse", args, 1);
+    auto& list = args[0].as_list();
+    return std::vector<Value>(list.rbegin(), list.rend());
+}
+
+inline Value func_index_of(std::vector<Value> args)
+{
+    expect_arity("index-of", args, 2);
+    auto& list = args[0].as_list();
+    auto found = std::find(list.begin(), list.end(), args[1]);
+    return Value(found == list.end() ? int64_t { -1 } : static_cast<int64_t>(found - list.begin()));
+}
+
+inline Value func_contains(std::vector<Value> args)
+{
+    expect_arity("contains", args, 2);
+    auto& list = args[0].as_list();
+    return Value(std::find(list.begin(), list.end(), args[1]) != list.end());
+}
+
+inline Value func_add(std::vector<Value> args)
+{
+    expect_arity("add", args, 2);
+    return args[0] + args[1];
+}
+
+inline Value func_sub(std::vector<Value> args)
+{
+    expect_arity("sub", args, 2);
+    return args[0] - args[1];
+}
+
+inline Value func_lt(std::vector<Value> args)
+{
+    expect_arity("lt", args, 2);
+    return Value(args[0] < args[1]);
+}
+
+inline Value func_eq(std::vector<Value> args)
+{
+    expect_arity("eq", args, 2);
+    return Value(args[0] == args[1]);
+}
+
+// Checks the arguments of `(name function list)` and gives the list.
+inline std::vector<Value>& function_and_list(const std::string& name, std::vector<Value>& args)
+{
+    expect_arity(name, args, 2);
+    if (!args[0].is_function())
+    {
+        raise_error("type error", name + " expects a function. Got " + args[0].get_type() + ".");
+    }
+    return args[1].as_list();
+}
+
+inline Value func_map_function(std::vector<Value> args)
+{
+    std::vector<Value> result;
+    for (auto& item : function_and_list("map", args))
+    {
+        result.push_back(args[0]({ item }));
+    }
+    return result;
+}
+
+inline Value func_filter(std::vector<Value> args)
+{
+    std::vector<Value> result;
+    for (auto& item : function_and_list("filter", args))
+    {
+        if (args[0]({ item }))
+        {
+            result.push_back(item);
+        }
+    }
+    return result;
+}
+
+// `(reduce function list)` or `(reduce function initial list)`, the first
+// element being the initial value when none is given.
+inline Value func_reduce(std::vector<Value> args)
+{
+    if (args.size() != 2 && args.size() != 3)
+    {
+        raise_error("arity error", "reduce expects 2 or 3 arguments. Got " + std::to_string(args.size()) + ".");
+    }
+
+    std::vector<Value> function_list { args[0], args.back() };
+    auto& list = function_and_list("reduce", function_list);
+    auto item = list.begin();
+    Value accumulator;
+    if (args.size() == 3)
+    {
+        accumulator = args[1];
+    }
+    else if (item == list.end())
+    {
+        raise_error("value error", "reduce of an empty list with no initial value.");
+    }
+    else
+    {
+        accumulator = *item++;
+    }
+
+    for (; item != list.end(); ++item)
+    {
+        accumulator = function_list[0]({ accumulator, *item });
+    }
+    return accumulator;
+}
+
+// Orders numbers and strings, as `sort` does.
+inline bool sort_less(const std::string& name, const Value& lhs, const Value& rhs)
+{
+    auto is_number = [](const std::string& type) { return type == "int" || type == "float"; };
+    auto left = lhs.get_type();
+    auto right = rhs.get_type();
+    if (!(is_number(left) && is_number(right)) && !(left == "string" && right == "string"))
+    {
+        raise_error("type error", name + " can't compare a " + left + " with a " + right + ".");
+    }
+    return lhs < rhs;
+}
+
+// Sorts `items` by their `keys`, keeping the order of equal ones.
+inline Value sort_by_keys(const std::string& name, const std::vector<Value>& items, const std::vector<Value>& keys)
+{
+    std::vector<size_t> order(items.size());
+    for (size_t i = 0; i < order.size(); ++i)
+    {
+        order[i] = i;
+    }
+    std::stable_sort(order.begin(), order.end(), [&](size_t a, size_t b) { return sort_less(name, keys[a], keys[b]); });
+
+    std::vector<Value> result;
+    for (auto i : order)
+    {
+        result.push_back(items[i]);
+    }
+    return result;
+}
+
+inline Value func_sort(std::vector<Value> args)
+{
+    expect_arity("sort", args, 1);
+    auto& list = args[0].as_list();
+    return sort_by_keys("sort", list, list);
+}
+
+inline Value func_sort_by(std::vector<Value> args)
+{
+    auto& list = function_and_list("sort-by", args);
+    std::vector<Value> keys;
+    for (auto& item : list)
+    {
+        keys.push_back(args[0]({ item }));
+    }
+    return sort_by_keys("sort-by", list, keys);
+}
+
+Value string_find(std::vector<Value> args);
+
+// Given a string, the index of a string in it, see `string_find`.
+inline Value func_find(std::vector<Value> args)
+{
+    if (!args.empty() && args[0].get_type() == "string")
+    {
+        return string_find(args);
+    }
+    for (auto& item : function_and_list("find", args))
+    {
+        if (args[0]({ item }))
+        {
+            return item;
+        }
+    }
+    return Value();
+}
+
+inline Value func_any(std::vector<Value> args)
+{
+    for (auto& item : function_and_list("any", args))
+    {
+        if (args[0]({ item }))
+        {
+            return Value(true);
+        }
+    }
+    return Value(false);
+}
+
+inline Value func_all(std::vector<Value> args)
+{
+    for (auto& item : function_and_list("all", args))
+    {
+        if (!args[0]({ item }))
+        {
+            return Value(false);
+        }
+    }
+    return Value(true);
+}
+
+// Lists of the n-th elements of each list, as long as the shortest one.
+inline Value func_zip(std::vector<Value> args)
+{
+    size_t size = args.empty() ? 0 : SIZE_MAX;
+    for (auto& arg : args)
+    {
+        size = std::min(size, arg.as_list().size());
+    }
+
+    std::vector<Value> result;
+    for (size_t i = 0; i < size; ++i)
+    {
+        std::vector<Value> tuple;
+        for (auto& arg : args)
+        {
+            tuple.push_back(arg.as_list()[i]);
+        }
+        result.push_back(tuple);
+    }
+    return result;
+}
+
+// Strings are UTF-8 and indexed by code point, like in the interpreter.
+inline std::u32string utf8_decode(const std::string& string)
+{
+    std::u32string result;
+    for (size_t i = 0; i < string.size();)
+    {
+        unsigned char c = string[i];
+        size_t length = c < 0x80 ? 1 : c < 0xE0 ? 2 : c < 0xF0 ? 3 : 4;
+        char32_t code = length == 1 ? c : c & (0x7F >> length);
+        for (size_t j = 1; j < length && i + j < string.size(); ++j)
+        {
+            code = (code << 6) | (string[i + j] & 0x3F);
+        }
+        result.push_back(code);
+        i += length;
+    }
+    return result;
+}
+
+inline std::string utf8_encode(const std::u32string& string)
+{
+    std::string result;
+    for (char32_t code : string)
+    {
+        if (code < 0x80)
+        {
+            result.push_back(static_cast<char>(code));
+        }
+        else if (code < 0x800)
+        {
+            result.push_back(static_cast<char>(0xC0 | (code >> 6)));
+            result.push_back(static_cast<char>(0x80 | (code & 0x3F)));
+        }
+        else if (code < 0x10000)
+        {
+            result.push_back(static_cast<char>(0xE0 | (code >> 12)));
+            result.push_back(static_cast<char>(0x80 | ((code >> 6) & 0x3F)));
+            result.push_back(static_cast<char>(0x80 | (code & 0x3F)));
+        }
+        else
+        {
+            result.push_back(static_cast<char>(0xF0 | (code >> 18)));
+            result.push_back(static_cast<char>(0x80 | ((code >> 12) & 0x3F)));
+            result.push_back(static_cast<char>(0x80 | ((code >> 6) & 0x3F)));
+            result.push_back(static_cast<char>(0x80 | (code & 0x3F)));
+        }
+    }
+    return result;
+}
+
+inline size_t char_index(const std::string& name, const Value& index, size_t length, bool end)
+{
+    auto i = index.as_int();
+    if (i < 0 || static_cast<size_t>(i) > length || (!end && static_cast<size_t>(i) == length))
+    {
+        raise_error("index error", name + " out-of-bound access. string length: " + std::to_string(length) + ", index provided: " + std::to_string(i));
+    }
+    return static_cast<size_t>(i);
+}
+
+inline Value func_str_len(std::vector<Value> args)
+{
+    expect_arity("str-len", args, 1);
+    return Value(static_cast<int64_t>(utf8_decode(args[0].as_string()).size()));
+}
+
+inline Value func_substr(std::vector<Value> args)
+{
+    if (args.size() != 2 && args.size() != 3)
+    {
+        raise_error("arity error", "substr expects 2 or 3 arguments. Got " + std::to_string(args.size()) + ".");
+    }
+
+    auto string = utf8_decode(args[0].as_string());
+    auto start = char_index("substr", args[1], string.size(), true);
+    auto end = args.size() == 3 ? char_index("substr", args[2], string.size(), true) : string.size();
+    if (start > end)
+    {
+        raise_error("index error", "substr starts after its end. start: " + std::to_string(start) + ", end: " + std::to_string(end));
+    }
+    return utf8_encode(string.substr(start, end - start));
+}
+
+inline Value func_split(std::vector<Value> args)
+{
+    expect_arity("split", args, 2);
+    auto string = args[0].as_string();
+    auto separator = args[1].as_string();
+
+    std::vector<Value> parts;
+    if (separator.empty())
+    {
+        for (char32_t c : utf8_decode(string))
+        {
+            parts.push_back(utf8_encode(std::u32string(1, c)));
+        }
+        return parts;
+    }
+
+    size_t start = 0;
+    size_t found;
+    while ((found = string.find(separator, start)) != std::string::npos)
+    {
+        parts.push_back(string.substr(start, found - start));
+        start = found + separator.size();
+    }
+    parts.push_back(string.substr(start));
+    return parts;
+}
+
+inline Value func_join(std::vector<Value> args)
+{
+    if (args.size() != 1 && args.size() != 2)
+    {
+        raise_error("arity error", "join expects 1 or 2 arguments. Got " + std::to_string(args.size()) + ".");
+    }
+
+    auto separator = args.size() == 2 ? args[1].as_string() : ""s;
+    std::ostringstream result;
+    auto& list = args[0].as_list();
+    for (size_t i = 0; i < list.size(); ++i)
+    {
+        if (i > 0)
+        {
+            result << separator;
+        }
+        result << list[i];
+    }
+    return result.str();
+}
+
+inline Value string_find(std::vector<Value> args)
+{
+    expect_arity("find", args, 2);
+    auto string = args[0].as_string();
+    auto found = string.find(args[1].as_string());
+    if (found == std::string::npos)
+    {
+        return Value(int64_t { -1 });
+    }
+    return Value(static_cast<int64_t>(utf8_decode(string.substr(0, found)).size()));
+}
+
+inline Value func_replace(std::vector<Value> args)
+{
+    expect_arity("replace", args, 3);
+    auto string = args[0].as_string();
+    auto from = args[1].as_string();
+    auto to = args[2].as_string();
+    if (from.empty())
+    {
+        raise_error("value error", "replace can't replace an empty string.");
+    }
+
+    std::string result;
+    size_t start = 0;
+    size_t found;
+    while ((found = string.find(from, start)) != std::string::npos)
+    {
+        result += string.substr(start, found - start) + to;
+        start = found + from.size();
+    }
+    return result + string.substr(start);
+}
+
+// The range of a case table holding `code`, if any. Tables are sorted by
+// their first code point.
+template <typename Range>
+inline const Range* find_range(std::span<const Range> ranges, char32_t code)
+{
+    auto found = std::upper_bound(ranges.begin(), ranges.end(), code, [](char32_t c, const Range& range) { return c < range.first; });
+    if (found == ranges.begin() || code > (found - 1)->last)
+    {
+        return nullptr;
+    }
+    return &*(found - 1);
+}
+
+// Whether the first code point which isn't case ignorable is cased.
+template <typename Iterator>
+inline bool ignorable_then_cased(Iterator first, Iterator last)
+{
+    auto found = std::find_if(first, last, [](char32_t c) { return !find_range<CodeRange>(case_ignorable, c); });
+    return found != last && find_range<CodeRange>(cased, *found);
+}
+
+// Changes the case with the tables of Rust's std, so that a character can
+// become several ones (ß to SS) and a Σ ending a word is lowered to ς.
+inline std::string change_case(const std::string& string, bool upper)
+{
+    std::span<const CaseRange> ranges = upper ? std::span<const CaseRange>(upper_case_ranges) : lower_case_ranges;
+    std::span<const SpecialCase> special = upper ? std::span<const SpecialCase>(upper_case_special) : lower_case_special;
+
+    auto codes = utf8_decode(string);
+    std::u32string result;
+    for (size_t i = 0; i < codes.size(); ++i)
+    {
+        auto code = codes[i];
+        if (!upper && code == U'Σ')
+        {
+            bool final = ignorable_then_cased(codes.rbegin() + (codes.size() - i), codes.rend())
+                && !ignorable_then_cased(codes.begin() + i + 1, codes.end());
+            result.push_back(final ? U'ς' : U'σ');
+            continue;
+        }
+
+        auto found = std::lower_bound(special.begin(), special.end(), code, [](const SpecialCase& s, char32_t c) { return s.code < c; });
+        if (found != special.end() && found->code == code)
+        {
+            result += found->mapping;
+            continue;
+        }
+
+        auto range = find_range(ranges, code);
+        if (range && (code - range->first) % range->stride == 0)
+        {
+            code = static_cast<char32_t>(static_cast<int32_t>(code) + range->delta);
+        }
+        result.push_back(code);
+    }
+    return utf8_encode(result);
+}
+
+inline Value func_upper(std::vector<Value> args)
+{
+    expect_arity("upper", args, 1);
+    return change_case(args[0].as_string(), true);
+}
+
+inline Value func_lower(std::vector<Value> args)
+{
+    expect_arity("lower", args, 1);
+    return change_case(args[0].as_string(), false);
+}
+
+// The code points with the Unicode White_Space property.
+inline bool is_white_space(char32_t c)
+{
+    return (c >= 0x09 && c <= 0x0D) || c == 0x20 || c == 0x85 || c == 0xA0 || c == 0x1680
+        || (c >= 0x2000 && c <= 0x200A) || c == 0x2028 || c == 0x2029 || c == 0x202F || c == 0x205F || c == 0x3000;
+}
+
+inline Value func_trim(std::vector<Value> args)
+{
+    expect_arity("trim", args, 1);
+    auto string = utf8_decode(args[0].as_string());
+    size_t start = 0;
+    size_t end = string.size();
+    while (start < end && is_white_space(string[start]))
+    {
+        ++start;
+    }
+    while (end > start && is_white_space(string[end - 1]))
+    {
+        --end;
+    }
+    return utf8_encode(string.substr(start, end - start));
+}
+
+inline Value func_starts_with(std::vector<Value> args)
+{
+    expect_arity("starts-with", args, 2);
+    auto string = args[0].as_string();
+    auto prefix = args[1].as_string();
+    return Value(string.compare(0, prefix.size(), prefix) == 0);
+}
+
+inline Value func_ends_with(std::vector<Value> args)
+{
+    expect_arity("ends-with", args, 2);
+    auto string = args[0].as_string();
+    auto suffix = args[1].as_string();
+    return Value(string.size() >= suffix.size() && string.compare(string.size() - suffix.size(), suffix.size(), suffix) == 0);
+}
+
+inline Value func_char_at(std::vector<Value> args)
+{
+    expect_arity("char-at", args, 2);
+    auto string = utf8_decode(args[0].as_string());
+    return utf8_encode(std::u32string(1, string[char_index("char-at", args[1], string.size(), false)]));
+}
+
+// Strings which aren't integers give null, like in the interpreter.
+inline Value func_to_int(std::vector<Value> args)
+{
+    expect_arity("to-int", args, 1);
+    auto type = args[0].get_type();
+    if (type == "int")
+    {
+        return args[0];
+    }
+    else if (type == "float")
+    {
+        // Saturates, as Rust's `as` does.
+        auto f = args[0].as_float();
+        if (std::isnan(f))
+        {
+            return Value(int64_t { 0 });
+        }
+        return Value(f >= 9223372036854775807.0 ? INT64_MAX : f <= -9223372036854775808.0 ? INT64_MIN : static_cast<int64_t>(f));
+    }
+    else if (type != "string")
+    {
+        raise_error("type error", "to-int can't convert a " + type + ".");
+    }
+
+    auto string = args[0].as_string();
+    size_t i = string.size() > 0 && (string[0] == '+' || string[0] == '-') ? 1 : 0;
+    if (i == string.size() || !std::all_of(string.begin() + i, string.end(), [](char c) { return c >= '0' && c <= '9'; }))
+    {
+        return Value();
+    }
+
+    errno = 0;
+    auto value = std::strtoll(string.c_str(), nullptr, 10);
+    if (errno == ERANGE)
+    {
+        return Value();
+    }
+    return Value(static_cast<int64_t>(value));
+}
+
+inline Value func_to_float(std::vector<Value> args)
+{
+    expect_arity("to-float", args, 1);
+    auto type = args[0].get_type();
+    if (type == "int")
+    {
+        return Value(static_cast<double>(args[0].as_int()));
+    }
+    else if (type == "float")
+    {
+        return args[0];
+    }
+    else if (type != "string")
+    {
+        raise_error("type error", "to-float can't convert a " + type + ".");
+    }
+
+    // strtod also reads leading spaces and hexadecimal numbers, which Rust's
+    // parsing rejects.
+    auto string = args[0].as_string();
+    if (string.empty() || is_white_space(string[0]) || string.find_first_of("xX") != std::string::npos)
+    {
+        return Value();
+    }
+
+    char* end = nullptr;
+    auto value = std::strtod(string.c_str(), &end);
+    if (*end != '\0')
+    {
+        return Value();
+    }
+    return Value(value);
+}
+
+// Formats a float as Rust does: the shortest text reading back as the same
+// number, or rounded to `precision` decimals, never with an exponent.
+inline std::string format_float(double f, std::optional<size_t> precision)
+{
+    if (std::isnan(f))
+    {
+        return "NaN";
+    }
+    else if (std::isinf(f))
+    {
+        return f < 0 ? "-inf" : "inf";
+    }
+
+    if (precision)
+    {
+        // The largest doubles have 309 integer digits.
+        std::string buffer(320 + *precision, '\0');
+        auto result = std::to_chars(buffer.data(), buffer.data() + buffer.size(), f, std::chars_format::fixed, static_cast<int>(*precision));
+        buffer.resize(result.ptr - buffer.data());
+        return buffer;
+    }
+
+    // Without a precision, fixed notation would write out every digit of the
+    // binary value, so the shortest digits are placed around the point.
+    char buffer[32];
+    auto result = std::to_chars(buffer, buffer + sizeof(buffer), f, std::chars_format::scientific);
+    std::string scientific(buffer, result.ptr);
+    auto e = scientific.find('e');
+    auto exponent = std::stoi(scientific.substr(e + 1));
+    std::string sign = scientific[0] == '-' ? "-" : "";
+    std::string digits;
+    for (char c : scientific.substr(sign.size(), e - sign.size()))
+    {
+        if (c != '.')
+        {
+            digits += c;
+        }
+    }
+
+    auto point = exponent + 1;
+    if (point <= 0)
+    {
+        return sign + "0." + std::string(-point, '0') + digits;
+    }
+    else if (static_cast<size_t>(point) >= digits.size())
+    {
+        return sign + digits + std::string(point - digits.size(), '0');
+    }
+    return sign + digits.substr(0, point) + "." + digits.substr(point);
+}
+
+// Writes a value as the interpreter does, which `operator<<` doesn't for
+// bools, null and floats.
+inline std::string display(const Value& value)
+{
+    auto type = value.get_type();
+    if (type == "NULL")
+    {
+        return "null";
+    }
+    else if (type == "bool")
+    {
+        return value ? "true" : "false";
+    }
+    else if (type == "float")
+    {
+        return format_float(value.as_float(), std::nullopt);
+    }
+    else if (value.is_list())
+    {
+        std::string result = "[ ";
+        for (auto& item : value.as_list())
+        {
+            result += display(item) + " ";
+        }
+        return result + "]";
+    }
+    else if (value.is_map())
+    {
+        std::string result = "{ ";
+        for (auto& [key, item] : value.as_map().entries)
+        {
+            result += display(key) + " " + display(item) + " ";
+        }
+        return result + "}";
+    }
+
+    std::ostringstream stream;
+    stream << value;
+    return stream.str();
+}
+
+// A `{index:<fill><align>0<width>.<precision>}` placeholder of `format`,
+// every part being optional. `0` pads numbers with zeros after their sign.
+struct Placeholder
+{
+    std::optional<size_t> index;
+    std::string fill = " ";
+    char align = '\0';
+    bool zero = false;
+    size_t width = 0;
+    std::optional<size_t> precision;
+};
+
+inline Placeholder parse_placeholder(const std::string& spec)
+{
+    auto number = [&spec](const std::string& digits) {
+        if (digits.empty() || !std::all_of(digits.begin(), digits.end(), [](char c) { return c >= '0' && c <= '9'; }))
+        {
+            raise_error("value error", "format has an invalid placeholder: {" + spec + "}");
+        }
+        return static_cast<size_t>(std::stoull(digits));
+    };
+    auto is_align = [](char32_t c) { return c == '<' || c == '^' || c == '>'; };
+
+    Placeholder placeholder;
+    auto colon = spec.find(':');
+    auto index = spec.substr(0, colon);
+    if (!index.empty())
+    {
+        placeholder.index = number(index);
+    }
+
+    auto options = utf8_decode(colon == std::string::npos ? "" : spec.substr(colon + 1));
+    if (options.size() >= 2 && is_align(options[1]))
+    {
+        placeholder.fill = utf8_encode(options.substr(0, 1));
+        placeholder.align = static_cast<char>(options[1]);
+        options = options.substr(2);
+    }
+    else if (!options.empty() && is_align(options[0]))
+    {
+        placeholder.align = static_cast<char>(options[0]);
+        options = options.substr(1);
+    }
+
+    auto rest = utf8_encode(options);
+    if (!rest.empty() && rest[0] == '0')
+    {
+        placeholder.zero = true;
+        rest = rest.substr(1);
+    }
+    auto dot = rest.find('.');
+    auto width = rest.substr(0, dot);
+    if (!width.empty())
+    {
+        placeholder.width = number(width);
+    }
+    if (dot != std::string::npos)
+    {
+        placeholder.precision = number(rest.substr(dot + 1));
+    }
+    return placeholder;
+}
+
+// A precision rounds numbers, integers included, and cuts anything else to
+// that many characters.
+inline std::string format_value(const Value& value, const Placeholder& placeholder)
+{
+    auto type = value.get_type();
+    bool numeric = type == "int" || type == "float";
+    std::string text;
+    if (type == "float")
+    {
+        text = format_float(value.as_float(), placeholder.precision);
+    }
+    else if (type == "int" && placeholder.precision)
+    {
+        text = format_float(static_cast<double>(value.as_int()), placeholder.precision);
+    }
+    else
+    {
+        text = display(value);
+        if (placeholder.precision && !numeric)
+        {
+            text = utf8_encode(utf8_decode(text).substr(0, *placeholder.precision));
+        }
+    }
+
+    auto length = utf8_decode(text).size();
+    if (length >= placeholder.width)
+    {
+        return text;
+    }
+
+    // Numbers are aligned to the right by default, as in Rust.
+    auto padding = placeholder.width - length;
+    if (placeholder.zero && numeric)
+    {
+        size_t sign = text[0] == '-' ? 1 : 0;
+        return text.substr(0, sign) + std::string(padding, '0') + text.substr(sign);
+    }
+    size_t left = 0;
+    if (placeholder.align == '>' || (placeholder.align == '\0' && numeric))
+    {
+        left = padding;
+    }
+    else if (placeholder.align == '^')
+    {
+        left = padding / 2;
+    }
+
+    std::string result;
+    for (size_t i = 0; i < left; ++i)
+    {
+        result += placeholder.fill;
+    }
+    result += text;
+    for (size_t i = left; i < padding; ++i)
+    {
+        result += placeholder.fill;
+    }
+    return result;
+}
+
+// `{}` takes the next argument and `{0}` a given one, `{{` and `}}` are
+// literal braces.
+inline Value func_format(std::vector<Value> args)
+{
+    if (args.empty())
+    {
+        raise_error("arity error", "format expects at least 1 argument. Got 0.");
+    }
+
+    auto format = args[0].as_string();
+    size_t count = args.size() - 1;
+    size_t next = 0;
+    std::string output;
+    for (size_t i = 0; i < format.size(); ++i)
+    {
+        char c = format[i];
+        if ((c == '{' || c == '}') && i + 1 < format.size() && format[i + 1] == c)
+        {
+            output += c;
+            ++i;
+        }
+        else if (c == '{')
+        {
+            auto close = format.find('}', i);
+            if (close == std::string::npos)
+            {
+                raise_error("value error", "format has an unclosed placeholder.");
+            }
+
+            auto placeholder = parse_placeholder(format.substr(i + 1, close - i - 1));
+            auto index = placeholder.index ? *placeholder.index : next++;
+            if (index >= count)
+            {
+                raise_error("index error", "format has no argument " + std::to_string(index) + ". Got " + std::to_string(count) + " argument(s).");
+            }
+            output += format_value(args[index + 1], placeholder);
+            i = close;
+        }
+        else if (c == '}')
+        {
+            raise_error("value error", "format has an unmatched '}'.");
+        }
+        else
+        {
+            output += c;
+        }
+    }
+    return output;
+}
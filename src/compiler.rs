@@ -3,8 +3,9 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io::Write;
-use pest::Parser;
-use crate::parser::parse_block;
+use crate::diagnostics::parse_source;
+use crate::error::{ErrorKind, SlError};
+use crate::generator::escape_string;
 use crate::*;
 use std::fs;
 use std::process::Command;
@@ -189,7 +190,7 @@ impl Compiler {
 }}");
 
         for (id, s) in self.strings.iter().enumerate() {
-            writeln!(self.file, "data $str{id} = {{ b \"{s}\", b 0 }}");
+            writeln!(self.file, "data $str{id} = {{ b \"{}\", b 0 }}", escape_string(s));
         }
     }
 
@@ -395,7 +396,7 @@ impl Compiler {
             }
 
             if name == "print" {
-                fmt.push_str("\\n");
+                fmt.push('\n');
             }
 
             let fmt_id = self.try_insert_string(&fmt);
@@ -458,11 +459,9 @@ impl Compiler {
 
     fn generate_file(&mut self, filename: &str) -> Vec<Ast> {
         let data = fs::read_to_string(filename).unwrap();
-        let res = SimpleLispParser::parse(Rule::file, &data);
-
-        let nodes = match res {
-            Ok(pairs) => parse_block(pairs).unwrap(),
-            Err(e) => panic!("Can't parse {}:\n{:?}", filename, e),
+        let nodes = match parse_source(filename, &data) {
+            Ok(nodes) => nodes,
+            Err(diagnostics) => SlError::from_diagnostics(ErrorKind::Parse, diagnostics).exit(),
         };
 
         let mut asts = vec![];
@@ -476,54 +475,62 @@ impl Compiler {
 
     fn node_to_ast(&self, node: &Node) -> Ast {
         match node {
-            Node::Call { name, args } => {
+            Node::Call { name, args, span } => {
                 match name.as_str() {
                     "let" => {
-                        assert_eq!(args.len(), 2);
-                        let Node::Identifier(name) = &args[0] else {
-                            panic!("{args:?}");
+                        if args.len() != 2 {
+                            SlError::arity_error(format!("'let' expects 2 arguments. Got {}.", args.len())).at(*span).exit();
+                        }
+                        let Node::Identifier(name, _) = &args[0] else {
+                            SlError::type_error(format!("'let' expects an identifier. Got {}.", args[0].type_name())).at(*span).exit();
                         };
-                        let name = name.clone();
+                        let name = mangle_name(name);
 
                         Ast::Let {
                             name,
                             init: Box::new(self.node_to_ast(&args[1])),
                         }
                     }
-                    "switch" => {
-                        let mut iter = args.iter();
-                        let condition = Box::new(self.node_to_ast(iter.next().unwrap()));
+                    "match" => {
+                        let condition = Box::new(self.node_to_ast(&args[0]));
                         let mut cases = vec![];
                         let mut default = None;
-                        while let Some(n) = iter.next() {
-                            if let Node::Call { name, args } = n {
-                                let first = Box::new(self.node_to_ast(&args[0]));
-                                let second = Box::new(self.node_to_ast(&args[1]));
-                                cases.push((first, second));
-                            } else {
-                                default = Some(n);
-                                break;
+                        for clause in &args[1..] {
+                            let clause = match clause {
+                                Node::List(clause) => match_clause(clause),
+                                _ => None,
+                            };
+                            // Only literal patterns map onto a QBE switch.
+                            match clause {
+                                Some((Node::Identifier(id, _), None, [body])) if id == "_" && default.is_none() => {
+                                    default = Some(body);
+                                }
+                                Some((pattern @ (Node::Integer(_) | Node::String(_)), None, [body])) if default.is_none() => {
+                                    cases.push((Box::new(self.node_to_ast(pattern)), Box::new(self.node_to_ast(body))));
+                                }
+                                _ => SlError::value_error("QBE only supports literal patterns with a single expression, then a final `_` clause.").at(*span).exit(),
                             }
                         }
-                        assert!(iter.next().is_none());
-                        let default = Box::new(self.node_to_ast(default.unwrap()));
+                        let Some(default) = default else {
+                            SlError::value_error("QBE needs a final `_` clause in 'match'.").at(*span).exit();
+                        };
                         Ast::Switch {
                             condition,
                             cases,
-                            default,
+                            default: Box::new(self.node_to_ast(default)),
                         }
                     }
                     "class" => {
                         let mut fields = vec![];
                         let mut functions = vec![];
                         let mut args_iter = args.iter();
-                        let Node::Identifier(class_name) = args_iter.next().unwrap() else {
-                            todo!();
+                        let Some(Node::Identifier(class_name, _)) = args_iter.next() else {
+                            SlError::type_error("'class' expects an identifier.").at(*span).exit();
                         };
 
                         for arg in args_iter {
                             match arg {
-                                Node::Function { name, params, body } => {
+                                Node::Function { name, params, body, .. } => {
                                     // println!("params! {params:?}", );
                                     let body = body.iter().map(|e| self.node_to_ast(e)).collect::<Vec<_>>();
                                     // println!("{name}: {body:?}", );
@@ -531,25 +538,27 @@ impl Compiler {
                                     let mut ast_params = vec![];
                                     for p in params {
                                         ast_params.push(Param {
-                                            name: format!("%{}", p.name),
+                                            name: format!("%{}", mangle_name(&p.name)),
                                             default: p.default_value.clone().map(|node: node::Node| self.node_to_ast(&node)),
                                         });
                                     }
 
                                     functions.push(Function {
-                                        name: name.to_string(),
+                                        name: mangle_name(name),
                                         params: ast_params,
                                         body,
                                     });
                                 }
-                                Node::Call { name, args } => {
-                                    assert_eq!(name, "let");
+                                Node::Call { name, args, span } => {
+                                    if name != "let" {
+                                        SlError::value_error(format!("Can't call '{name}' inside the body of a class, only 'let' and 'fun' are available.")).at(*span).exit();
+                                    }
 
                                     let var_name = self.node_to_ast(&args[0]);
                                     let var_init = self.node_to_ast(&args[1]);
 
                                     let Ast::Identifier(var_name) = var_name else {
-                                        todo!();
+                                        SlError::type_error("'let' expects an identifier.").at(*span).exit();
                                     };
 
                                     fields.push(Variable {
@@ -562,21 +571,24 @@ impl Compiler {
                         }
 
                         Ast::Class {
-                            name: class_name.clone(),
+                            name: mangle_name(class_name),
                             fields,
                             functions,
                         }
                     }
+                    "quote" | "quasiquote" | "unquote" | "unquote-splicing" => {
+                        SlError::value_error(format!("'{name}' is only supported by the interpreter.")).at(*span).exit();
+                    }
                     "add" => {
                         let lhs = Box::new(self.node_to_ast(&args[0]));
                         let rhs = Box::new(self.node_to_ast(&args[1]));
                         Ast::Add { lhs, rhs }
                     }
                     "new" => {
-                        let Node::Identifier(class) = &args[0] else {
-                            todo!();
+                        let Node::Identifier(class, _) = &args[0] else {
+                            SlError::type_error(format!("'new' only accept identifiers. Got {}.", args[0].type_name())).at(*span).exit();
                         };
-                        let class = class.clone();
+                        let class = mangle_name(class);
                         Ast::New { class }
                     }
                     _ => {
@@ -586,7 +598,7 @@ impl Compiler {
                             vargs.push(Box::new(self.node_to_ast(arg)));
                         }
 
-                        let name = name.clone();
+                        let name = mangle_name(name);
 
                         Ast::Call {
                             name,
@@ -595,11 +607,28 @@ impl Compiler {
                     }
                 }
             }
-            Node::Integer(i) => Ast::Integer(*i),
-            Node::Float(f) => Ast::Float(*f),
+            Node::Integer(i) => match i32::try_from(*i) {
+                Ok(i) => Ast::Integer(i),
+                Err(_) => SlError::value_error(format!("integer {i} does not fit in a QBE word (32 bits)")).exit(),
+            },
+            Node::Float(f) => Ast::Float(*f as f32),
             Node::String(id) => Ast::String(id.clone()),
-            Node::Identifier(id) => Ast::Identifier(id.clone()),
+            Node::Identifier(id, _) => Ast::Identifier(mangle_name(id)),
             _ => panic!("{node:?}", ),
         }
     }
 }
+
+/// QBE names only take `[A-Za-z0-9_.]`, every other character is written
+/// as `.` followed by its hexadecimal code point and `.`.
+fn mangle_name(name: &str) -> String {
+    let mut mangled = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            mangled.push(c);
+        } else {
+            mangled.push_str(&format!(".{:x}.", c as u32));
+        }
+    }
+    mangled
+}
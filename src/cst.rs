@@ -0,0 +1,187 @@
+use std::fmt;
+use crate::diagnostics::Diagnostic;
+use crate::span::Span;
+
+/// Lossless syntax tree of a source file: unlike `Node` it keeps every
+/// comment and every whitespace, so printing it gives the file back byte
+/// for byte. It is what the formatter works on.
+#[derive(Debug, Clone)]
+pub enum Cst {
+    /// `(...)`, `[...]` or `{...}`, `children` including the trivia.
+    List {
+        open: char,
+        children: Vec<Cst>,
+        close: char,
+    },
+    /// Identifiers, numbers, strings and character literals.
+    Atom(String),
+    /// `'`, `` ` ``, `,`, `,@` and `#;`, which apply to the next datum.
+    Prefix(String),
+    /// `; ...` or `/* ... */`.
+    Comment(String),
+    Whitespace(String),
+}
+
+impl fmt::Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cst::List { open, children, close } => {
+                write!(f, "{open}")?;
+                for child in children {
+                    write!(f, "{child}")?;
+                }
+                write!(f, "{close}")
+            },
+            Cst::Atom(s) | Cst::Prefix(s) | Cst::Comment(s) | Cst::Whitespace(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+pub fn parse_cst(file: usize, data: &str) -> Result<Vec<Cst>, Diagnostic> {
+    let mut reader = Reader { file, data, position: 0 };
+    let mut stack: Vec<(usize, char, Vec<Cst>)> = vec![];
+    let mut nodes = vec![];
+
+    while let Some(c) = reader.peek() {
+        let start = reader.position;
+        let node = match c {
+            '(' | '[' | '{' => {
+                reader.bump();
+                stack.push((start, c, std::mem::take(&mut nodes)));
+                continue;
+            },
+            ')' | ']' | '}' => {
+                reader.bump();
+                let Some((open_at, open, parent)) = stack.pop() else {
+                    return Err(Diagnostic::new(format!("unmatched closing `{c}`"), Span::new(file, start, start + 1)));
+                };
+                if closing(open) != c {
+                    return Err(Diagnostic::new(
+                        format!("`{open}` opened on line {} is closed by `{c}`", Span::new(file, open_at, open_at).line),
+                        Span::new(file, start, start + 1),
+                    ));
+                }
+                let children = std::mem::replace(&mut nodes, parent);
+                Cst::List { open, children, close: c }
+            },
+            c if c.is_whitespace() => Cst::Whitespace(reader.take_while(|c| c.is_whitespace()).to_string()),
+            ';' => Cst::Comment(reader.take_while(|c| c != '\n').to_string()),
+            '/' if reader.rest().starts_with("/*") => Cst::Comment(reader.block_comment()?.to_string()),
+            '#' if reader.rest().starts_with("#;") => {
+                reader.bump();
+                reader.bump();
+                Cst::Prefix("#;".to_string())
+            },
+            '\'' | '`' => {
+                reader.bump();
+                Cst::Prefix(c.to_string())
+            },
+            ',' => {
+                reader.bump();
+                if reader.peek() == Some('@') {
+                    reader.bump();
+                }
+                Cst::Prefix(reader.data[start..reader.position].to_string())
+            },
+            '"' => Cst::Atom(reader.string()?.to_string()),
+            _ => {
+                if reader.rest().starts_with("#\\") {
+                    reader.bump();
+                    reader.bump();
+                    reader.bump();
+                }
+                reader.take_while(|c| !is_delimiter(c));
+                Cst::Atom(reader.data[start..reader.position].to_string())
+            },
+        };
+
+        nodes.push(node);
+    }
+
+    if let Some((open_at, open, _)) = stack.pop() {
+        return Err(Diagnostic::new(format!("unclosed `{open}`"), Span::new(file, open_at, open_at + 1)));
+    }
+
+    Ok(nodes)
+}
+
+fn closing(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+}
+
+struct Reader<'a> {
+    file: usize,
+    data: &'a str,
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.data[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) {
+        if let Some(c) = self.peek() {
+            self.position += c.len_utf8();
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let start = self.position;
+        while self.peek().is_some_and(&predicate) {
+            self.bump();
+        }
+        &self.data[start..self.position]
+    }
+
+    fn string(&mut self) -> Result<&'a str, Diagnostic> {
+        let start = self.position;
+        self.bump();
+        loop {
+            match self.peek() {
+                Some('"') => break,
+                Some('\\') => {
+                    self.bump();
+                    self.bump();
+                },
+                Some(_) => self.bump(),
+                None => return Err(Diagnostic::new("unterminated string", Span::new(self.file, start, start + 1))),
+            }
+        }
+        self.bump();
+        Ok(&self.data[start..self.position])
+    }
+
+    fn block_comment(&mut self) -> Result<&'a str, Diagnostic> {
+        let start = self.position;
+        let mut depth = 0;
+        loop {
+            if self.rest().starts_with("/*") {
+                depth += 1;
+                self.position += 2;
+            } else if self.rest().starts_with("*/") {
+                depth -= 1;
+                self.position += 2;
+                if depth == 0 {
+                    return Ok(&self.data[start..self.position]);
+                }
+            } else if self.peek().is_some() {
+                self.bump();
+            } else {
+                return Err(Diagnostic::new("unterminated block comment", Span::new(self.file, start, start + 2)));
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+use pest::Parser;
+use pest::error::{ErrorVariant, InputLocation};
+use crate::interpreter::{Rule, SimpleLispParser};
+use crate::macros::expand_macros;
+use crate::parser::parse_block;
+use crate::span::{add_source, Span};
+use crate::Node;
+
+/// A single located message, as produced by the parser.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if self.span.is_known() {
+            write!(f, "\n{}", self.span.render())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a whole file and expands its macros, giving the code every
+/// backend runs.
+pub fn parse_source(filename: &str, data: &str) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    expand_macros(parse_syntax(filename, data)?)
+}
+
+/// Parses a whole file. On failure every top-level form is parsed on its
+/// own so that all the mistakes of the file are reported at once.
+pub fn parse_syntax(filename: &str, data: &str) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    let file = add_source(filename, data);
+
+    match SimpleLispParser::parse(Rule::file, data) {
+        Ok(pairs) => parse_block(pairs, file),
+        Err(e) => {
+            let diagnostics = recover(file, data);
+            if diagnostics.is_empty() {
+                Err(vec![from_pest(file, data, e)])
+            } else {
+                Err(diagnostics)
+            }
+        },
+    }
+}
+
+fn recover(file: usize, data: &str) -> Vec<Diagnostic> {
+    let (forms, mut diagnostics) = split_forms(file, data);
+
+    for (start, end) in forms {
+        // Everything outside the form is blanked, one space per byte, so
+        // pest reports offsets (and thus lines and columns) relative to the
+        // whole file.
+        let blanked: String = data.char_indices().map(|(i, c)| {
+            if (start..end).contains(&i) || c == '\n' { c.to_string() } else { " ".repeat(c.len_utf8()) }
+        }).collect();
+
+        match SimpleLispParser::parse(Rule::file, &blanked) {
+            Ok(pairs) => {
+                if let Err(errors) = parse_block(pairs, file) {
+                    diagnostics.extend(errors);
+                }
+            },
+            Err(e) => diagnostics.push(from_pest(file, data, e)),
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.span.start);
+    diagnostics
+}
+
+/// Cuts the file into its top-level s-expressions by matching parentheses,
+/// skipping comments and strings. A `(` in the first column while a form is
+/// still open is taken as the start of the next form.
+fn split_forms(file: usize, data: &str) -> (Vec<(usize, usize)>, Vec<Diagnostic>) {
+    let bytes = data.as_bytes();
+    let mut forms = vec![];
+    let mut diagnostics = vec![];
+    let mut opened: Vec<usize> = vec![];
+    let mut form_start = 0;
+    let mut commented = None;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                match block_comment_end(bytes, i) {
+                    Some(end) => i = end,
+                    None => {
+                        diagnostics.push(Diagnostic::new("unterminated block comment", Span::new(file, i, i + 2)));
+                        break;
+                    },
+                }
+                continue;
+            },
+            b';' if i == 0 || bytes[i - 1] != b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            },
+            b'#' if bytes.get(i + 1) == Some(&b'\\') => {
+                // Skips over character literals such as `#\(`, `#\;` or `#\λ`.
+                i += 2 + data[i + 2..].chars().next().map_or(1, char::len_utf8);
+                continue;
+            },
+            b'#' if opened.is_empty() && bytes.get(i + 1) == Some(&b';') => {
+                // The commented-out datum is parsed along with the next form.
+                commented.get_or_insert(i);
+                i += 2;
+                continue;
+            },
+            b'"' if !opened.is_empty() => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                if i >= bytes.len() {
+                    let (line, column) = line_col(file, start);
+                    diagnostics.push(Diagnostic::new(format!("unterminated string opened at {line}:{column}"), Span::new(file, start, start + 1)));
+                    opened.clear();
+                    break;
+                }
+            },
+            b'(' => {
+                let first_column = i == 0 || bytes[i - 1] == b'\n';
+                if first_column && !opened.is_empty() {
+                    diagnostics.push(unclosed(file, *opened.last().unwrap()));
+                    opened.clear();
+                }
+                if opened.is_empty() {
+                    form_start = commented.take().unwrap_or(i);
+                }
+                opened.push(i);
+            },
+            b')' => {
+                if opened.pop().is_none() {
+                    diagnostics.push(Diagnostic::new("unmatched closing paren", Span::new(file, i, i + 1)));
+                } else if opened.is_empty() {
+                    forms.push((form_start, i + 1));
+                }
+            },
+            c if opened.is_empty() && !c.is_ascii_whitespace() => {
+                let start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'(' && bytes[i] != b')' {
+                    i += 1;
+                }
+                if commented.take().is_none() {
+                    diagnostics.push(Diagnostic::new(format!("expected `(` at top level, found `{}`", &data[start..i]), Span::new(file, start, i)));
+                }
+                continue;
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+
+    if let Some(&open) = opened.last() {
+        diagnostics.push(unclosed(file, open));
+    }
+
+    (forms, diagnostics)
+}
+
+/// Returns the offset just past the `*/` closing the (possibly nested) block
+/// comment starting at `start`.
+fn block_comment_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i + 1 < bytes.len() {
+        match (bytes[i], bytes[i + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                i += 2;
+            },
+            (b'*', b'/') => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => i += 1,
+        }
+    }
+
+    None
+}
+
+fn unclosed(file: usize, position: usize) -> Diagnostic {
+    let (line, column) = line_col(file, position);
+    Diagnostic::new(format!("unclosed paren opened at {line}:{column}"), Span::new(file, position, position + 1))
+}
+
+fn line_col(file: usize, position: usize) -> (usize, usize) {
+    let span = Span::new(file, position, position);
+    (span.line, span.column)
+}
+
+fn from_pest(file: usize, data: &str, e: pest::error::Error<Rule>) -> Diagnostic {
+    let (start, end) = match e.location {
+        InputLocation::Pos(p) => (p, p),
+        InputLocation::Span((s, e)) => (s, e),
+    };
+
+    let message = match &e.variant {
+        ErrorVariant::ParsingError { positives, .. } => {
+            let mut expected: Vec<&str> = positives.iter().map(rule_name).collect();
+            expected.dedup();
+            let found = match data[start..].chars().next() {
+                Some(c) => format!("`{c}`"),
+                None => "end of file".to_string(),
+            };
+
+            match expected.len() {
+                0 => format!("unexpected {found}"),
+                1 => format!("expected {}, found {found}", expected[0]),
+                n => format!("expected {} or {}, found {found}", expected[..n - 1].join(", "), expected[n - 1]),
+            }
+        },
+        ErrorVariant::CustomError { message } => message.clone(),
+    };
+
+    Diagnostic::new(message, Span::new(file, start, end.max(start + 1).min(data.len())))
+}
+
+fn rule_name(rule: &Rule) -> &'static str {
+    match rule {
+        Rule::identifier => "an identifier",
+        Rule::number => "an integer",
+        Rule::float => "a float",
+        Rule::string | Rule::inner | Rule::char => "a string",
+        Rule::character => "a character",
+        Rule::sexp => "`(`",
+        Rule::vector => "`[`",
+        Rule::map => "`{`",
+        Rule::EOI => "end of file",
+        _ => "an expression",
+    }
+}
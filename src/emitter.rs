@@ -1,27 +1,31 @@
 use std::collections::HashMap;
 use std::io::Write;
 use std::fs::File;
-use pest::Parser;
-use crate::parser::parse_block;
+use crate::diagnostics::parse_source;
+use crate::error::{ErrorKind, SlError};
+use crate::span::Span;
 use crate::*;
 use std::fs;
 use byteorder::{WriteBytesExt, LittleEndian};
 
 pub fn emit(filename: &str) {
     let data = fs::read_to_string(filename).unwrap();
-    let res = SimpleLispParser::parse(Rule::file, &data);
-
-    let nodes = match res {
-        Ok(pairs) => parse_block(pairs).unwrap(),
-        Err(e) => panic!("Can't parse {}:\n{:?}", filename, e),
+    let nodes = match parse_source(filename, &data) {
+        Ok(nodes) => nodes,
+        Err(diagnostics) => SlError::from_diagnostics(ErrorKind::Parse, diagnostics).exit(),
     };
 
     let mut emitter = Emitter {
         strings: vec![],
         classes: HashMap::new(),
         closure_id: 0,
+        loop_id: 0,
+        loops: 0,
+        span: Span::default(),
     };
 
+    let nodes: Vec<Node> = nodes.iter().map(mangle_names).collect();
+
     let path = Path::new(filename).with_extension("rock");
     let filename = path.to_str().unwrap();
     emitter.parse_root(filename, &nodes);
@@ -53,6 +57,11 @@ struct Emitter {
     strings: Vec<String>,
     classes: HashMap<String, Class>,
     closure_id: u32,
+    /// Numbers the hidden variables of `for` and `each`.
+    loop_id: u32,
+    /// Loops opened in the function being emitted.
+    loops: usize,
+    span: Span,
 }
 
 #[derive(Debug)]
@@ -84,7 +93,8 @@ impl Emitter {
         let mut main_bytes = vec![];
         for node in nodes {
             match node {
-                Node::Call { name, args } => {
+                Node::Call { name, args, span } => {
+                    self.span = *span;
                     let mut context = Context {
                         classname: None,
                         function: FunctionDecl {
@@ -99,7 +109,8 @@ impl Emitter {
                         main_bytes.push(OP_POP);
                     }
                 },
-                Node::Function { name, params, body } => {
+                Node::Function { name, params, body, span, .. } => {
+                    self.span = *span;
                     let name_only = name.clone();
                     let args_ph = if params.len() > 0 { format!("_{}", ",_".repeat(params.len() - 1)) } else { "".to_string() };
                     let name = format!("{name}({args_ph})");
@@ -146,7 +157,7 @@ impl Emitter {
 
                         let mut args = vec![];
                         for idx in 1..(i + 1) {
-                            args.push(Node::Identifier(args_names[idx].clone()));
+                            args.push(Node::Identifier(args_names[idx].clone(), span::Span::default()));
                         }
 
                         for idx in i..params.len() {
@@ -274,7 +285,7 @@ impl Emitter {
             },
             "inc" => {
                 let (write_op, index) = match &args[0] {
-                    Node::Identifier(name) => {
+                    Node::Identifier(name, _) => {
                         if context.function.args.contains(name) {
                             bytes.write_u8(OP_LOAD_MODULE_VAR).unwrap();
                             let index = context.function.args.iter().position(|r| r == name).unwrap();
@@ -294,7 +305,7 @@ impl Emitter {
                         }
                     },
                     _ =>{
-                        panic!("'inc' only accepts identifiers. Got {:?}.", args[0]);
+                        SlError::type_error(format!("'inc' only accepts identifiers. Got {}.", args[0].type_name())).at(self.span).exit();
                     } ,
                 };
 
@@ -325,21 +336,69 @@ impl Emitter {
                 self.str_push("[_]");
                 self.str_push("[_]=(_)");
 
-                bytes.extend(self.load_variable(&args[0], context));
-                bytes.extend(self.parse_node(&args[1], context));
                 if args.len() == 2 {
+                    bytes.extend(self.load_variable(&args[0], context));
+                    bytes.extend(self.parse_node(&args[1], context));
                     bytes.write_u8(OP_CALL).unwrap();
                     bytes.write_u16::<LittleEndian>(self.str_index("[_]")).unwrap();
                     bytes.write_u8(1).unwrap();
                 } else if args.len() == 3 {
+                    bytes.extend(self.load_list_copy(name, &args[0], context));
+                    bytes.extend(self.parse_node(&args[1], context));
                     bytes.extend(self.parse_node(&args[2], context));
                     bytes.write_u8(OP_CALL).unwrap();
                     bytes.write_u16::<LittleEndian>(self.str_index("[_]=(_)")).unwrap();
                     bytes.write_u8(2).unwrap();
                 } else {
-                    panic!("nth");
+                    SlError::arity_error(format!("'nth' expects 2 or 3 arguments. Got {}.", args.len())).at(self.span).exit();
                 }
             },
+            "size" | "push" | "pop" | "set-nth" | "insert" | "remove-at" | "index-of" | "contains" => {
+                let (signature, arity) = match name {
+                    "size" => ("count", 1),
+                    "push" => ("add(_)", 2),
+                    "pop" => ("removeAt(_)", 1),
+                    "set-nth" => ("[_]=(_)", 3),
+                    "insert" => ("insert(_,_)", 3),
+                    "remove-at" => ("removeAt(_)", 2),
+                    "index-of" => ("indexOf(_)", 2),
+                    _ => ("contains(_)", 2),
+                };
+                if args.len() != arity {
+                    SlError::arity_error(format!("'{name}' expects {arity} argument(s). Got {}.", args.len())).at(self.span).exit();
+                }
+
+                if matches!(name, "size" | "index-of" | "contains") {
+                    bytes.extend(self.parse_node(&args[0], context));
+                } else {
+                    bytes.extend(self.load_list_copy(name, &args[0], context));
+                }
+                for a in &args[1..] {
+                    bytes.extend(self.parse_node(a, context));
+                }
+                if name == "pop" {
+                    bytes.extend(self.parse_constant(&Node::Integer(-1)));
+                }
+                self.call_method(&mut bytes, signature, if name == "pop" { 1 } else { arity as u8 - 1 });
+            },
+            "concat" => {
+                self.str_push("List");
+                load_module_var(&mut bytes, self.str_index("List"));
+                self.call_method(&mut bytes, "new()", 0);
+                for a in args {
+                    bytes.write_u8(OP_DUP).unwrap();
+                    bytes.extend(self.parse_node(a, context));
+                    self.call_method(&mut bytes, "addAll(_)", 1);
+                    bytes.write_u8(OP_POP).unwrap();
+                }
+            },
+            "slice" | "reverse" => {
+                let arity = if name == "slice" { 2..=3 } else { 1..=1 };
+                if !arity.contains(&args.len()) {
+                    SlError::arity_error(format!("'{name}' expects {} argument(s). Got {}.", if name == "slice" { "2 or 3" } else { "1" }, args.len())).at(self.span).exit();
+                }
+                bytes.extend(self.emit_list_copy(args, name == "reverse", context));
+            },
             "if" => {
                 bytes.extend(self.parse_node(&args[0], context));
                 bytes.write_u8(OP_JUMP_IF).unwrap();
@@ -359,41 +418,255 @@ impl Emitter {
                 let pos2end = bytes.len();
                 bytes[pos2] = self.count_opcodes(&bytes[(pos2 + 1)..pos2end]);
             },
-            "while" => {
-                let loop1 = bytes.len();
+            "and" | "or" => {
+                // Every operand jumps to the end as soon as it decides the
+                // result, which is pushed there.
+                let mut jumps = vec![];
+                for a in args {
+                    bytes.extend(self.parse_node(a, context));
+                    if name == "and" {
+                        bytes.write_u8(OP_NOT).unwrap();
+                    }
+                    bytes.write_u8(OP_JUMP_IF).unwrap();
+                    jumps.push(bytes.len());
+                    bytes.write_u8(0).unwrap();
+                }
+                bytes.write_u8(if name == "and" { OP_TRUE } else { OP_FALSE }).unwrap();
+                bytes.write_u8(OP_JUMP).unwrap();
+                bytes.write_u8(1).unwrap();
 
+                let short = bytes.len();
+                for jump in jumps {
+                    bytes[jump] = self.count_opcodes(&bytes[(jump + 1)..short]);
+                }
+                bytes.write_u8(if name == "and" { OP_FALSE } else { OP_TRUE }).unwrap();
+            },
+            "not" => {
+                if args.len() != 1 {
+                    SlError::arity_error(format!("'not' expects 1 argument. Got {}.", args.len())).at(self.span).exit();
+                }
                 bytes.extend(self.parse_node(&args[0], context));
                 bytes.write_u8(OP_NOT).unwrap();
-                bytes.write_u8(OP_JUMP_IF).unwrap();
-                let jmp1 = bytes.len();
-                bytes.write_u8(0).unwrap();
+            },
+            "cond" | "when" | "unless" => {
+                let clauses: Vec<(Node, Vec<Node>)> = match name {
+                    "cond" => args.iter().map(|clause| match clause {
+                        Node::List(clause) if !clause.is_empty() => (clause[0].clone(), clause[1..].to_vec()),
+                        _ => SlError::type_error(format!("cond expects (condition body...) clauses. Got {}.", clause.type_name())).at(self.span).exit(),
+                    }).collect(),
+                    _ => {
+                        let Some((cond, body)) = args.split_first() else {
+                            SlError::arity_error(format!("'{name}' expects at least 1 argument(s). Got 0.")).at(self.span).exit();
+                        };
+                        let cond = if name == "unless" {
+                            Node::Call { name: "not".to_string(), args: vec![cond.clone()], span: self.span }
+                        } else {
+                            cond.clone()
+                        };
+                        vec![(cond, body.to_vec())]
+                    },
+                };
 
-                bytes.extend(self.parse_node(&args[1], context));
+                let mut exits = vec![];
+                let mut has_else = false;
+                for (cond, body) in clauses {
+                    let is_else = matches!(&cond, Node::Identifier(id, _) if id == "else");
+                    let mut next = None;
+                    if !is_else {
+                        bytes.extend(self.parse_node(&cond, context));
+                        bytes.write_u8(OP_NOT).unwrap();
+                        bytes.write_u8(OP_JUMP_IF).unwrap();
+                        next = Some(bytes.len());
+                        bytes.write_u8(0).unwrap();
+                    }
 
-                for i in 2..args.len() {
-                    bytes.extend(self.parse_node(&args[i], context));
-                    bytes.write_u8(OP_POP).unwrap();
+                    if body.is_empty() {
+                        bytes.write_u8(OP_NULL).unwrap();
+                    }
+                    for (i, node) in body.iter().enumerate() {
+                        if i > 0 {
+                            bytes.write_u8(OP_POP).unwrap();
+                        }
+                        bytes.extend(self.parse_node(node, context));
+                    }
+
+                    if is_else {
+                        has_else = true;
+                        break;
+                    }
+
+                    bytes.write_u8(OP_JUMP).unwrap();
+                    exits.push(bytes.len());
+                    bytes.write_u8(0).unwrap();
+                    if let Some(next) = next {
+                        bytes[next] = self.count_opcodes(&bytes[(next + 1)..]);
+                    }
+                }
+                if !has_else {
+                    bytes.write_u8(OP_NULL).unwrap();
                 }
 
-                let loop2 = bytes.len();
-                let loop_count = self.count_opcodes(&bytes[loop1..loop2]);
-                bytes.write_u8(OP_LOOP).unwrap();
-                bytes.write_u8(loop_count).unwrap();
+                for exit in exits {
+                    bytes[exit] = self.count_opcodes(&bytes[(exit + 1)..]);
+                }
+            },
+            "match" => {
+                let Some((value, clauses)) = args.split_first() else {
+                    SlError::arity_error("'match' expects at least 1 argument(s). Got 0.").at(self.span).exit();
+                };
+                self.loop_id += 1;
+                let subject = self.hidden_variable("match");
+                bytes.extend(self.parse_node(value, context));
+                store_module_var(&mut bytes, subject);
+
+                let mut exits = vec![];
+                for clause in clauses {
+                    let Some((pattern, guard, body)) = (match clause {
+                        Node::List(clause) => match_clause(clause),
+                        _ => None,
+                    }) else {
+                        SlError::type_error(format!("match expects (pattern body...) clauses. Got {}.", clause.type_name())).at(self.span).exit();
+                    };
+
+                    // Every failed check jumps to the next clause.
+                    let mut fails = vec![];
+                    self.emit_pattern(pattern, subject, &mut bytes, &mut fails, context);
+                    if let Some(guard) = guard {
+                        bytes.extend(self.parse_node(guard, context));
+                        fail_unless(&mut bytes, &mut fails);
+                    }
 
-                let jmp2 = bytes.len();
-                bytes[jmp1] = self.count_opcodes(&bytes[(jmp1 + 1)..jmp2]);
+                    if body.is_empty() {
+                        bytes.write_u8(OP_NULL).unwrap();
+                    }
+                    for (i, node) in body.iter().enumerate() {
+                        if i > 0 {
+                            bytes.write_u8(OP_POP).unwrap();
+                        }
+                        bytes.extend(self.parse_node(node, context));
+                    }
+
+                    bytes.write_u8(OP_JUMP).unwrap();
+                    exits.push(bytes.len());
+                    bytes.write_u8(0).unwrap();
+                    for fail in fails {
+                        bytes[fail] = self.count_opcodes(&bytes[(fail + 1)..]);
+                    }
+                }
                 bytes.write_u8(OP_NULL).unwrap();
+
+                for exit in exits {
+                    bytes[exit] = self.count_opcodes(&bytes[(exit + 1)..]);
+                }
+            },
+            "while" => {
+                let cond = self.parse_node(&args[0], context);
+                bytes.extend(self.emit_loop(vec![], cond, vec![], &args[1..], vec![], context));
+            },
+            "each" | "for" => {
+                let header = match args.first() {
+                    Some(Node::Call { name, args, .. }) if !args.is_empty() => {
+                        let mut header = vec![Node::Identifier(name.clone(), self.span)];
+                        header.extend(args.iter().cloned());
+                        header
+                    },
+                    _ => SlError::type_error(format!("'{name}' expects a (variable ...) header.")).at(self.span).exit(),
+                };
+                let Node::Identifier(varname, _) = &header[0] else { unreachable!() };
+                self.str_push(varname);
+                let variable = self.str_index(varname);
+
+                self.loop_id += 1;
+                let mut prelude = vec![];
+                let mut cond = vec![];
+                let mut binding = vec![];
+                let mut step = vec![];
+
+                if name == "each" {
+                    if header.len() != 2 {
+                        SlError::arity_error("'each' expects a (variable list) header.").at(self.span).exit();
+                    }
+                    let list = self.hidden_variable("list");
+                    let index = self.hidden_variable("index");
+                    self.str_push("count");
+                    self.str_push("[_]");
+
+                    prelude.extend(self.parse_node(&header[1], context));
+                    store_module_var(&mut prelude, list);
+                    prelude.extend(self.parse_constant(&Node::Integer(0)));
+                    store_module_var(&mut prelude, index);
+
+                    load_module_var(&mut cond, index);
+                    load_module_var(&mut cond, list);
+                    cond.write_u8(OP_CALL).unwrap();
+                    cond.write_u16::<LittleEndian>(self.str_index("count")).unwrap();
+                    cond.write_u8(0).unwrap();
+                    cond.write_u8(OP_LOWER_THAN).unwrap();
+
+                    load_module_var(&mut binding, list);
+                    load_module_var(&mut binding, index);
+                    binding.write_u8(OP_CALL).unwrap();
+                    binding.write_u16::<LittleEndian>(self.str_index("[_]")).unwrap();
+                    binding.write_u8(1).unwrap();
+                    store_module_var(&mut binding, variable);
+
+                    load_module_var(&mut step, index);
+                    step.extend(self.parse_constant(&Node::Integer(1)));
+                    step.write_u8(OP_ADD).unwrap();
+                    store_module_var(&mut step, index);
+                } else {
+                    if !(3..=4).contains(&header.len()) {
+                        SlError::arity_error("'for' expects a (variable start end [step]) header.").at(self.span).exit();
+                    }
+                    let end = self.hidden_variable("end");
+                    let increment = self.hidden_variable("step");
+
+                    prelude.extend(self.parse_node(&header[1], context));
+                    store_module_var(&mut prelude, variable);
+                    prelude.extend(self.parse_node(&header[2], context));
+                    store_module_var(&mut prelude, end);
+                    match header.get(3) {
+                        Some(node) => prelude.extend(self.parse_node(node, context)),
+                        None => prelude.extend(self.parse_constant(&Node::Integer(1))),
+                    }
+                    store_module_var(&mut prelude, increment);
+
+                    // (i - end) * step < 0 counts up or down depending on the
+                    // sign of the step.
+                    load_module_var(&mut cond, variable);
+                    load_module_var(&mut cond, end);
+                    cond.write_u8(OP_SUB).unwrap();
+                    load_module_var(&mut cond, increment);
+                    cond.write_u8(OP_MUL).unwrap();
+                    cond.extend(self.parse_constant(&Node::Integer(0)));
+                    cond.write_u8(OP_LOWER_THAN).unwrap();
+
+                    load_module_var(&mut step, variable);
+                    load_module_var(&mut step, increment);
+                    step.write_u8(OP_ADD).unwrap();
+                    store_module_var(&mut step, variable);
+                }
+
+                bytes.extend(self.emit_loop(prelude, cond, binding, &args[1..], step, context));
+            },
+            "break" | "continue" => {
+                if self.loops == 0 {
+                    SlError::value_error(format!("'{name}' can only be used inside a loop.")).at(self.span).exit();
+                }
+                // Turned into a jump by the enclosing loop once its layout is known.
+                bytes.write_u8(if name == "break" { OP_BREAK } else { OP_CONTINUE }).unwrap();
+                bytes.write_u8(0).unwrap();
             },
             "class" => {
                 let classname = match &args[0] {
-                    Node::Identifier(id) => id,
-                    _ => panic!("'class' only accept identifiers. Got {:?}", args[0]),
+                    Node::Identifier(id, _) => id,
+                    _ => SlError::type_error(format!("'class' only accept identifiers. Got {}.", args[0].type_name())).at(self.span).exit(),
                 };
 
                 let mut skipped = 1;
                 let mut parent = "Object".to_string();
                 if args.len() > 1 {
-                    if let Node::Identifier(parent_class) = &args[1] {
+                    if let Node::Identifier(parent_class, _) = &args[1] {
                         parent = parent_class.clone();
                         skipped += 1;
                     }
@@ -408,14 +681,14 @@ impl Emitter {
 
                 for elem in args.iter().skip(skipped) {
                     match elem {
-                        Node::Call { name, args } => {
+                        Node::Call { name, args, .. } => {
                             if name != "let" {
-                                panic!("Can't call '{}' inside the body of a class, only 'let' and 'fun' are available.", name);
+                                SlError::value_error(format!("Can't call '{}' inside the body of a class, only 'let' and 'fun' are available.", name)).at(elem.span()).exit();
                             }
 
                             let field = match &args[0] {
-                                Node::Identifier(id) => id,
-                                _ => panic!("'let' expects an identifier. Got {:?}", args[0]),
+                                Node::Identifier(id, _) => id,
+                                _ => SlError::type_error(format!("'let' expects an identifier. Got {}.", args[0].type_name())).at(elem.span()).exit(),
                             };
 
                             let default = self.parse_node(&args[1], context);
@@ -425,7 +698,7 @@ impl Emitter {
                         },
                         Node::Function { .. } => {},
                         _ => {
-                            panic!("{:?}", elem);
+                            SlError::value_error(format!("Only 'let' and 'fun' are available inside the body of a class. Got {}.", elem.type_name())).at(self.span).exit();
                         },
                     }
                 }
@@ -434,7 +707,7 @@ impl Emitter {
                 for elem in args.iter().skip(skipped) {
                     match elem {
                         Node::Call { .. } => {},
-                        Node::Function { name, params, body } => {
+                        Node::Function { name, params, body, .. } => {
                             let mut context = Context {
                                 classname: Some(classname.clone()),
                                 function: FunctionDecl {
@@ -481,7 +754,7 @@ impl Emitter {
                             });
                         },
                         _ => {
-                            panic!("{:?}", elem);
+                            SlError::value_error(format!("Only 'let' and 'fun' are available inside the body of a class. Got {}.", elem.type_name())).at(self.span).exit();
                         },
                     };
                 }
@@ -503,7 +776,7 @@ impl Emitter {
                 let _parent_class = if let Some(cname) = &context.classname {
                     self.classes.get(cname).unwrap().parent.clone()
                 } else {
-                    panic!("Can't call 'super' outside of a method.");
+                    SlError::value_error("Can't call 'super' outside of a method.").at(self.span).exit();
                 };
 
                 let args_count = context.function.args.len() - 1;
@@ -521,7 +794,7 @@ impl Emitter {
                 self.str_push(&name);
 
                 if args_count < args.len() {
-                    panic!("Too many args, expected at most {}, got {}.", args_count, args.len());
+                    SlError::arity_error(format!("Too many args, expected at most {}, got {}.", args_count, args.len())).at(self.span).exit();
                 }
 
                 // this
@@ -542,8 +815,8 @@ impl Emitter {
             },
             "let" => {
                 let name = match &args[0] {
-                    Node::Identifier(id) => id,
-                    _ => panic!("'let' expects an identifier. Got {:?}", args[0]),
+                    Node::Identifier(id, _) => id,
+                    _ => SlError::type_error(format!("'let' expects an identifier. Got {}.", args[0].type_name())).at(self.span).exit(),
                 };
 
                 self.str_push(&name);
@@ -553,32 +826,18 @@ impl Emitter {
             },
             "set" => {
                 let name = match &args[0] {
-                    Node::Identifier(id) => id,
-                    _ => panic!("'set' expects an identifier. Got {:?}", args[0]),
+                    Node::Identifier(id, _) => id,
+                    _ => SlError::type_error(format!("'set' expects an identifier. Got {}.", args[0].type_name())).at(self.span).exit(),
                 };
 
                 self.str_push(&name);
                 bytes.extend(self.parse_node(&args[1], context));
-
-                if context.function.args.contains(name) {
-                    bytes.write_u8(OP_STORE_LOCAL_VAR).unwrap();
-                    let index = context.function.args.iter().position(|r| r == name).unwrap();
-                    bytes.write_u16::<LittleEndian>(index as u16).unwrap();
-                } else if context.fields.contains(name) {
-                    bytes.write_u8(OP_STORE_FIELD_THIS).unwrap();
-                    let index = self.str_index(name);
-                    bytes.write_u16::<LittleEndian>(index).unwrap();
-                } else {
-                    bytes.write_u8(OP_STORE_MODULE_VAR).unwrap();
-                    self.str_push(name);
-                    let index = self.str_index(name);
-                    bytes.write_u16::<LittleEndian>(index).unwrap();
-                }
+                bytes.extend(self.store_variable(name, context));
             },
             "new" => {
                 let name = match &args[0] {
-                    Node::Identifier(id) => id,
-                    _ => panic!("'new' expects an identifier. Got {:?}", args[0]),
+                    Node::Identifier(id, _) => id,
+                    _ => SlError::type_error(format!("'new' expects an identifier. Got {}.", args[0].type_name())).at(self.span).exit(),
                 };
 
                 self.str_push(name);
@@ -615,8 +874,8 @@ impl Emitter {
                 self.str_push("new(_)");
 
                 let (name, params, body) = match &args[0] {
-                    Node::Function { name, params, body } => (name, params, body),
-                    _ => panic!("'fiber' expects an function. Got {:?}", args[0]),
+                    Node::Function { name, params, body, .. } => (name, params, body),
+                    _ => SlError::type_error(format!("'fiber' expects a function. Got {}.", args[0].type_name())).at(self.span).exit(),
                 };
 
                 let mut name = name.clone();
@@ -625,7 +884,7 @@ impl Emitter {
                     self.str_push(&name);
                     self.closure_id += 1;
                 } else {
-                    panic!("Only unnamed functions can be passed to 'fiber'. Got {name}");
+                    SlError::value_error(format!("Only unnamed functions can be passed to 'fiber'. Got {name}")).at(args[0].span()).exit();
                 }
 
                 let mut args_names = vec!["this".to_string()];
@@ -633,6 +892,7 @@ impl Emitter {
                     args_names.push(p.name.clone());
                 }
 
+                let loops = std::mem::take(&mut self.loops);
                 let mut code = vec![];
                 let body_size = body.len();
                 for (i, o) in body.iter().enumerate() {
@@ -642,6 +902,7 @@ impl Emitter {
                     }
                 }
                 code.write_u8(OP_RETURN).unwrap();
+                self.loops = loops;
 
                 self.classes.get_mut("$self").unwrap().functions.push(Function {
                         name: name.clone(), arity: params.len() as u8,
@@ -677,6 +938,9 @@ impl Emitter {
                 bytes.write_u16::<LittleEndian>(self.str_index("yield(_)")).unwrap();
                 bytes.write_u8(1).unwrap();
             },
+            "quote" | "quasiquote" | "unquote" | "unquote-splicing" => {
+                SlError::value_error(format!("'{name}' is only supported by the interpreter.")).at(self.span).exit();
+            },
             "return" => {
                 bytes.write_u8(OP_RETURN).unwrap();
             },
@@ -684,7 +948,7 @@ impl Emitter {
                 bytes.write_u8(OP_IMPORT_MODULE).unwrap();
                 let name = match &args[0] {
                     Node::String(s) => s,
-                    _ => panic!("'load' expects a string. Got {:?}", args[0]),
+                    _ => SlError::type_error(format!("'load' expects a string. Got {}.", args[0].type_name())).at(self.span).exit(),
                 };
                 self.str_push(name);
                 let index = self.str_index(name);
@@ -694,11 +958,11 @@ impl Emitter {
 
                 for arg in args.iter().skip(1) {
                     match arg {
-                        Node::Call { name, args } => {
+                        Node::Call { name, args, .. } => {
                             self.str_push(name);
                             let index = self.str_index(name);
                             bytes.write_u16::<LittleEndian>(index as u16).unwrap();
-                            let Node::Identifier(new_name) = &args[0] else {
+                            let Node::Identifier(new_name, _) = &args[0] else {
                                 panic!("{:?}", args);
                             };
 
@@ -706,7 +970,7 @@ impl Emitter {
                             let index = self.str_index(&new_name);
                             bytes.write_u16::<LittleEndian>(index as u16).unwrap();
                         },
-                        Node::Identifier(id) => {
+                        Node::Identifier(id, _) => {
                             self.str_push(id);
                             let index = self.str_index(id);
                             bytes.write_u16::<LittleEndian>(index as u16).unwrap();
@@ -768,7 +1032,7 @@ impl Emitter {
         let mut bytes = vec![];
 
         match node {
-            Node::Identifier(name) => {
+            Node::Identifier(name, _) => {
                 if context.function.args.contains(name) {
                     bytes.write_u8(OP_LOAD_LOCAL_VAR).unwrap();
                     let index = context.function.args.iter().position(|r| r == name).unwrap();
@@ -792,6 +1056,52 @@ impl Emitter {
         bytes
     }
 
+    /// Stores the value on top of the stack, leaving it there.
+    fn store_variable(&mut self, name: &str, context: &Context) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        if context.function.args.iter().any(|arg| arg == name) {
+            bytes.write_u8(OP_STORE_LOCAL_VAR).unwrap();
+            let index = context.function.args.iter().position(|r| r == name).unwrap();
+            bytes.write_u16::<LittleEndian>(index as u16).unwrap();
+        } else if context.fields.iter().any(|field| field == name) {
+            bytes.write_u8(OP_STORE_FIELD_THIS).unwrap();
+            let index = self.str_index(name);
+            bytes.write_u16::<LittleEndian>(index).unwrap();
+        } else {
+            bytes.write_u8(OP_STORE_MODULE_VAR).unwrap();
+            self.str_push(name);
+            let index = self.str_index(name);
+            bytes.write_u16::<LittleEndian>(index).unwrap();
+        }
+
+        bytes
+    }
+
+    /// Lists are values, as in the interpreter: the list a mutation changes
+    /// is first copied into its variable, so that other variables holding
+    /// it don't see the change.
+    fn load_list_copy(&mut self, name: &str, node: &Node, context: &mut Context) -> Vec<u8> {
+        let Node::Identifier(variable, _) = node else {
+            // The change would be lost, which the other backends report when
+            // the call is made.
+            let message = Node::String(format!("{name} changes a list held in a variable, not a value."));
+            return self.parse_call("abort", &vec![message], context);
+        };
+
+        let mut bytes = vec![];
+        self.str_push("List");
+        load_module_var(&mut bytes, self.str_index("List"));
+        self.call_method(&mut bytes, "new()", 0);
+        bytes.write_u8(OP_DUP).unwrap();
+        bytes.extend(self.load_variable(node, context));
+        self.call_method(&mut bytes, "addAll(_)", 1);
+        bytes.write_u8(OP_POP).unwrap();
+        bytes.extend(self.store_variable(variable, context));
+
+        bytes
+    }
+
     fn parse_node(&mut self, node: &Node, context: &mut Context) -> Vec<u8> {
         let mut bytes = vec![];
 
@@ -799,8 +1109,37 @@ impl Emitter {
             Node::String(_) => self.parse_constant(node),
             Node::Integer(_) => self.parse_constant(node),
             Node::Float(_) => self.parse_constant(node),
-            Node::Call { name, args } => self.parse_call(name, args, context),
-            Node::Identifier(name) => {
+            Node::List(items) => self.parse_call("list", items, context),
+            Node::Map(entries) => {
+                self.str_push("Map");
+                self.str_push("new()");
+                self.str_push("[_]=(_)");
+
+                bytes.write_u8(OP_LOAD_MODULE_VAR).unwrap();
+                bytes.write_u16::<LittleEndian>(self.str_index("Map")).unwrap();
+                bytes.write_u8(OP_CALL).unwrap();
+                bytes.write_u16::<LittleEndian>(self.str_index("new()")).unwrap();
+                bytes.write_u8(0).unwrap();
+
+                for (key, value) in entries {
+                    bytes.write_u8(OP_DUP).unwrap();
+                    bytes.extend(self.parse_node(key, context));
+                    bytes.extend(self.parse_node(value, context));
+                    bytes.write_u8(OP_CALL).unwrap();
+                    bytes.write_u16::<LittleEndian>(self.str_index("[_]=(_)")).unwrap();
+                    bytes.write_u8(2).unwrap();
+                    bytes.write_u8(OP_POP).unwrap();
+                }
+
+                vec![]
+            },
+            Node::Call { name, args, span } => {
+                let outer = std::mem::replace(&mut self.span, *span);
+                let bytes = self.parse_call(name, args, context);
+                self.span = outer;
+                bytes
+            },
+            Node::Identifier(name, _) => {
                 if context.function.args.contains(name) {
                     bytes.write_u8(OP_LOAD_LOCAL_VAR).unwrap();
                     let index = context.function.args.iter().position(|r| r == name).unwrap();
@@ -832,7 +1171,7 @@ impl Emitter {
 
                 vec![]
             },
-            Node::Function { name, params, body } => {
+            Node::Function { name, params, body, .. } => {
                 let mut name = name.clone();
                 if name.len() == 0 {
                     name = format!("closure#{}", self.closure_id);
@@ -845,6 +1184,7 @@ impl Emitter {
                     args_names.push(p.name.clone());
                 }
 
+                let loops = std::mem::take(&mut self.loops);
                 let mut code = vec![];
                 let body_size = body.len();
                 for (i, o) in body.iter().enumerate() {
@@ -854,6 +1194,7 @@ impl Emitter {
                     }
                 }
                 code.write_u8(OP_RETURN).unwrap();
+                self.loops = loops;
 
                 self.classes.get_mut("$self").unwrap().functions.push(Function {
                         name: name.clone(), arity: params.len() as u8,
@@ -874,26 +1215,204 @@ impl Emitter {
         bytes
     }
 
+    /// Lays out a loop: `prelude`, then the `cond` check, `binding`, the
+    /// body, `step` and the jump back. `break` and `continue` in the body
+    /// jump to the end of the loop and to `step`.
+    fn emit_loop(&mut self, prelude: Vec<u8>, cond: Vec<u8>, binding: Vec<u8>, body: &[Node], step: Vec<u8>, context: &mut Context) -> Vec<u8> {
+        let mut bytes = prelude;
+
+        let loop1 = bytes.len();
+        bytes.extend(cond);
+        bytes.write_u8(OP_NOT).unwrap();
+        bytes.write_u8(OP_JUMP_IF).unwrap();
+        let jmp1 = bytes.len();
+        bytes.write_u8(0).unwrap();
+        bytes.extend(binding);
+
+        let body_start = bytes.len();
+        self.loops += 1;
+        for node in body {
+            bytes.extend(self.parse_node(node, context));
+            bytes.write_u8(OP_POP).unwrap();
+        }
+        self.loops -= 1;
+
+        let step_start = bytes.len();
+        bytes.extend(step);
+
+        let loop2 = bytes.len();
+        let loop_count = self.count_opcodes(&bytes[loop1..loop2]);
+        bytes.write_u8(OP_LOOP).unwrap();
+        bytes.write_u8(loop_count).unwrap();
+
+        let jmp2 = bytes.len();
+        bytes[jmp1] = self.count_opcodes(&bytes[(jmp1 + 1)..jmp2]);
+
+        let mut i = body_start;
+        while i < step_start {
+            let operand = i + 1;
+            if bytes[i] == OP_BREAK || bytes[i] == OP_CONTINUE {
+                let target = if bytes[i] == OP_BREAK { jmp2 } else { step_start };
+                bytes[i] = OP_JUMP;
+                bytes[operand] = self.count_opcodes(&bytes[(operand + 1)..target]);
+            }
+            i += 1 + instruction_operands(&bytes, i);
+        }
+
+        bytes.write_u8(OP_NULL).unwrap();
+
+        bytes
+    }
+
+    /// Checks that the module variable `value` matches `pattern` and binds
+    /// its names, pushing in `fails` the operands of the jumps taken when it
+    /// doesn't.
+    fn emit_pattern(&mut self, pattern: &Node, value: u16, bytes: &mut Vec<u8>, fails: &mut Vec<usize>, context: &mut Context) {
+        match pattern {
+            Node::Identifier(name, _) if name == "_" => {},
+            Node::Identifier(name, _) if matches!(name.as_str(), "true" | "false" | "null") => {
+                load_module_var(bytes, value);
+                bytes.extend(self.parse_node(pattern, context));
+                bytes.write_u8(OP_EQUAL).unwrap();
+                fail_unless(bytes, fails);
+            },
+            Node::Identifier(name, _) => {
+                self.str_push(name);
+                load_module_var(bytes, value);
+                store_module_var(bytes, self.str_index(name));
+            },
+            Node::Integer(_) | Node::Float(_) | Node::String(_) => {
+                load_module_var(bytes, value);
+                bytes.extend(self.parse_constant(pattern));
+                bytes.write_u8(OP_EQUAL).unwrap();
+                fail_unless(bytes, fails);
+            },
+            Node::List(patterns) => {
+                if patterns.iter().any(|p| matches!(p, Node::Identifier(id, _) if id == "&")) {
+                    SlError::value_error("RockVM doesn't support `&` in patterns.").at(self.span).exit();
+                }
+                self.str_push("type");
+                self.str_push("List");
+                self.str_push("count");
+                self.str_push("[_]");
+
+                load_module_var(bytes, value);
+                bytes.write_u8(OP_CALL).unwrap();
+                bytes.write_u16::<LittleEndian>(self.str_index("type")).unwrap();
+                bytes.write_u8(0).unwrap();
+                load_module_var(bytes, self.str_index("List"));
+                bytes.write_u8(OP_EQUAL).unwrap();
+                fail_unless(bytes, fails);
+
+                load_module_var(bytes, value);
+                bytes.write_u8(OP_CALL).unwrap();
+                bytes.write_u16::<LittleEndian>(self.str_index("count")).unwrap();
+                bytes.write_u8(0).unwrap();
+                bytes.extend(self.parse_constant(&Node::Integer(patterns.len() as i64)));
+                bytes.write_u8(OP_EQUAL).unwrap();
+                fail_unless(bytes, fails);
+
+                for (i, pattern) in patterns.iter().enumerate() {
+                    self.loop_id += 1;
+                    let element = self.hidden_variable("element");
+                    load_module_var(bytes, value);
+                    bytes.extend(self.parse_constant(&Node::Integer(i as i64)));
+                    bytes.write_u8(OP_CALL).unwrap();
+                    bytes.write_u16::<LittleEndian>(self.str_index("[_]")).unwrap();
+                    bytes.write_u8(1).unwrap();
+                    store_module_var(bytes, element);
+                    self.emit_pattern(pattern, element, bytes, fails, context);
+                }
+            },
+            Node::Call { .. } => SlError::value_error("RockVM doesn't support class patterns.").at(self.span).exit(),
+            _ => SlError::type_error(format!("A {} can't be used as a pattern.", pattern.type_name())).at(self.span).exit(),
+        }
+    }
+
+    /// Copies into a new list the elements of `args[0]` from `args[1]` to
+    /// `args[2]`, or all of them backwards for `reverse`.
+    fn emit_list_copy(&mut self, args: &[Node], reverse: bool, context: &mut Context) -> Vec<u8> {
+        self.loop_id += 1;
+        let list = self.hidden_variable("list");
+        let result = self.hidden_variable("result");
+        let index = self.hidden_variable("index");
+        let end = self.hidden_variable("end");
+        self.str_push("List");
+
+        let mut prelude = self.parse_node(&args[0], context);
+        store_module_var(&mut prelude, list);
+        load_module_var(&mut prelude, self.str_index("List"));
+        self.call_method(&mut prelude, "new()", 0);
+        store_module_var(&mut prelude, result);
+        if reverse {
+            load_module_var(&mut prelude, list);
+            self.call_method(&mut prelude, "count", 0);
+            prelude.extend(self.parse_constant(&Node::Integer(1)));
+            prelude.write_u8(OP_SUB).unwrap();
+            store_module_var(&mut prelude, index);
+        } else {
+            prelude.extend(self.parse_node(&args[1], context));
+            store_module_var(&mut prelude, index);
+            match args.get(2) {
+                Some(node) => prelude.extend(self.parse_node(node, context)),
+                None => {
+                    load_module_var(&mut prelude, list);
+                    self.call_method(&mut prelude, "count", 0);
+                },
+            }
+            store_module_var(&mut prelude, end);
+        }
+
+        let mut cond = vec![];
+        if reverse {
+            cond.extend(self.parse_constant(&Node::Integer(-1)));
+            load_module_var(&mut cond, index);
+        } else {
+            load_module_var(&mut cond, index);
+            load_module_var(&mut cond, end);
+        }
+        cond.write_u8(OP_LOWER_THAN).unwrap();
+
+        let mut binding = vec![];
+        load_module_var(&mut binding, result);
+        load_module_var(&mut binding, list);
+        load_module_var(&mut binding, index);
+        self.call_method(&mut binding, "[_]", 1);
+        self.call_method(&mut binding, "add(_)", 1);
+        binding.write_u8(OP_POP).unwrap();
+
+        let mut step = vec![];
+        load_module_var(&mut step, index);
+        step.extend(self.parse_constant(&Node::Integer(1)));
+        step.write_u8(if reverse { OP_SUB } else { OP_ADD }).unwrap();
+        store_module_var(&mut step, index);
+
+        let mut bytes = self.emit_loop(prelude, cond, binding, &[], step, context);
+        bytes.write_u8(OP_POP).unwrap();
+        load_module_var(&mut bytes, result);
+
+        bytes
+    }
+
+    fn call_method(&mut self, bytes: &mut Vec<u8>, signature: &str, arity: u8) {
+        self.str_push(signature);
+        bytes.write_u8(OP_CALL).unwrap();
+        bytes.write_u16::<LittleEndian>(self.str_index(signature)).unwrap();
+        bytes.write_u8(arity).unwrap();
+    }
+
+    /// A module variable only the emitter can name, for the state of a loop.
+    fn hidden_variable(&mut self, name: &str) -> u16 {
+        let name = format!("${name}{}", self.loop_id);
+        self.str_push(&name);
+        self.str_index(&name)
+    }
+
     fn count_opcodes(&self, buf: &[u8]) -> u8 {
         let mut total = 0;
         let mut i = 0;
         while i < buf.len() {
-            i += 1 + match buf[i] {
-                OP_CONSTANT => match buf[i+1] {
-                    VAL_NULL => 1,
-                    VAL_BOOL => 2,
-                    VAL_INTEGER => 5,
-                    VAL_FLOAT => 5,
-                    VAL_STRING => 3,
-                    _ => panic!("{:?}", buf[i+1]),
-                },
-                OP_LOAD_MODULE_VAR | OP_STORE_MODULE_VAR | OP_LOAD_LOCAL_VAR | OP_STORE_LOCAL_VAR => 2,
-                OP_JUMP | OP_JUMP_IF | OP_LOOP | OP_LOOP_IF => 1,
-                OP_CALL => 3,
-                OP_ADD | OP_SUB | OP_MUL | OP_DIV | OP_EQUAL | OP_LOWER_THAN | OP_GREATER_THAN
-                | OP_NEGATE | OP_RETURN | OP_POP | OP_NOT => 0,
-                _ => panic!("count_opcodes: {} at index {i}", buf[i]),
-            };
+            i += 1 + instruction_operands(buf, i);
             total += 1;
         }
 
@@ -914,11 +1433,14 @@ impl Emitter {
             },
             Node::Integer(i) => {
                 bytes.write_u8(VAL_INTEGER).unwrap();
-                bytes.write_i32::<LittleEndian>(*i).unwrap();
+                let Ok(i) = i32::try_from(*i) else {
+                    SlError::value_error(format!("integer {i} does not fit in a RockVM integer (32 bits)")).at(self.span).exit();
+                };
+                bytes.write_i32::<LittleEndian>(i).unwrap();
             },
             Node::Float(i) => {
                 bytes.write_u8(VAL_FLOAT).unwrap();
-                bytes.write_f32::<LittleEndian>(*i).unwrap();
+                bytes.write_f32::<LittleEndian>(*i as f32).unwrap();
             },
             Node::Null => {
                 bytes.write_u8(VAL_NULL).unwrap();
@@ -947,6 +1469,91 @@ impl Emitter {
     }
 }
 
+/// Size in bytes of the operands of the instruction at `i`.
+fn instruction_operands(buf: &[u8], i: usize) -> usize {
+    match buf[i] {
+        OP_CONSTANT => 1 + match buf[i+1] {
+            VAL_NULL => 0,
+            VAL_BOOL => 1,
+            VAL_INTEGER => 4,
+            VAL_FLOAT => 4,
+            VAL_STRING => 2,
+            _ => panic!("{:?}", buf[i+1]),
+        },
+        OP_LOAD_MODULE_VAR | OP_STORE_MODULE_VAR | OP_LOAD_LOCAL_VAR | OP_STORE_LOCAL_VAR
+        | OP_LOAD_FIELD_THIS | OP_STORE_FIELD_THIS | OP_CLOSURE => 2,
+        OP_JUMP | OP_JUMP_IF | OP_LOOP | OP_LOOP_IF | OP_BREAK | OP_CONTINUE => 1,
+        OP_CALL => 3,
+        OP_ADD | OP_SUB | OP_MUL | OP_DIV | OP_EQUAL | OP_LOWER_THAN | OP_GREATER_THAN
+        | OP_NEGATE | OP_RETURN | OP_POP | OP_NOT | OP_NULL | OP_TRUE | OP_FALSE | OP_DUP => 0,
+        _ => panic!("count_opcodes: {} at index {i}", buf[i]),
+    }
+}
+
+fn load_module_var(bytes: &mut Vec<u8>, index: u16) {
+    bytes.write_u8(OP_LOAD_MODULE_VAR).unwrap();
+    bytes.write_u16::<LittleEndian>(index).unwrap();
+}
+
+/// Jumps when the value on top of the stack is false, pushing the operand of
+/// the jump in `fails` to be patched later.
+fn fail_unless(bytes: &mut Vec<u8>, fails: &mut Vec<usize>) {
+    bytes.write_u8(OP_NOT).unwrap();
+    bytes.write_u8(OP_JUMP_IF).unwrap();
+    fails.push(bytes.len());
+    bytes.write_u8(0).unwrap();
+}
+
+/// Stores the value on top of the stack and pops it.
+fn store_module_var(bytes: &mut Vec<u8>, index: u16) {
+    bytes.write_u8(OP_STORE_MODULE_VAR).unwrap();
+    bytes.write_u16::<LittleEndian>(index).unwrap();
+    bytes.write_u8(OP_POP).unwrap();
+}
+
+/// Escapes the characters RockVM can't take in a name, or which would clash
+/// with the signatures the emitter builds (`add(_)`, `[_]=(_)`, `$self`...),
+/// as `$` followed by the hexadecimal code point and `;`.
+fn mangle_name(name: &str) -> String {
+    let mut mangled = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '/' {
+            mangled.push(c);
+        } else {
+            mangled.push_str(&format!("${:x};", c as u32));
+        }
+    }
+    mangled
+}
+
+fn mangle_names(node: &Node) -> Node {
+    match node {
+        Node::Function { name, params, body, env, span } => Node::Function {
+            name: mangle_name(name),
+            params: params.iter().map(|p| Param {
+                name: mangle_name(&p.name),
+                default_value: p.default_value.as_ref().map(mangle_names),
+                rest: p.rest,
+            }).collect(),
+            body: body.iter().map(mangle_names).collect(),
+            env: env.clone(),
+            span: *span,
+        },
+        Node::Call { name, args, span } => Node::Call {
+            name: mangle_name(name),
+            args: args.iter().map(mangle_names).collect(),
+            span: *span,
+        },
+        // Keywords mark the forms they are part of, like the `:when` of a
+        // `match` guard, and name nothing.
+        Node::Identifier(name, _) if name.starts_with(':') => node.clone(),
+        Node::Identifier(name, span) => Node::Identifier(mangle_name(name), *span),
+        Node::List(items) => Node::List(items.iter().map(mangle_names).collect()),
+        Node::Map(entries) => Node::Map(entries.iter().map(|(k, v)| (mangle_names(k), mangle_names(v))).collect()),
+        _ => node.clone(),
+    }
+}
+
 const OP_RETURN: u8 = 1;
 const OP_CONSTANT: u8 = 2;
 const OP_NEGATE: u8 = 3;
@@ -980,6 +1587,11 @@ const OP_IMPORT_MODULE: u8 = 30;
 const OP_SUPER: u8 = 31;
 //const OP_DUMP_STACK: u8 = 255;
 
+/// Emitted for `break` and `continue` until the loop around them replaces
+/// them with an `OP_JUMP`, they never reach the VM.
+const OP_BREAK: u8 = 0xF0;
+const OP_CONTINUE: u8 = 0xF1;
+
 const VAL_NULL: u8 = 1;
 const VAL_BOOL: u8 = 2;
 const VAL_INTEGER: u8 = 3;
@@ -0,0 +1,134 @@
+use std::fmt;
+use crate::diagnostics::Diagnostic;
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Io,
+    Parse,
+    Name,
+    Type,
+    Arity,
+    Index,
+    Value,
+    /// A value thrown by the script itself.
+    Thrown,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Io => "io error",
+            ErrorKind::Parse => "parse error",
+            ErrorKind::Name => "name error",
+            ErrorKind::Type => "type error",
+            ErrorKind::Arity => "arity error",
+            ErrorKind::Index => "index error",
+            ErrorKind::Value => "value error",
+            ErrorKind::Thrown => "uncaught error",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Error raised while running or compiling a script. `span` points at the
+/// innermost expression that failed and `stack` lists the functions the
+/// error went through, innermost first. `related` holds the other errors
+/// found in the same pass, e.g. every parse error of a file.
+#[derive(Debug, Clone)]
+pub struct SlError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Span,
+    pub stack: Vec<String>,
+    pub related: Vec<Diagnostic>,
+}
+
+pub type SlResult<T> = Result<T, SlError>;
+
+impl SlError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            span: Span::default(),
+            stack: vec![],
+            related: vec![],
+        }
+    }
+
+    pub fn from_diagnostics(kind: ErrorKind, mut diagnostics: Vec<Diagnostic>) -> Self {
+        let first = diagnostics.remove(0);
+        let mut error = Self::new(kind, first.message).at(first.span);
+        error.related = diagnostics;
+        error
+    }
+
+    pub fn io_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Io, message)
+    }
+
+    pub fn name_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Name, message)
+    }
+
+    pub fn type_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Type, message)
+    }
+
+    pub fn arity_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Arity, message)
+    }
+
+    pub fn index_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Index, message)
+    }
+
+    pub fn value_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Value, message)
+    }
+
+    /// Error for a `throw` of the script, the value itself is kept by the
+    /// interpreter.
+    pub fn thrown(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Thrown, message)
+    }
+
+    /// Attaches a location unless a more precise one is already known.
+    pub fn at(mut self, span: Span) -> Self {
+        if !self.span.is_known() {
+            self.span = span;
+        }
+        self
+    }
+
+    pub fn with_frame(mut self, name: &str) -> Self {
+        self.stack.push(name.to_string());
+        self
+    }
+
+    /// Prints the error and stops the process, used by the compilers which
+    /// have no caller to hand the error back to.
+    pub fn exit(self) -> ! {
+        eprintln!("{self}");
+        std::process::exit(1);
+    }
+}
+
+impl fmt::Display for SlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)?;
+        if self.span.is_known() {
+            write!(f, "\n{}", self.span.render())?;
+        }
+        for frame in &self.stack {
+            write!(f, "\n    in {frame}")?;
+        }
+        for diagnostic in &self.related {
+            write!(f, "\n{}: {}", self.kind, diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SlError {}
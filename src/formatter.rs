@@ -0,0 +1,154 @@
+use std::fs;
+use crate::cst::{parse_cst, Cst};
+use crate::diagnostics::{parse_syntax, Diagnostic};
+use crate::error::{ErrorKind, SlError};
+use crate::span::add_source;
+
+/// Formats the files in place, or with `check` only reports the ones that
+/// are not formatted. Returns whether every file was already formatted
+/// (`check`) or could be formatted.
+pub fn format_files(files: &[String], check: bool) -> bool {
+    let mut success = true;
+
+    for filename in files {
+        let data = match fs::read_to_string(filename) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("{}", SlError::io_error(format!("Can't read {filename}: {e}")));
+                success = false;
+                continue;
+            },
+        };
+
+        let formatted = match format_source(filename, &data) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                eprintln!("{}", SlError::from_diagnostics(ErrorKind::Parse, diagnostics));
+                success = false;
+                continue;
+            },
+        };
+
+        if formatted == data {
+            continue;
+        }
+
+        if check {
+            println!("{filename} is not formatted");
+            success = false;
+        } else if let Err(e) = fs::write(filename, formatted) {
+            eprintln!("{}", SlError::io_error(format!("Can't write {filename}: {e}")));
+            success = false;
+        }
+    }
+
+    success
+}
+
+/// Re-indents a file with tabs: a line is indented once more than the line
+/// that opened the innermost list it is in, and a line starting with a
+/// closing paren is aligned with the line that opened it. Line breaks are
+/// kept as written, with at most one blank line in a row, and elements of
+/// a line are separated by a single space. The spacing before a trailing
+/// comment is kept so comments can stay aligned.
+pub fn format_source(filename: &str, data: &str) -> Result<String, Vec<Diagnostic>> {
+    // Only valid files are formatted, with the parser's own diagnostics.
+    parse_syntax(filename, data)?;
+
+    let file = add_source(filename, data);
+    let nodes = parse_cst(file, data).map_err(|d| vec![d])?;
+
+    let mut printer = Printer::default();
+    for node in &nodes {
+        printer.print(node);
+    }
+
+    if !printer.output.is_empty() {
+        printer.output.push('\n');
+    }
+
+    Ok(printer.output)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Prefix,
+    Atom,
+    Comment,
+}
+
+#[derive(Default)]
+struct Printer {
+    output: String,
+    /// Indentation of the line each open list started on.
+    openers: Vec<usize>,
+    /// Indentation of the current line.
+    indent: usize,
+    /// Line breaks seen since the last token.
+    newlines: usize,
+    /// Whitespace seen since the last token, on the same line.
+    gap: String,
+    /// Whether the next token sticks to the previous one, as after `(`.
+    glued: bool,
+}
+
+impl Printer {
+    fn print(&mut self, node: &Cst) {
+        match node {
+            Cst::List { open, children, close } => {
+                self.token(&open.to_string(), Token::Open);
+                self.openers.push(self.indent);
+                for child in children {
+                    self.print(child);
+                }
+                self.token(&close.to_string(), Token::Close);
+                self.openers.pop();
+            },
+            Cst::Atom(text) => self.token(text, Token::Atom),
+            // Unlike quotes, `#;` is kept apart from the datum it removes.
+            Cst::Prefix(text) if text == "#;" => self.token(text, Token::Atom),
+            Cst::Prefix(text) => self.token(text, Token::Prefix),
+            Cst::Comment(text) => self.token(text.trim_end(), Token::Comment),
+            Cst::Whitespace(text) => {
+                let newlines = text.matches('\n').count();
+                if newlines > 0 {
+                    self.newlines += newlines;
+                    self.gap.clear();
+                } else if self.newlines == 0 {
+                    self.gap = text.clone();
+                }
+            },
+        }
+    }
+
+    fn token(&mut self, text: &str, token: Token) {
+        let line_start = self.output.is_empty() || self.newlines > 0;
+
+        if line_start {
+            if !self.output.is_empty() {
+                self.output.push('\n');
+                if self.newlines > 1 {
+                    self.output.push('\n');
+                }
+            }
+
+            self.indent = match (token, self.openers.last()) {
+                (Token::Close, Some(&indent)) => indent,
+                (_, Some(&indent)) => indent + 1,
+                (_, None) => 0,
+            };
+            self.output.push_str(&"\t".repeat(self.indent));
+        } else if token == Token::Comment && !self.gap.is_empty() {
+            self.output.push_str(&self.gap);
+        } else if !self.glued && token != Token::Close {
+            self.output.push(' ');
+        }
+
+        self.output.push_str(text);
+        self.newlines = 0;
+        self.gap.clear();
+        self.glued = matches!(token, Token::Open | Token::Prefix);
+    }
+}
@@ -3,8 +3,9 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io::Write;
-use pest::Parser;
-use crate::parser::parse_block;
+use crate::diagnostics::parse_source;
+use crate::error::{ErrorKind, SlError};
+use crate::span::Span;
 use crate::*;
 use std::fs;
 use std::process::Command;
@@ -23,6 +24,12 @@ struct Generator {
     current_method_args: Vec<String>,
     classes: HashMap::<String, String>,
     converted_names: HashMap::<String, String>,
+    /// Loops opened in the current function, `return` has to leave them.
+    loops: usize,
+    /// Whether the current function has a `return` inside a loop.
+    returns_from_loop: bool,
+    /// Lists destructured by `let` so far, to name their variables.
+    destructures: usize,
 }
 
 pub fn generate(filename: &str) {
@@ -38,6 +45,9 @@ pub fn generate(filename: &str) {
         current_class: None,
         current_method: None,
         current_method_args: vec![],
+        loops: 0,
+        returns_from_loop: false,
+        destructures: 0,
         classes: HashMap::new(),
         converted_names: HashMap::new(),
     };
@@ -56,6 +66,11 @@ pub fn generate(filename: &str) {
     std::io::stderr().write_all(&output.stderr).unwrap();
 }
 
+/// Natives taking functions, to which the name of a function is passed as
+/// the function itself. `map` only maps when its first argument is a
+/// function, and builds a map otherwise.
+const HIGHER_ORDER_NATIVES: [&str; 7] = ["map", "filter", "reduce", "sort-by", "find", "any", "all"];
+
 impl Generator {
     fn generate(&mut self, filename: &str) -> String {
         let path = Path::new(filename);
@@ -65,6 +80,15 @@ impl Generator {
         for i in 1..16 {
             self.functions_names.insert("print".into());
             self.functions_names.insert("write".into());
+            self.functions_names.insert("range".into());
+        }
+        for native in [
+            "map", "map_get", "map_set", "map_remove", "has_key", "keys", "values", "map_size", "is_map",
+            "size", "nth", "push", "pop", "set_nth", "insert", "remove_at", "slice", "concat", "reverse", "index_of", "contains",
+            "filter", "reduce", "sort", "sort_by", "find", "any", "all", "zip", "add", "sub", "lt", "eq",
+            "str_len", "substr", "split", "join", "replace", "upper", "lower", "trim", "starts_with", "ends_with", "char_at", "to_int", "to_float", "format",
+        ] {
+            self.functions_names.insert(native.into());
         }
 
         self.generate_file(filename);
@@ -120,7 +144,15 @@ impl Generator {
         }
 
         output.push_str("int main() {\n");
+        output.push_str("try {\n");
         output.push_str(&self.main.last().unwrap());
+        output.push_str("} catch (const Value& value) {\n");
+        output.push_str("std::cerr << \"uncaught error: \" << value << '\\n';\n");
+        output.push_str("return 1;\n");
+        output.push_str("} catch (const RuntimeError& error) {\n");
+        output.push_str("std::cerr << error.kind << \": \" << error.message << '\\n';\n");
+        output.push_str("return 1;\n");
+        output.push_str("}\n");
         output.push_str("return 0;\n");
         output.push_str("}\n");
 
@@ -129,11 +161,9 @@ impl Generator {
 
     fn generate_file(&mut self, filename: &str) {
         let data = fs::read_to_string(filename).unwrap();
-        let res = SimpleLispParser::parse(Rule::file, &data);
-
-        let nodes = match res {
-            Ok(pairs) => parse_block(pairs).unwrap(),
-            Err(e) => panic!("Can't parse {}:\n{:?}", filename, e),
+        let nodes = match parse_source(filename, &data) {
+            Ok(nodes) => nodes,
+            Err(diagnostics) => SlError::from_diagnostics(ErrorKind::Parse, diagnostics).exit(),
         };
 
         for node in nodes {
@@ -145,17 +175,30 @@ impl Generator {
                     self.functions.push(res);
                     self.functions_names.insert(converted_name);
                 },
-                Node::Call { name, args } => {
-                    if name == "let" {
+                Node::Call { name, args, span } => {
+                    if name == "let" && matches!(args[0], Node::List(_)) {
+                        for varname in pattern_names(&args[0]) {
+                            let new_name = self.convert_name(&varname);
+                            self.headers.push(format!("Value {};\n", new_name));
+                        }
+                        self.depth += 1;
+                        self.inside_expression = 1;
+                        let n = self.generate_node(args[1].clone());
+                        self.inside_expression = 0;
+                        self.depth -= 1;
+                        for statement in self.destructure(&args[0], &n, false, span) {
+                            self.main.last_mut().unwrap().push_str(&format!("{statement};\n"));
+                        }
+                    } else if name == "let" && !matches!(args[0], Node::Map(_)) {
                         let varname = match args[0].clone() {
-                            Node::Identifier(s) => s,
-                            _ => panic!("{}", args[0]),
+                            Node::Identifier(s, _) => s,
+                            _ => SlError::type_error(format!("'let' expects an identifier. Got {}.", args[0].type_name())).at(span).exit(),
                         };
                         let new_name = self.convert_name(&varname);
                         self.headers.push(format!("Value {};\n", new_name));
                         self.depth += 1;
                         self.inside_expression = 1;
-                        let n = self.generate_node(args[1].clone());
+                        let n = self.generate_value(args[1].clone());
                         self.inside_expression = 0;
                         self.depth -= 1;
                         self.main.last_mut().unwrap().push_str(&format!("{} = {};\n", new_name, n));
@@ -176,12 +219,13 @@ impl Generator {
         let mut ret = String::new();
 
         match node {
-            Node::Function { name, params, body } => {
+            Node::Function { name, params, body, span, .. } => {
                 if self.current_class.is_some() {
                     self.current_method = Some(name.clone());
 
                     for p in &params {
-                        self.current_method_args.push(p.name.clone());
+                        let converted_name = self.convert_name(&p.name);
+                        self.current_method_args.push(converted_name);
                     }
                 }
 
@@ -195,7 +239,7 @@ impl Generator {
                     }
                     output.push_str("(Value::Function([=]");
                     if name.len() > 0 && self.inside_expression > 0 {
-                        panic!("Not supported until gcc/clang support C++23's deducing this.");
+                        SlError::value_error("Named functions inside expressions are not supported until gcc/clang support C++23's deducing this.").at(span).exit();
                         output.push_str(&format!("<typename Self>(this Self & {}, ", converted_name));
                     } else {
                         output.push_str("(");
@@ -229,41 +273,49 @@ impl Generator {
                     output.push_str("}\n\n");
                 }
 
+                if !params.is_empty() {
+                    let has_rest = params.last().is_some_and(|p| p.rest);
+                    if has_rest {
+                        output.push_str("std::vector<Value> rest1;\n");
+                    }
+
+                    let names: Vec<_> = params.iter().filter(|p| !p.rest).map(|p| format!("\"{}\"s", escape_string(&p.name))).collect();
+                    let frame = if name.is_empty() { "<lambda>" } else { &name };
+                    output.push_str(&format!(
+                        "auto params1 = bind_arguments(args1, {{ {} }}, \"{}\"s, {});\n",
+                        names.join(", "), escape_string(frame), if has_rest { "&rest1" } else { "nullptr" },
+                    ));
+                }
+
                 for (id, p) in params.iter().enumerate() {
-                    let pname = &p.name;
-                    if let Some(default_value) = &p.default_value {
+                    let pname = &self.convert_name(&p.name);
+                    if p.rest {
+                        output.push_str(&format!("Value {pname} = rest1;\n"));
+                    } else if let Some(default_value) = &p.default_value {
                         output.push_str(&format!("Value {pname};\n"));
-                        output.push_str(&format!("if ({id} < args1.size()) {{\n"));
-                        output.push_str(&format!("{pname} = args1[{id}];\n"));
+                        output.push_str(&format!("if (params1[{id}]) {{\n"));
+                        output.push_str(&format!("{pname} = *params1[{id}];\n"));
                         output.push_str("} else {\n");
                         output.push_str(&format!("{pname} = {default_value};\n"));
                         output.push_str("}\n");
                     } else {
-                        output.push_str(&format!("Value {pname} = args1[{id}];\n"));
+                        output.push_str(&format!("Value {pname} = required_argument(params1[{id}], \"{}\"s);\n", escape_string(&p.name)));
                     }
                 }
+                let loops = std::mem::take(&mut self.loops);
+                let returns_from_loop = std::mem::take(&mut self.returns_from_loop);
+                let prologue = output.len();
                 output.push_str("Value ret1;\n");
-                if body.len() > 0 {
-                    self.depth += 1;
-                    let last = body.len() - 1;
-                    for (i, arg) in body.iter().enumerate() {
-                        if i == last {
-                            if let Node::Call{ name, args } = arg {
-                                if name != "let" {
-                                    output.push_str(&format!("ret1 = {};\n", self.generate_node(arg.clone())));
-                                } else {
-                                    output.push_str(&format!("{};\nret1 = {};\n", self.generate_node(arg.clone()), args[1]));
-                                }
-                            } else {
-                                    output.push_str(&format!("ret1 = {};\n", self.generate_node(arg.clone())));
-                            }
-                        } else {
-                            output.push_str(&format!("{};\n", self.generate_node(arg.clone())));
-                        }
-                    }
-                    self.depth -= 1;
-                }
+                self.depth += 1;
+                output.push_str(&self.generate_block(&body));
+                self.depth -= 1;
                 output.push_str("return ret1;\n");
+                if self.returns_from_loop {
+                    output.insert_str(prologue, "try {\n");
+                    output.push_str("} catch (const ReturnValue& r) {\nreturn r.value;\n}\n");
+                }
+                self.loops = loops;
+                self.returns_from_loop = returns_from_loop;
                 if name.len() > 0 && self.depth == 0 {
                     output.push_str("}\n");
                 } else {
@@ -275,16 +327,46 @@ impl Generator {
                 self.current_method = None;
                 self.current_method_args.clear();
             },
-            Node::Call { name, args } => {
+            Node::Call { name, args, span } => {
                 match name.as_str() {
+                    "let" if matches!(args.first(), Some(Node::Map(_))) => {
+                        let Node::Map(bindings) = &args[0] else { unreachable!() };
+
+                        self.depth += 1;
+                        ret.push_str("[&]() -> Value {\n");
+                        for (i, (pattern, value)) in bindings.iter().enumerate() {
+                            // The value is computed before the names it binds
+                            // hide the outer ones.
+                            self.inside_expression = 1;
+                            let value = self.generate_node(value.clone());
+                            self.inside_expression = 0;
+                            ret.push_str(&format!("Value let{} = {value};\n", i + 1));
+                            for statement in self.destructure(pattern, &format!("let{}", i + 1), true, span) {
+                                ret.push_str(&format!("{statement};\n"));
+                            }
+                        }
+                        ret.push_str("Value ret1;\n");
+                        ret.push_str(&self.generate_block(&args[1..]));
+                        ret.push_str("return ret1;\n");
+                        ret.push_str("}()");
+                        self.depth -= 1;
+                    },
+                    "let" if matches!(args.first(), Some(Node::List(_))) => {
+                        self.depth += 1;
+                        self.inside_expression = 1;
+                        let value = self.generate_node(args[1].clone());
+                        self.inside_expression = 0;
+                        self.depth -= 1;
+                        ret.push_str(&self.destructure(&args[0], &value, true, span).join(";\n"));
+                    },
                     "let" => {
                         let varname = match args[0].clone() {
-                            Node::Identifier(s) => s,
-                            _ => panic!("{}", args[0]),
+                            Node::Identifier(s, _) => s,
+                            _ => SlError::type_error(format!("'let' expects an identifier. Got {}.", args[0].type_name())).at(span).exit(),
                         };
                         self.depth += 1;
                         self.inside_expression = 1;
-                        ret.push_str(&format!("Value {} = {}", self.convert_name(&varname), self.generate_node(args[1].clone())));
+                        ret.push_str(&format!("Value {} = {}", self.convert_name(&varname), self.generate_value(args[1].clone())));
                         self.inside_expression = 0;
                         self.depth -= 1;
 
@@ -294,31 +376,54 @@ impl Generator {
                     },
                     "set" => {
                         let varname = match args[0].clone() {
-                            Node::Identifier(s) => s,
-                            _ => panic!("{}", args[0]),
+                            Node::Identifier(s, _) => s,
+                            _ => SlError::type_error(format!("'set' expects an identifier. Got {}.", args[0].type_name())).at(span).exit(),
                         };
-                        ret.push_str(&format!("{} = {}", self.convert_name(&varname), self.generate_node(args[1].clone())));
+                        ret.push_str(&format!("{} = {}", self.convert_name(&varname), self.generate_value(args[1].clone())));
                     },
                     "while" => {
-                        ret.push_str("[=]() mutable {\nValue ret1;\nwhile (");
-                        ret.push_str(&format!("{}", self.generate_node(args[0].clone())));
-                        ret.push_str(") {\n");
-                        let last = args.len() - 2;
-                        for (i, arg) in args.iter().skip(1).enumerate() {
-                            if i == last {
-                                if let Node::Call{ name, args } = arg {
-                                    if name != "let" {
-                                        ret.push_str(&format!("ret1 = {};\n", self.generate_node(arg.clone())));
-                                    } else {
-                                        ret.push_str(&format!("{};\nret1 = {};\n", self.generate_node(arg.clone()), args[1]));
-                                    }
-                                }
-                            } else {
-                                ret.push_str(&format!("{};\n", self.generate_node(arg.clone())));
+                        let cond = self.generate_node(args[0].clone());
+                        ret.push_str(&self.generate_loop("", &format!("while ({cond})"), "", &args[1..]));
+                    },
+                    "each" | "for" => {
+                        let header = match args.first() {
+                            Some(Node::Call { name, args, .. }) if !args.is_empty() => {
+                                let mut header = vec![Node::Identifier(name.clone(), span)];
+                                header.extend(args.iter().cloned());
+                                header
+                            },
+                            _ => SlError::type_error(format!("'{name}' expects a (variable ...) header.")).at(span).exit(),
+                        };
+                        let Node::Identifier(varname, _) = &header[0] else { unreachable!() };
+                        let varname = self.convert_name(varname);
+
+                        if name == "each" {
+                            if header.len() != 2 {
+                                SlError::arity_error("'each' expects a (variable list) header.").at(span).exit();
+                            }
+                            let list = self.generate_node(header[1].clone());
+                            let prelude = format!("auto list1 = Value({list}).as_list();\n");
+                            ret.push_str(&self.generate_loop(&prelude, &format!("for (Value {varname} : list1)"), "", &args[1..]));
+                        } else {
+                            if !(3..=4).contains(&header.len()) {
+                                SlError::arity_error("'for' expects a (variable start end [step]) header.").at(span).exit();
                             }
+                            let start = self.generate_node(header[1].clone());
+                            let end = self.generate_node(header[2].clone());
+                            let step = match header.get(3) {
+                                Some(step) => self.generate_node(step.clone()),
+                                None => "1".to_string(),
+                            };
+                            let mut prelude = format!("int64_t start1 = Value({start}).as_int();\n");
+                            prelude.push_str(&format!("int64_t end1 = Value({end}).as_int();\n"));
+                            prelude.push_str(&format!("int64_t step1 = Value({step}).as_int();\n"));
+                            prelude.push_str("if (step1 == 0) {\nraise_error(\"value error\", \"'for' step can't be 0.\");\n}\n");
+                            let header = "for (int64_t i1 = start1; step1 > 0 ? i1 < end1 : i1 > end1; i1 += step1)";
+                            ret.push_str(&self.generate_loop(&prelude, header, &format!("Value {varname} = i1;\n"), &args[1..]));
                         }
-                        ret.push_str("}\nreturn ret1;\n}()");
                     },
+                    "break" => ret.push_str("break_loop()"),
+                    "continue" => ret.push_str("continue_loop()"),
                     "<" | ">" | "+" | "<=" | ">=" | "*" | "-" | "/" | "=" => {
                         let first = self.generate_node(args[0].clone());
                         ret.push_str(&format!("({}", first));
@@ -339,7 +444,7 @@ impl Generator {
                         ret.push_str(&format!("if ({}) {{\n", cond));
                         if let Node::Call{ name, .. } = &args[1] {
                             if name == "return" {
-                                ret.push_str(&format!("{truthy}"));
+                                ret.push_str(&format!("{truthy};\n"));
                             } else {
                                 ret.push_str(&format!("return {truthy};\n"));
                             }
@@ -349,7 +454,7 @@ impl Generator {
                         ret.push_str("} else {\n");
                         if let Node::Call{ name, .. } = &args[2] {
                             if name == "return" {
-                                ret.push_str(&format!("{falsy}"));
+                                ret.push_str(&format!("{falsy};\n"));
                             } else {
                                 ret.push_str(&format!("return {falsy};\n"));
                             }
@@ -359,9 +464,66 @@ impl Generator {
                         ret.push_str("}\n");
                         ret.push_str("}()");
                     },
+                    "and" | "or" => {
+                        // `&&` and `||` already skip the operands they don't need.
+                        let operands: Vec<_> = args.iter().map(|a| format!("Value({})", self.generate_node(a.clone()))).collect();
+                        if operands.is_empty() {
+                            ret.push_str(if name == "and" { "Value(true)" } else { "Value(false)" });
+                        } else {
+                            ret.push_str(&format!("Value({})", operands.join(if name == "and" { " && " } else { " || " })));
+                        }
+                    },
+                    "not" => {
+                        if args.len() != 1 {
+                            SlError::arity_error(format!("'not' expects 1 argument. Got {}.", args.len())).at(span).exit();
+                        }
+                        ret.push_str(&format!("Value(!Value({}))", self.generate_node(args[0].clone())));
+                    },
+                    "cond" | "when" | "unless" => {
+                        let clauses = match name.as_str() {
+                            "cond" => args.iter().map(|clause| match clause {
+                                Node::List(clause) if !clause.is_empty() => (clause[0].clone(), clause[1..].to_vec()),
+                                _ => SlError::type_error(format!("cond expects (condition body...) clauses. Got {}.", clause.type_name())).at(span).exit(),
+                            }).collect(),
+                            _ => {
+                                let Some((cond, body)) = args.split_first() else {
+                                    SlError::arity_error(format!("'{name}' expects at least 1 argument(s). Got 0.")).at(span).exit();
+                                };
+                                let cond = if name == "unless" {
+                                    Node::Call { name: "not".to_string(), args: vec![cond.clone()], span }
+                                } else {
+                                    cond.clone()
+                                };
+                                vec![(cond, body.to_vec())]
+                            },
+                        };
+
+                        self.depth += 1;
+                        ret.push_str("[&]() -> Value {\n");
+                        ret.push_str("Value ret1;\n");
+                        for (i, (cond, body)) in clauses.into_iter().enumerate() {
+                            if i > 0 {
+                                ret.push_str("else ");
+                            }
+                            match cond {
+                                Node::Identifier(id, _) if id == "else" => ret.push_str("{\n"),
+                                _ => ret.push_str(&format!("if (Value({})) {{\n", self.generate_node(cond))),
+                            }
+                            ret.push_str(&self.generate_block(&body));
+                            ret.push_str("}\n");
+                        }
+                        ret.push_str("return ret1;\n");
+                        ret.push_str("}()");
+                        self.depth -= 1;
+                    },
                     "return" => {
                         let val = self.generate_node(args[0].clone());
-                        ret.push_str(&format!("return {}", val));
+                        if self.loops > 0 {
+                            self.returns_from_loop = true;
+                            ret.push_str(&format!("return_value({})", val));
+                        } else {
+                            ret.push_str(&format!("return {}", val));
+                        }
                     },
                     "list" => {
                         ret.push_str(&format!("[={}]() mutable -> Value {{\n", if self.current_class.is_some() { ", this"} else { "" }));
@@ -373,24 +535,13 @@ impl Generator {
                         ret.push_str("}()");
                     },
                     "block" => {
+                        self.depth += 1;
                         ret.push_str(&format!("[={}]() mutable -> Value {{\n", if self.current_class.is_some() { ", this"} else { "" }));
                         ret.push_str("Value ret1;\n");
-                        let last = args.len() - 1;
-                        for (i, arg) in args.iter().enumerate() {
-                            if i == last {
-                                if let Node::Call{ name, args } = arg {
-                                    if name != "let" {
-                                        ret.push_str(&format!("ret1 = {};\n", self.generate_node(arg.clone())));
-                                    } else {
-                                        ret.push_str(&format!("{};\nret1 = {};\n", self.generate_node(arg.clone()), args[1]));
-                                    }
-                                }
-                            } else {
-                                ret.push_str(&format!("{};\n", self.generate_node(arg.clone())));
-                            }
-                        }
+                        ret.push_str(&self.generate_block(&args));
                         ret.push_str("return ret1;\n");
                         ret.push_str("}()");
+                        self.depth -= 1;
                     },
                     "dump" => {
                         ret.push_str(&format!("[={}]() mutable -> Value {{\n", if self.current_class.is_some() { ", this"} else { "" }));
@@ -398,7 +549,7 @@ impl Generator {
                         let mut cout = String::from("std::cout");
                         for (i, arg) in args.iter().enumerate() {
                             let output = match &arg {
-                                Node::Identifier(id) => self.convert_name(&id),
+                                Node::Identifier(id, _) => self.convert_name(&id),
                                 _ => self.generate_node(arg.clone()),
                             };
 
@@ -420,24 +571,115 @@ impl Generator {
                         }
                         ret.push_str(")");
                     },
-                    "switch" => {
+                    "match" => {
+                        self.destructures += 1;
+                        let id = self.destructures;
+
+                        self.depth += 1;
+                        ret.push_str("[&]() -> Value {\n");
+                        ret.push_str(&format!("Value match{id} = {};\n", self.generate_node(args[0].clone())));
+                        // A clause is left with `break` as soon as its
+                        // pattern or its guard fails.
+                        for clause in &args[1..] {
+                            let Some((pattern, guard, body)) = (match clause {
+                                Node::List(clause) => match_clause(clause),
+                                _ => None,
+                            }) else {
+                                SlError::type_error(format!("match expects (pattern body...) clauses. Got {}.", clause.type_name())).at(span).exit();
+                            };
+
+                            ret.push_str("do {\n");
+                            ret.push_str(&self.generate_pattern(pattern, &format!("match{id}"), span));
+                            if let Some(guard) = guard {
+                                ret.push_str(&format!("if (!Value({})) break;\n", self.generate_node(guard.clone())));
+                            }
+                            ret.push_str("Value ret1;\n");
+                            ret.push_str(&self.generate_block(body));
+                            ret.push_str("return ret1;\n");
+                            ret.push_str("} while (false);\n");
+                        }
+                        ret.push_str("return Value();\n");
+                        ret.push_str("}()");
+                        self.depth -= 1;
+                    },
+                    "apply" => {
+                        if args.len() < 2 {
+                            SlError::arity_error(format!("'apply' expects at least 2 argument(s). Got {}.", args.len())).at(span).exit();
+                        }
+
+                        let callee = match &args[0] {
+                            Node::Identifier(id, _) => self.callee(id),
+                            func => format!("Value({}).as_func()", self.generate_node(func.clone())),
+                        };
+
                         ret.push_str(&format!("[={}]() mutable -> Value {{\n", if self.current_class.is_some() { ", this"} else { "" }));
-                        let val = self.generate_node(args[0].clone());
-                        ret.push_str(&format!("Value test1 = {};\n", val));
+                        ret.push_str("std::vector<Value> args2;\n");
+                        let last = args.len() - 1;
+                        for arg in &args[1..last] {
+                            ret.push_str(&format!("args2.emplace_back({});\n", self.generate_node(arg.clone())));
+                        }
+                        ret.push_str(&format!("auto spread2 = Value({}).as_list();\n", self.generate_node(args[last].clone())));
+                        ret.push_str("args2.insert(args2.end(), spread2.begin(), spread2.end());\n");
+                        ret.push_str(&format!("return {callee}(args2);\n"));
+                        ret.push_str("}()");
+                    },
+                    "throw" => {
+                        ret.push_str(&format!("throw_value({})", self.generate_node(args[0].clone())));
+                    },
+                    "try" => {
+                        let mut body = &args[..];
+                        let mut finally = None;
+                        if let Some((Node::Call { name, args, .. }, rest)) = body.split_last() {
+                            if name == "finally" {
+                                finally = Some(args.clone());
+                                body = rest;
+                            }
+                        }
+                        let mut catch = None;
+                        if let Some((Node::Call { name, args, span }, rest)) = body.split_last() {
+                            if name == "catch" {
+                                let Some(Node::Identifier(varname, _)) = args.first() else {
+                                    SlError::type_error("'catch' expects an identifier.").at(*span).exit();
+                                };
+                                catch = Some((self.convert_name(varname), args[1..].to_vec()));
+                                body = rest;
+                            }
+                        }
 
-                        let count = args.len() - 1;
-                        for i in (1..count).step_by(2) {
-                            ret.push_str(&format!("if (test1 == {}) {{\n", args[i]));
-                            ret.push_str(&format!("return {};\n", args[i+1]));
+                        self.depth += 1;
+                        ret.push_str(&format!("[={}]() mutable -> Value {{\n", if self.current_class.is_some() { ", this"} else { "" }));
+                        ret.push_str("Value ret1;\n");
+                        if finally.is_some() {
+                            ret.push_str("try {\n");
+                        }
+                        if catch.is_some() {
+                            ret.push_str("try {\n");
+                        }
+                        ret.push_str(&self.generate_block(body));
+                        if let Some((varname, handler)) = catch {
+                            ret.push_str("} catch (...) {\n");
+                            ret.push_str(&format!("Value {varname} = caught_value(std::current_exception());\n"));
+                            ret.push_str(&self.generate_block(&handler));
+                            ret.push_str("}\n");
+                        }
+                        if let Some(finally) = finally {
+                            // Runs on the way out of both the normal and the
+                            // exceptional path.
+                            let cleanup: Vec<_> = finally.into_iter().map(|n| format!("{};\n", self.generate_node(n))).collect();
+                            ret.push_str("} catch (...) {\n");
+                            ret.push_str(&cleanup.concat());
+                            ret.push_str("throw;\n");
                             ret.push_str("}\n");
+                            ret.push_str(&format!("{{\n{}}}\n", cleanup.concat()));
                         }
-                        ret.push_str(&format!("return {};\n", args[count]));
+                        ret.push_str("return ret1;\n");
                         ret.push_str("}()");
+                        self.depth -= 1;
                     },
                     "load" => {
                         let filename = match &args[0] {
                             Node::String(s) => s.clone(),
-                            _ => panic!("load expects a string."),
+                            _ => SlError::type_error(format!("'load' expects a string. Got {}.", args[0].type_name())).at(span).exit(),
                         };
 
                         let filename = if filename.ends_with(".sl") {
@@ -461,12 +703,12 @@ impl Generator {
                         let mut header = String::new();
 
                         let name = match &args[0] {
-                            Node::Identifier(id) => id,
-                            _ => panic!("'class' only accept identifiers. Got {:?}", args[0]),
+                            Node::Identifier(id, _) => id,
+                            _ => SlError::type_error(format!("'class' only accept identifiers. Got {}.", args[0].type_name())).at(span).exit(),
                         };
 
                         let (parent_name, skip) = match &args[1] {
-                            Node::Identifier(id) => (id.clone(), 2),
+                            Node::Identifier(id, _) => (id.clone(), 2),
                             _ => ("SimpleListObject".to_string(), 1),
                         };
 
@@ -488,8 +730,8 @@ impl Generator {
                     },
                     "new" => {
                         let classname = match &args[0] {
-                            Node::Identifier(id) => id,
-                            _ => panic!("'new' only accept identifiers. Got {:?}.", args[0]),
+                            Node::Identifier(id, _) => id,
+                            _ => SlError::type_error(format!("'new' only accept identifiers. Got {}.", args[0].type_name())).at(span).exit(),
                         };
 
                         ret.push_str(&format!("Value(new {}(", classname));
@@ -502,6 +744,9 @@ impl Generator {
                         }
                         ret.push_str("))");
                     },
+                    "quote" | "quasiquote" | "unquote" | "unquote-splicing" => {
+                        SlError::value_error(format!("'{name}' is only supported by the interpreter.")).at(span).exit();
+                    },
                     "lt" => {
                         ret.push_str(&format!("{} < {}", self.generate_node(args[0].clone()), self.generate_node(args[1].clone())));
                     },
@@ -514,6 +759,15 @@ impl Generator {
                     "sub" => {
                         ret.push_str(&format!("{} - {}", self.generate_node(args[0].clone()), self.generate_node(args[1].clone())));
                     },
+                    // Lists are values, so the natives changing one change the
+                    // variable holding it.
+                    "push" | "pop" | "set-nth" | "insert" | "remove-at" | "nth"
+                        if matches!(args.first(), Some(Node::Identifier(..))) && (name != "nth" || args.len() == 3) => {
+                        let Node::Identifier(list, _) = &args[0] else { unreachable!() };
+                        let list = self.convert_name(list);
+                        let rest: Vec<_> = args[1..].iter().map(|a| format!("Value({})", self.generate_node(a.clone()))).collect();
+                        ret.push_str(&format!("update_list({list}, \"{name}\", {{ {} }})", rest.join(", ")));
+                    },
                     _ => {
                         if name == "super" {
                             let cur_meth = self.current_method.clone().unwrap();
@@ -522,25 +776,19 @@ impl Generator {
                             let parent_class_name = self.classes.get(&cur_cls).unwrap();
                             ret.push_str(&format!("{parent_class_name}::func_{converted_name}"));
                         } else {
-                            let converted_name = self.convert_name(&name);
-                            if self.functions_names.contains(&converted_name) || self.class_functions_names.contains(&converted_name) {
-                                if self.current_class.is_some() {
-                                    ret.push_str("::");
-                                }
-                                ret.push_str(&format!("func_{converted_name}"));
-                            } else {
-                                ret.push_str(&format!("{converted_name}.as_func()"));
-                            }
+                            ret.push_str(&self.callee(&name));
                         }
 
                         ret.push_str("({");
 
+                        let takes_functions = HIGHER_ORDER_NATIVES.contains(&name.as_str());
                         let mut is_first_arg = true;
                         for p in &args {
                             if !is_first_arg { ret.push_str(", "); }
                             is_first_arg = false;
 
-                            ret.push_str(&format!("Value({})", self.generate_node(p.clone())));
+                            let arg = if takes_functions { self.generate_value(p.clone()) } else { self.generate_node(p.clone()) };
+                            ret.push_str(&format!("Value({})", arg));
                         }
 
                         if args.len() < self.current_method_args.len() {
@@ -556,14 +804,30 @@ impl Generator {
                     },
                 };
             },
-            Node::Integer(i) => ret.push_str(&format!("{i}")),
-            Node::Float(f) => ret.push_str(&format!("{f}")),
-            Node::String(s) => ret.push_str(&format!("\"{s}\"s")),
-            Node::Identifier(id) => {
-                if id == "this" {
+            Node::Integer(i) => ret.push_str(&cpp_integer(i)),
+            Node::Float(f) => ret.push_str(&format!("{f:?}")),
+            Node::String(s) => ret.push_str(&format!("\"{}\"s", escape_string(&s))),
+            Node::List(items) => {
+                let items: Vec<_> = items.into_iter().map(|i| format!("Value({})", self.generate_node(i))).collect();
+                ret.push_str(&format!("Value(std::vector<Value> {{ {} }})", items.join(", ")));
+            },
+            Node::Map(entries) => {
+                let entries: Vec<_> = entries.into_iter()
+                    .map(|(k, v)| format!("{{ Value({}), Value({}) }}", self.generate_node(k), self.generate_node(v)))
+                    .collect();
+                ret.push_str(&format!("make_map({{ {} }})", entries.join(", ")));
+            },
+            Node::Identifier(id, _) => {
+                if let Some(keyword) = id.strip_prefix(':').filter(|k| !k.is_empty()) {
+                    ret.push_str(&format!("Value(Keyword {{ \"{}\"s }})", escape_string(keyword)));
+                } else if id == "this" {
                     ret.push_str("shared_from_this()");
+                } else if id == "true" || id == "false" {
+                    ret.push_str(&format!("Value({id})"));
+                } else if id == "null" {
+                    ret.push_str("Value()");
                 } else {
-                    ret.push_str(&format!("{id}"));
+                    ret.push_str(&self.convert_name(&id));
                 }
             }
             _ => panic!("{:?}", node),
@@ -572,13 +836,258 @@ impl Generator {
         ret
     }
 
+    /// Generates `nodes` as statements, the value of the last one going to
+    /// `ret1`.
+    fn generate_block(&mut self, nodes: &[Node]) -> String {
+        let mut output = String::new();
+        let last = nodes.len().saturating_sub(1);
+
+        for (i, node) in nodes.iter().enumerate() {
+            // The list a pattern `let` destructures is the first one it
+            // numbers.
+            let destructured = format!("destructure{}", self.destructures + 1);
+            let statement = self.generate_node(node.clone());
+            match node {
+                Node::Call { name, args, .. } if i == last && name == "let" && matches!(args[0], Node::List(_)) => {
+                    output.push_str(&format!("{statement};\nret1 = {destructured};\n"));
+                },
+                Node::Call { name, args, .. } if i == last && name == "let" && !matches!(args[0], Node::Map(_)) => {
+                    let Node::Identifier(varname, _) = &args[0] else { unreachable!() };
+                    output.push_str(&format!("{statement};\nret1 = {};\n", self.convert_name(varname)));
+                },
+                _ if i == last => output.push_str(&format!("ret1 = {statement};\n")),
+                _ => output.push_str(&format!("{statement};\n")),
+            }
+        }
+
+        output
+    }
+
+    /// Generates a loop as an immediately called lambda. `break` and
+    /// `continue` throw, and are caught around each iteration.
+    fn generate_loop(&mut self, prelude: &str, header: &str, binding: &str, body: &[Node]) -> String {
+        self.depth += 1;
+        self.loops += 1;
+        let mut output = String::from("[&]() -> Value {\n");
+        output.push_str(prelude);
+        output.push_str("Value ret1;\n");
+        output.push_str(&format!("{header} {{\n"));
+        output.push_str(binding);
+        output.push_str("try {\n");
+        output.push_str(&self.generate_block(body));
+        output.push_str("} catch (const ContinueLoop&) {\n");
+        output.push_str("} catch (const BreakLoop&) {\n");
+        output.push_str("break;\n");
+        output.push_str("}\n");
+        output.push_str("}\n");
+        output.push_str("return ret1;\n");
+        output.push_str("}()");
+        self.loops -= 1;
+        self.depth -= 1;
+
+        output
+    }
+
+    /// Statements binding `value` to `pattern`, a name or a list pattern
+    /// such as `[x y & rest]`. Names are declared unless they already are,
+    /// as globals are.
+    fn destructure(&mut self, pattern: &Node, value: &str, declare: bool, span: Span) -> Vec<String> {
+        let declaration = if declare { "Value " } else { "" };
+        match pattern {
+            Node::Identifier(name, _) if name == "_" => vec![],
+            Node::Identifier(name, _) => vec![format!("{declaration}{} = {value}", self.convert_name(name))],
+            Node::List(patterns) => {
+                self.destructures += 1;
+                let id = self.destructures;
+                let (patterns, rest) = match patterns.iter().position(|p| matches!(p, Node::Identifier(id, _) if id == "&")) {
+                    Some(i) if i + 2 == patterns.len() => (&patterns[..i], Some(&patterns[i + 1])),
+                    Some(_) => SlError::value_error("`&` must be followed by a single pattern.").at(span).exit(),
+                    None => (&patterns[..], None),
+                };
+
+                let mut statements = vec![
+                    format!("Value destructure{id} = {value}"),
+                    format!("auto& list{id} = destructure(destructure{id}, {})", patterns.len()),
+                ];
+                for (i, pattern) in patterns.iter().enumerate() {
+                    statements.extend(self.destructure(pattern, &format!("list{id}[{i}]"), declare, span));
+                }
+                if let Some(rest) = rest {
+                    let rest_value = format!("Value(std::vector<Value>(list{id}.begin() + {}, list{id}.end()))", patterns.len());
+                    statements.extend(self.destructure(rest, &rest_value, declare, span));
+                }
+
+                statements
+            },
+            _ => SlError::type_error(format!("'let' expects an identifier or a list pattern. Got {}.", pattern.type_name())).at(span).exit(),
+        }
+    }
+
+    /// Statements checking that `value` matches `pattern`, leaving the
+    /// enclosing loop with `break` when it doesn't, and declaring the names
+    /// it binds.
+    fn generate_pattern(&mut self, pattern: &Node, value: &str, span: Span) -> String {
+        match pattern {
+            Node::Identifier(name, _) if name == "_" => String::new(),
+            Node::Identifier(name, _) if matches!(name.as_str(), "true" | "false" | "null") => {
+                format!("if (!({value} == {})) break;\n", self.generate_node(pattern.clone()))
+            },
+            Node::Identifier(name, _) => format!("Value {} = {value};\n", self.convert_name(name)),
+            Node::Integer(_) | Node::Float(_) | Node::String(_) => {
+                format!("if (!({value} == Value({}))) break;\n", self.generate_node(pattern.clone()))
+            },
+            Node::List(patterns) => {
+                self.destructures += 1;
+                let id = self.destructures;
+                let (patterns, rest) = match patterns.iter().position(|p| matches!(p, Node::Identifier(id, _) if id == "&")) {
+                    Some(i) if i + 2 == patterns.len() => (&patterns[..i], Some(&patterns[i + 1])),
+                    Some(_) => SlError::value_error("`&` must be followed by a single pattern.").at(span).exit(),
+                    None => (&patterns[..], None),
+                };
+
+                let mut output = format!("if (!{value}.is_list()) break;\n");
+                output.push_str(&format!("auto& list{id} = {value}.as_list();\n"));
+                let size_check = if rest.is_some() { "<" } else { "!=" };
+                output.push_str(&format!("if (list{id}.size() {size_check} {}) break;\n", patterns.len()));
+                for (i, pattern) in patterns.iter().enumerate() {
+                    output.push_str(&self.generate_pattern(pattern, &format!("list{id}[{i}]"), span));
+                }
+                if let Some(rest) = rest {
+                    output.push_str(&format!("Value rest{id} = std::vector<Value>(list{id}.begin() + {}, list{id}.end());\n", patterns.len()));
+                    output.push_str(&self.generate_pattern(rest, &format!("rest{id}"), span));
+                }
+
+                output
+            },
+            Node::Call { name: classname, args, .. } if self.classes.contains_key(classname) => {
+                self.destructures += 1;
+                let id = self.destructures;
+
+                let mut output = format!("auto* object{id} = {value}.is_instance() ? dynamic_cast<{classname}*>({value}.as_instance()) : nullptr;\n");
+                output.push_str(&format!("if (!object{id}) break;\n"));
+                let mut args = args.iter();
+                while let Some(arg) = args.next() {
+                    let (field, pattern) = match arg {
+                        Node::Identifier(id, _) if id.starts_with(':') => match args.next() {
+                            Some(pattern) => (id[1..].to_string(), pattern),
+                            None => SlError::value_error(format!("Keyword '{id}' has no pattern.")).at(span).exit(),
+                        },
+                        Node::Identifier(id, _) => (id.clone(), arg),
+                        _ => SlError::type_error(format!("'{classname}' expects an identifier. Got {}.", arg.type_name())).at(span).exit(),
+                    };
+                    let field = format!("object{id}->{}", self.convert_name(&field));
+                    output.push_str(&self.generate_pattern(pattern, &field, span));
+                }
+
+                output
+            },
+            Node::Call { name, .. } => SlError::name_error(format!("Unknown class: {name}")).at(span).exit(),
+            _ => SlError::type_error(format!("A {} can't be used as a pattern.", pattern.type_name())).at(span).exit(),
+        }
+    }
+
+    /// Generates a value, where the name of a function stands for the
+    /// function itself, as in `(let show print)`.
+    fn generate_value(&mut self, node: Node) -> String {
+        if let Node::Identifier(id, _) = &node {
+            let converted_name = self.convert_name(id);
+            if self.functions_names.contains(&converted_name) {
+                return format!("Value(Value::Function({}))", self.callee(id));
+            }
+        }
+
+        self.generate_node(node)
+    }
+
+    /// The C++ expression to call to run the function `name`.
+    fn callee(&mut self, name: &str) -> String {
+        let converted_name = self.convert_name(name);
+        if self.functions_names.contains(&converted_name) || self.class_functions_names.contains(&converted_name) {
+            if self.current_class.is_some() {
+                format!("::func_{converted_name}")
+            } else {
+                format!("func_{converted_name}")
+            }
+        } else {
+            format!("{converted_name}.as_func()")
+        }
+    }
+
+    /// Translates a name to a C++ identifier. `-` becomes `_`, and every
+    /// other character that isn't a letter or a digit, `_` included, is
+    /// written as `$hex$`, so that two names never translate to the same
+    /// identifier. A leading digit, or the first letter of a C++ keyword, is
+    /// escaped the same way.
     fn convert_name(&mut self, name: &str) -> String {
-        let prev = name;
-        let name = str::replace(name, "-", "_");
-        let name = str::replace(&name, "+", "plus_");
-        let name = str::replace(&name, "*", "times_");
-        let name = str::replace(&name, "/", "divide_");
-        self.converted_names.insert(name.to_string(), prev.to_string());
-        name
+        let mut converted = String::new();
+        for (i, c) in name.chars().enumerate() {
+            let escaped = i == 0 && (c.is_ascii_digit() || CPP_KEYWORDS.contains(&name.replace('-', "_").as_str()));
+            match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' if !escaped => converted.push(c),
+                '-' => converted.push('_'),
+                _ => converted.push_str(&format!("${:x}$", c as u32)),
+            }
+        }
+
+        self.converted_names.insert(converted.clone(), name.to_string());
+        converted
+    }
+}
+
+const CPP_KEYWORDS: [&str; 92] = [
+    "alignas", "alignof", "and", "and_eq", "asm", "auto", "bitand", "bitor", "bool", "break",
+    "case", "catch", "char", "char8_t", "char16_t", "char32_t", "class", "compl", "concept", "const",
+    "consteval", "constexpr", "constinit", "const_cast", "continue", "co_await", "co_return", "co_yield", "decltype", "default",
+    "delete", "do", "double", "dynamic_cast", "else", "enum", "explicit", "export", "extern", "false",
+    "float", "for", "friend", "goto", "if", "inline", "int", "long", "mutable", "namespace",
+    "new", "noexcept", "not", "not_eq", "nullptr", "operator", "or", "or_eq", "private", "protected",
+    "public", "register", "reinterpret_cast", "requires", "return", "short", "signed", "sizeof", "static", "static_assert",
+    "static_cast", "struct", "switch", "template", "this", "thread_local", "throw", "true", "try", "typedef",
+    "typeid", "typename", "union", "unsigned", "using", "virtual", "void", "volatile", "wchar_t", "while",
+    "xor", "xor_eq",
+];
+
+/// Names a `let` pattern binds.
+fn pattern_names(pattern: &Node) -> Vec<String> {
+    match pattern {
+        Node::Identifier(name, _) if name == "_" || name == "&" => vec![],
+        Node::Identifier(name, _) => vec![name.clone()],
+        Node::List(patterns) => patterns.iter().flat_map(pattern_names).collect(),
+        _ => vec![],
+    }
+}
+
+/// Escapes a string for a C++ string literal. The assembler behind QBE reads
+/// the same escapes.
+pub fn escape_string(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            ' '..='~' => escaped.push(c),
+            // Octal escapes stop after three digits, unlike `\x`.
+            _ => {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    escaped.push_str(&format!("\\{byte:03o}"));
+                }
+            },
+        }
+    }
+    escaped
+}
+
+/// Spells an integer literal for g++ as an `int64_t` so that constant
+/// arithmetic does not overflow an `int`. `i64::MIN` has no literal and is
+/// spelled `INT64_MIN`.
+pub fn cpp_integer(i: i64) -> String {
+    if i == i64::MIN {
+        "INT64_MIN".to_string()
+    } else {
+        format!("int64_t{{{i}}}")
     }
 }
@@ -1,11 +1,15 @@
 use std::io::Write;
 use std::path::Path;
-use crate::parser::*;
-use crate::Node;
+use crate::diagnostics::parse_source;
+use crate::error::*;
+use crate::span::*;
+use crate::{match_clause, Env, Node, Param};
 use std::fs;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::path::PathBuf;
-use pest::Parser;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use pest_derive::Parser;
 use rand::Rng;
 
@@ -13,6 +17,13 @@ use rand::Rng;
 #[grammar = "simple-lisp.pest"]
 pub struct SimpleLispParser;
 
+pub type NativeFunction = Box<dyn Fn(Vec<Node>) -> SlResult<Node>>;
+pub type Natives = HashMap<String, NativeFunction>;
+/// A native calling back into the script, such as `map` with a lambda,
+/// through `Visitor::call_function`.
+pub type CallingNative = fn(&mut Visitor, Vec<Node>) -> SlResult<Node>;
+pub type CallingNatives = HashMap<String, CallingNative>;
+
 #[derive(Clone)]
 struct Class {
     parent: Option<String>,
@@ -21,11 +32,16 @@ struct Class {
 }
 
 #[derive(Debug)]
-struct Scope {
+pub struct Scope {
     functions: HashMap<String, Node>,
     variables: HashMap<String, Node>,
 }
 
+/// Scopes are shared between the call stack and the closures created in
+/// them, so a closure keeps its defining environment alive and sees the
+/// updates made to it.
+pub type ScopeRef = Rc<RefCell<Scope>>;
+
 impl Scope {
     fn new() -> Self {
         Self {
@@ -33,50 +49,79 @@ impl Scope {
             variables: HashMap::new(),
         }
     }
+
+    fn shared(self) -> ScopeRef {
+        Rc::new(RefCell::new(self))
+    }
+}
+
+/// How evaluation leaves its normal flow, set by `return`, `break` and
+/// `continue` and handled by the function or the loop they jump out of.
+#[derive(Debug)]
+enum Control {
+    Return(Node),
+    Break,
+    Continue,
 }
 
 pub struct Visitor {
-    scopes: Vec<Scope>,
-    natives: HashMap<String, Box<dyn Fn(Vec<Node>) -> Node>>,
+    scopes: Vec<ScopeRef>,
+    natives: Natives,
+    calling_natives: CallingNatives,
     classes: HashMap<String, Class>,
-    return_value: Option<Node>,
+    control: Option<Control>,
+    /// Set while evaluating the expression a function returns the value of.
+    tail_position: bool,
+    /// Call made in tail position, run by `execute_function` once the
+    /// current frame is gone: the function and the scope of its arguments.
+    tail_call: Option<(Node, Scope)>,
+    /// Value of the `throw` being unwound.
+    thrown: Option<Node>,
     paths: Vec<String>,
     libs: Vec<libloading::Library>,
 }
 
 impl Visitor {
     pub fn new() -> Self {
-        let mut natives = HashMap::<String, Box::<dyn Fn(Vec<Node>) -> Node>>::new();
+        let mut natives = Natives::new();
 
         load_io_module(&mut natives);
         load_maths_module(&mut natives);
         load_list_module(&mut natives);
+        load_map_module(&mut natives);
+        load_string_module(&mut natives);
         load_type_module(&mut natives);
 
+        let mut calling_natives = CallingNatives::new();
+        load_functional_module(&mut calling_natives);
+
         let mut root = Scope::new();
         root.variables.insert("null".to_string(), Node::Null);
         root.variables.insert("true".to_string(), Node::Bool(true));
         root.variables.insert("false".to_string(), Node::Bool(false));
 
         Self {
-            scopes: vec![root],
-            natives: natives,
+            scopes: vec![root.shared()],
+            natives,
+            calling_natives,
             classes: HashMap::new(),
-            return_value: None,
+            control: None,
+            tail_position: false,
+            tail_call: None,
+            thrown: None,
             paths: vec![std::env::current_dir().unwrap().as_path().to_str().unwrap().to_string()],
             libs: vec![],
         }
     }
 
-    pub fn interpret(&mut self, filename: &str) -> Node {
+    pub fn interpret(&mut self, filename: &str) -> SlResult<Node> {
         let cur_path = self.paths.last().unwrap().clone();
-        let filename = if filename.starts_with("/") {
+        let filename = if filename.starts_with('/') {
             PathBuf::from(&filename)
         } else {
             PathBuf::from(&format!("{}/{}", cur_path, filename))
         };
         let path = filename.parent().unwrap().to_str().unwrap().to_string();
-        self.paths.push(path);
 
         let filename = filename.to_str().unwrap().to_string();
         let filename = if filename.ends_with(".sl") {
@@ -85,229 +130,477 @@ impl Visitor {
             format!("{}.sl", filename)
         };
 
-        let data = fs::read_to_string(&filename).unwrap();
-        let res = SimpleLispParser::parse(Rule::file, &data);
-
-        let ast = match res {
-            Ok(pairs) => parse_block(pairs).unwrap(),
-            Err(e) => panic!("Can't parse {}:\n{:?}", filename, e),
+        let data = match fs::read_to_string(&filename) {
+            Ok(data) => data,
+            Err(e) => return Err(SlError::io_error(format!("Can't read {}: {}", filename, e))),
+        };
+        let ast = match parse_source(&filename, &data) {
+            Ok(nodes) => nodes,
+            Err(diagnostics) => return Err(SlError::from_diagnostics(ErrorKind::Parse, diagnostics)),
         };
 
-        self.scopes.push(Scope::new());
+        self.paths.push(path);
+        self.scopes.push(Scope::new().shared());
         let ret = self.evaluate_block(ast);
         self.scopes.pop();
-        self.return_value = None;
+        let control = self.leave_frame();
 
         self.paths.pop();
 
+        control?;
+        ret
+    }
+
+    /// Defines the function of a `defmacro`, under a name no script can
+    /// call, for `expand_macro`.
+    pub fn define_macro(&mut self, function: &Node) -> SlResult<()> {
+        let Node::Function { name, params, body, env, span } = function else {
+            return Err(SlError::type_error(format!("'defmacro' expects a function. Got {}.", function.type_name())));
+        };
+
+        let function = Node::Function {
+            name: macro_name(name),
+            params: params.clone(),
+            body: body.clone(),
+            env: env.clone(),
+            span: *span,
+        };
+        self.evaluate_node(&function)?;
+
+        Ok(())
+    }
+
+    /// Runs the macro `name` on its unevaluated arguments, as data, and
+    /// gives back the code it expands to, also as data.
+    pub fn expand_macro(&mut self, name: &str, args: Vec<Node>) -> SlResult<Node> {
+        let call = Node::Call {
+            name: macro_name(name),
+            args: args.into_iter().map(quoted).collect(),
+            span: Span::default(),
+        };
+        let ret = self.evaluate_node(&call);
+        self.leave_frame()?;
+
+        ret
+    }
+
+    /// Calls a function value, a lambda or a native, with arguments already
+    /// evaluated.
+    pub fn call_function(&mut self, function: &Node, args: Vec<Node>) -> SlResult<Node> {
+        let Node::Function { name, .. } = function else {
+            return Err(SlError::type_error(format!("Can't call a {}.", function.type_name())));
+        };
+        if let Some(native) = name.strip_prefix("native#") {
+            return self.call_native(native, args);
+        }
+
+        self.insert_functions("lambda#1", function.clone());
+        let args = args.into_iter().map(quoted).collect();
+        let ret = self.execute_function("lambda#1", &args);
+        self.scopes.last().unwrap().borrow_mut().functions.remove("lambda#1");
+
         ret
     }
 
-    fn evaluate_block(&mut self, nodes: Vec<Node>) -> Node {
+    fn is_native(&self, name: &str) -> bool {
+        self.natives.contains_key(name) || self.calling_natives.contains_key(name)
+    }
+
+    fn is_method(&self, name: &str) -> bool {
+        self.classes.values().any(|class| class.functions.contains_key(name))
+    }
+
+    /// Whether a user function hides the native of the same name. Methods
+    /// don't, they are only used when called on an instance.
+    fn shadows_native(&self, name: &str) -> bool {
+        !self.is_method(name) && self.find_function(name).is_some()
+    }
+
+    fn call_native(&mut self, name: &str, args: Vec<Node>) -> SlResult<Node> {
+        match self.calling_natives.get(name) {
+            Some(native) => native(self, args),
+            None => self.natives[name](args),
+        }
+    }
+
+    fn evaluate_block(&mut self, nodes: Vec<Node>) -> SlResult<Node> {
         let mut ret = Node::Null;
 
         for node in nodes {
-            ret = self.evaluate_node(&node);
+            ret = self.evaluate_node(&node)?;
 
-            if let Some(val) = &self.return_value {
-                ret = val.clone();
-                break;
+            match &self.control {
+                Some(Control::Return(val)) => {
+                    ret = val.clone();
+                    break;
+                },
+                Some(_) => break,
+                None => {},
             }
         }
 
-        ret
+        Ok(ret)
+    }
+
+    /// Evaluates the body of a function, its last form in tail position.
+    fn evaluate_body(&mut self, nodes: Vec<Node>) -> SlResult<Node> {
+        self.evaluate_forms(&nodes, true)
+    }
+
+    /// Evaluates `nodes` in order, the last one in tail position if `tail`
+    /// is set.
+    fn evaluate_forms(&mut self, nodes: &[Node], tail: bool) -> SlResult<Node> {
+        let Some((last, nodes)) = nodes.split_last() else {
+            return Ok(Node::Null);
+        };
+
+        let ret = self.evaluate_block(nodes.to_vec())?;
+        if self.control.is_some() {
+            return Ok(ret);
+        }
+
+        self.evaluate_branch(last, tail)
+    }
+
+    fn evaluate_condition(&mut self, form: &str, node: &Node) -> SlResult<bool> {
+        match self.evaluate_node(node)? {
+            Node::Bool(b) => Ok(b),
+            cond => Err(SlError::type_error(format!("{form} condition isn't a bool. Got '{}'", cond.type_name()))),
+        }
     }
 
-    fn evaluate_node(&mut self, node: &Node) -> Node {
-        let cpy = node.clone();
+    /// Evaluates a node, in tail position if `tail` is set.
+    fn evaluate_branch(&mut self, node: &Node, tail: bool) -> SlResult<Node> {
+        self.tail_position = tail && matches!(node, Node::Call { .. });
+        self.evaluate_node(node)
+    }
 
+    fn evaluate_node(&mut self, node: &Node) -> SlResult<Node> {
         match node {
             Node::Function { name, .. } => {
-                let ret = cpy.clone();
-
-                if name.len() > 0 {
-                    self.insert_functions(&name, cpy);
+                let closure = self.capture(node);
+                if !name.is_empty() {
+                    self.insert_functions(name, closure.clone());
                 }
 
-                ret
+                Ok(closure)
             },
-            Node::Call { name, args } => {
-                self.evaluate_call(name, args)
+            Node::Call { name, args, span } => {
+                self.evaluate_call(name, args).map_err(|e| e.at(*span))
             },
-            Node::Identifier(s) => {
-                let variable = self.find_variable(&s);
-                if let Some(v) = variable {
-                    v
-                } else if self.natives.contains_key(s) {
-                    Node::Function { name: "<native#1>".to_string(), params: vec![], body: vec![], }
+            Node::Identifier(s, span) => {
+                if let Some(v) = self.find_variable(s) {
+                    Ok(v)
+                } else if self.is_native(s) && !self.shadows_native(s) {
+                    Ok(Node::Function { name: native_name(s), params: vec![], body: vec![], env: Env::default(), span: Span::default() })
+                } else if let Some(func) = self.find_function(s) {
+                    Ok(func)
                 } else {
-                    if let Some(func) = self.find_function(&s) {
-                        func
-                    } else {
-                        panic!("Unknown variable or function: {s}");
-                    }
+                    Err(SlError::name_error(format!("Unknown variable or function: {s}")).at(*span))
+                }
+            },
+            Node::List(items) => Ok(Node::List(self.evaluate_list(items)?)),
+            Node::Map(entries) => {
+                // A key given twice keeps its first position and its last
+                // value.
+                let mut values = vec![];
+                for (key, value) in entries {
+                    let key = self.evaluate_node(key)?;
+                    let value = self.evaluate_node(value)?;
+                    map_insert(&mut values, key, value);
                 }
+
+                Ok(Node::Map(values))
             },
-            Node::Integer(i) => Node::Integer(*i),
-            Node::String(s) => Node::String(s.clone()),
-            Node::Float(f) => Node::Float(*f),
-            _ => panic!("{node}"),
+            _ => Ok(node.clone()),
         }
     }
 
-    fn evaluate_call(&mut self, name: &str, args: &Vec<Node>) -> Node {
+    fn evaluate_call(&mut self, name: &str, args: &Vec<Node>) -> SlResult<Node> {
+        let tail = std::mem::take(&mut self.tail_position);
+
         match name {
             "let" => {
-                let varname = match args[0].clone() {
-                    Node::Identifier(s) => s,
-                    _ => panic!("{}", args[0]),
-                };
-                let last_scope = self.scopes.last().unwrap();
-                if last_scope.variables.contains_key(&varname) {
-                    panic!("Variable '{varname}' already exists in that scope.");
+                if let Some(Node::Map(bindings)) = args.first() {
+                    // Each binding sees the ones before it.
+                    self.scopes.push(Scope::new().shared());
+                    let ret = bindings.iter()
+                        .try_for_each(|(pattern, value)| {
+                            let value = self.evaluate_node(value)?;
+                            self.bind_pattern(pattern, value)
+                        })
+                        .and_then(|_| self.evaluate_forms(&args[1..], tail));
+                    self.scopes.pop();
+
+                    return ret;
                 }
 
-                let ret = self.evaluate_node(&args[1]);
+                expect_args(name, args, 2)?;
+                let ret = self.evaluate_node(&args[1])?;
+                self.bind_pattern(&args[0], ret.clone())?;
 
-                let last_scope = self.scopes.last_mut().unwrap();
-                last_scope.variables.insert(varname, ret.clone());
-
-                ret
+                Ok(ret)
             },
-            "list" => {
-                let args = self.evaluate_list(args);
-
-                Node::List(args)
+            "quote" => {
+                expect_args(name, args, 1)?;
+                Ok(args[0].clone())
             },
-            "block" => {
-                let mut ret = Node::Null;
+            "quasiquote" => {
+                expect_args(name, args, 1)?;
+                self.expand_quasiquote(&args[0])
+            },
+            "unquote" | "unquote-splicing" => {
+                Err(SlError::value_error(format!("'{name}' can only be used inside a quasiquote.")))
+            },
+            "gensym" => {
+                // `#` can't appear in identifiers, so the symbol can't clash
+                // with a name from the source.
+                let prefix = match args.first() {
+                    Some(arg) => match self.evaluate_node(arg)? {
+                        Node::String(s) | Node::Symbol(s) => s,
+                        other => return Err(SlError::type_error(format!("'gensym' expects a string. Got {}.", other.type_name()))),
+                    },
+                    None => "g".to_string(),
+                };
 
-                for arg in args {
-                    ret = self.evaluate_node(&arg);
-                }
+                Ok(Node::Symbol(format!("{prefix}#{}", GENSYMS.fetch_add(1, Ordering::Relaxed) + 1)))
+            },
+            "list" => {
+                let args = self.evaluate_list(args)?;
 
-                ret
+                Ok(Node::List(args))
             },
+            "block" => self.evaluate_forms(args, tail),
             "set" => {
-                let varname = match args[0].clone() {
-                    Node::Identifier(s) => s,
-                    _ => panic!("{}", args[0]),
-                };
-                
+                expect_args(name, args, 2)?;
+                let varname = expect_identifier(name, &args[0])?;
+
                 if self.find_variable(&varname).is_none() {
-                    panic!("Variable '{varname}' does not exist in that scope.");
+                    return Err(SlError::name_error(format!("Variable '{varname}' does not exist in that scope.")));
                 }
 
-                let ret = self.evaluate_node(&args[1]);
+                let ret = self.evaluate_node(&args[1])?;
 
                 self.update_variable(&varname, ret.clone());
 
-                ret
+                Ok(ret)
             },
             "if" => {
-                let cond = self.evaluate_node(&args[0]);
+                expect_args(name, args, 2)?;
+                let cond = self.evaluate_node(&args[0])?;
 
                 match cond {
                     Node::Bool(b) => {
-                        let ret;
-
-                        self.scopes.push(Scope::new());
-                        if b {
-                            ret = self.evaluate_node(&args[1]);
+                        self.scopes.push(Scope::new().shared());
+                        let ret = if b {
+                            self.evaluate_branch(&args[1], tail)
                         } else if args.len() > 2 {
-                            ret = self.evaluate_node(&args[2]);
+                            self.evaluate_branch(&args[2], tail)
                         } else {
-                            ret = Node::Null;
-                        }
+                            Ok(Node::Null)
+                        };
                         self.scopes.pop();
 
                         ret
                     },
-                    _ => panic!("if condition isn't a bool. Got '{}'", args[0]),
+                    _ => Err(SlError::type_error(format!("if condition isn't a bool. Got '{}'", cond.type_name()))),
                 }
             },
-            "while" => {
-                let mut ret = Node::Null;
-                let mut continue_loop = true;
-
-                while continue_loop {
-                    let cond = self.evaluate_node(&args[0]);
-                    match cond {
-                        Node::Bool(b) => {
-                            if b {
-                                self.scopes.push(Scope::new());
-                                
-                                ret = self.evaluate_block(args.clone());
+            "and" | "or" => {
+                // Stops at the first argument that decides the result.
+                let decisive = name == "or";
+                for arg in args {
+                    if self.evaluate_condition(name, arg)? == decisive {
+                        return Ok(Node::Bool(decisive));
+                    }
+                }
 
-                                self.scopes.pop();
-                            }
+                Ok(Node::Bool(!decisive))
+            },
+            "not" => {
+                if args.len() != 1 {
+                    return Err(SlError::arity_error(format!("'not' expects 1 argument. Got {}.", args.len())));
+                }
 
-                            if self.return_value.is_some() {
-                                continue_loop = false;
-                            } else {
-                                continue_loop = b;
-                            }
-                        },
-                        _ => panic!("if condition isn't a bool. Got '{}'", args[0]),
-                    };
+                Ok(Node::Bool(!self.evaluate_condition(name, &args[0])?))
+            },
+            "when" | "unless" => {
+                expect_args(name, args, 1)?;
+                if self.evaluate_condition(name, &args[0])? != (name == "when") {
+                    return Ok(Node::Null);
                 }
 
+                self.scopes.push(Scope::new().shared());
+                let ret = self.evaluate_forms(&args[1..], tail);
+                self.scopes.pop();
+
                 ret
             },
-            "switch" => {
-                let mut ret = Node::Null;
-                let mut continue_loop = true;
-                let var = self.evaluate_node(&args[0]);
+            "cond" => {
+                for clause in args {
+                    let Node::List(clause) = clause else {
+                        return Err(SlError::type_error(format!("cond expects (condition body...) clauses. Got {}.", clause.type_name())));
+                    };
+                    let Some((cond, body)) = clause.split_first() else {
+                        return Err(SlError::arity_error("cond clauses can't be empty."));
+                    };
 
-                for i in 1..(args.len() - 1) {
-                    let Node::Call { name, args: list } = &args[i] else {
-                        panic!("switch expects case statements. Got {:?}.", args[i]);
+                    let matched = match cond {
+                        Node::Identifier(id, _) if id == "else" => true,
+                        _ => self.evaluate_condition(name, cond)?,
                     };
+                    if matched {
+                        self.scopes.push(Scope::new().shared());
+                        let ret = self.evaluate_forms(body, tail);
+                        self.scopes.pop();
 
-                    if name != "case" {
-                        panic!("switch expects case statements. Got {:?}.", name);
+                        return ret;
                     }
+                }
 
-                    if list.len() != 2 {
-                        panic!("switch expects lists of 2 elements. Got {} elements.", list.len());
-                    }
+                Ok(Node::Null)
+            },
+            "while" => {
+                expect_args(name, args, 1)?;
+                let mut ret = Node::Null;
 
-                    let value = self.evaluate_node(&list[0]);
+                loop {
+                    let cond = self.evaluate_node(&args[0])?;
+                    match cond {
+                        Node::Bool(true) => {
+                            let (value, go_on) = self.evaluate_iteration(Scope::new(), &args[1..])?;
+                            ret = value;
+                            if !go_on {
+                                break;
+                            }
+                        },
+                        Node::Bool(false) => break,
+                        _ => return Err(SlError::type_error(format!("while condition isn't a bool. Got '{}'", cond.type_name()))),
+                    };
+                }
 
-                    let are_equals = self.check_equality(&var, &value);
+                Ok(ret)
+            },
+            "each" => {
+                expect_args(name, args, 1)?;
+                let Node::Call { name: varname, args: list, .. } = &args[0] else {
+                    return Err(SlError::type_error(format!("'each' expects a (name list) binding. Got {}.", args[0].type_name())));
+                };
+                expect_args("each", list, 1)?;
 
-                    if are_equals {
-                        ret = self.evaluate_node(&list[1]);
-                        continue_loop = false;
-                    }
+                let items = match self.evaluate_node(&list[0])? {
+                    Node::List(items) => items,
+                    other => return Err(SlError::type_error(format!("'each' only iterates over lists. Got {}.", other.type_name()))),
+                };
 
-                    if !continue_loop {
+                let mut ret = Node::Null;
+                for item in items {
+                    let mut scope = Scope::new();
+                    scope.variables.insert(varname.clone(), item);
+                    let (value, go_on) = self.evaluate_iteration(scope, &args[1..])?;
+                    ret = value;
+                    if !go_on {
                         break;
                     }
                 }
 
-                if continue_loop {
-                    ret = args.last().unwrap().clone();
+                Ok(ret)
+            },
+            "for" => {
+                expect_args(name, args, 1)?;
+                let Node::Call { name: varname, args: bounds, .. } = &args[0] else {
+                    return Err(SlError::type_error(format!("'for' expects a (name start end) binding. Got {}.", args[0].type_name())));
+                };
+                expect_args("for", bounds, 2)?;
+
+                let mut bounds = self.evaluate_list(bounds)?.into_iter().map(|bound| match bound {
+                    Node::Integer(i) => Ok(i),
+                    _ => Err(SlError::type_error(format!("'for' bounds must be integers. Got {}.", bound.type_name()))),
+                });
+                let start = bounds.next().unwrap()?;
+                let end = bounds.next().unwrap()?;
+                let step = bounds.next().transpose()?.unwrap_or(1);
+                if step == 0 {
+                    return Err(SlError::value_error("'for' step can't be 0."));
+                }
+
+                let mut ret = Node::Null;
+                let mut i = start;
+                while (step > 0 && i < end) || (step < 0 && i > end) {
+                    let mut scope = Scope::new();
+                    scope.variables.insert(varname.clone(), Node::Integer(i));
+                    let (value, go_on) = self.evaluate_iteration(scope, &args[1..])?;
+                    ret = value;
+                    let Some(next) = i.checked_add(step).filter(|_| go_on) else {
+                        break;
+                    };
+                    i = next;
                 }
 
-                ret
+                Ok(ret)
+            },
+            "break" => {
+                self.control = Some(Control::Break);
+                Ok(Node::Null)
+            },
+            "continue" => {
+                self.control = Some(Control::Continue);
+                Ok(Node::Null)
+            },
+            "match" => {
+                expect_args(name, args, 1)?;
+                let value = self.evaluate_node(&args[0])?;
+
+                for clause in &args[1..] {
+                    let Some((pattern, guard, body)) = (match clause {
+                        Node::List(clause) => match_clause(clause),
+                        _ => None,
+                    }) else {
+                        return Err(SlError::type_error(format!("match expects (pattern body...) clauses. Got {}.", clause.type_name())));
+                    };
+
+                    self.scopes.push(Scope::new().shared());
+                    let matched = self.match_pattern(pattern, &value)
+                        .and_then(|matched| match guard {
+                            Some(guard) if matched => self.evaluate_condition("match guard", guard),
+                            _ => Ok(matched),
+                        });
+                    let ret = match matched {
+                        Ok(true) => Some(self.evaluate_forms(body, tail)),
+                        Ok(false) => None,
+                        Err(e) => Some(Err(e)),
+                    };
+                    self.scopes.pop();
+
+                    if let Some(ret) = ret {
+                        return ret;
+                    }
+                }
+
+                Ok(Node::Null)
             },
             "return" => {
-                let ret = self.evaluate_node(&args[0]);
-                self.return_value = Some(ret.clone());
-                ret
+                expect_args(name, args, 1)?;
+                let ret = self.evaluate_branch(&args[0], tail)?;
+                self.control = Some(Control::Return(ret.clone()));
+                Ok(ret)
             },
             "dump" => {
-                let ret = self.evaluate_node(&args[0]);
+                expect_args(name, args, 1)?;
+                let ret = self.evaluate_node(&args[0])?;
                 match ret {
                     Node::String(s) => println!("string: {s}"),
                     Node::Integer(i) => println!("int: {i}"),
                     Node::Float(f) => println!("float: {f}"),
                     Node::Bool(b) => println!("bool: {b}"),
+                    Node::Symbol(s) => println!("symbol: {s}"),
                     Node::List(list) => println!("list: {:?}", list),
+                    Node::Map(_) => println!("map: {}", display(&ret)),
                     Node::Null => println!("null: NULL"),
                     Node::Function { name, .. } => {
-                        if name.len() > 0 {
+                        if name.starts_with("native#") {
+                            println!("function: <native#1>");
+                        } else if !name.is_empty() {
                             println!("function: {name}");
                         } else {
                             println!("function: <lambda#1>");
@@ -316,31 +609,128 @@ impl Visitor {
                     _ => println!("{:?}", ret),
                 };
 
-                Node::Null
+                Ok(Node::Null)
             },
             "call" => {
+                expect_args(name, args, 1)?;
                 match &args[0] {
-                    Node::Identifier(id) => {
+                    Node::Identifier(id, _) => {
                         let mut args = args.clone();
                         args.remove(0);
                         self.execute_function(id, &args)
                     },
                     Node::Function { .. } => {
-                        self.scopes.last_mut().unwrap().functions.insert("lambda#1".to_string(), args[0].clone());
+                        let closure = self.evaluate_node(&args[0])?;
+                        self.insert_functions("lambda#1", closure);
                         let mut args = args.clone();
                         args.remove(0);
                         let ret = self.execute_function("lambda#1", &args);
-                        self.scopes.last_mut().unwrap().functions.remove("lambda#1");
+                        self.scopes.last().unwrap().borrow_mut().functions.remove("lambda#1");
+
+                        ret
+                    },
+                    _ => Err(SlError::type_error(format!("'call' expects a function. Got {}.", args[0].type_name()))),
+                }
+            },
+            "apply" => {
+                expect_args(name, args, 2)?;
+                let mut values = self.evaluate_list(&args[1..].to_vec())?;
+                let spread = match values.pop().unwrap() {
+                    Node::List(spread) => spread,
+                    last => return Err(SlError::type_error(format!("'apply' expects a list as last argument. Got {}.", last.type_name()))),
+                };
+                values.extend(spread);
+
+                // The arguments are already evaluated, quoting them keeps
+                // them from being evaluated again by the call.
+                let call_args: Vec<_> = values.into_iter().map(quoted).collect();
+
+                match &args[0] {
+                    Node::Identifier(id, _) => self.evaluate_call(id, &call_args),
+                    _ => {
+                        let func = self.evaluate_node(&args[0])?;
+                        if !matches!(func, Node::Function { .. }) {
+                            return Err(SlError::type_error(format!("'apply' expects a function. Got {}.", func.type_name())));
+                        }
+
+                        self.insert_functions("lambda#1", func);
+                        let ret = self.execute_function("lambda#1", &call_args);
+                        self.scopes.last().unwrap().borrow_mut().functions.remove("lambda#1");
 
                         ret
                     },
-                    _ => panic!("{:?}", args),
                 }
             },
+            "throw" => {
+                expect_args(name, args, 1)?;
+                let value = self.evaluate_node(&args[0])?;
+                let message = match &value {
+                    Node::String(s) | Node::Symbol(s) => s.clone(),
+                    Node::Integer(i) => i.to_string(),
+                    Node::Float(f) => f.to_string(),
+                    Node::Bool(b) => b.to_string(),
+                    _ => format!("{value:?}"),
+                };
+                self.thrown = Some(value);
+
+                Err(SlError::thrown(message))
+            },
+            "try" => {
+                let mut body = args.clone();
+                let finally = match body.last() {
+                    Some(Node::Call { name, args, .. }) if name == "finally" => {
+                        let finally = args.clone();
+                        body.pop();
+                        Some(finally)
+                    },
+                    _ => None,
+                };
+                let catch = match body.last() {
+                    Some(Node::Call { name, args, .. }) if name == "catch" => {
+                        expect_args(name, args, 1)?;
+                        let varname = expect_identifier(name, &args[0])?;
+                        let handler = args[1..].to_vec();
+                        body.pop();
+                        Some((varname, handler))
+                    },
+                    _ => None,
+                };
+
+                self.scopes.push(Scope::new().shared());
+                let mut ret = self.evaluate_block(body);
+                self.scopes.pop();
+
+                if let (Err(e), Some((varname, handler))) = (&ret, catch) {
+                    let mut scope = Scope::new();
+                    scope.variables.insert(varname, self.error_value(e));
+                    self.scopes.push(scope.shared());
+                    ret = self.evaluate_block(handler);
+                    self.scopes.pop();
+                }
+
+                if let Some(finally) = finally {
+                    // A `return` or `break` from the body still happens after
+                    // `finally`.
+                    let control = self.control.take();
+                    self.scopes.push(Scope::new().shared());
+                    let cleanup = self.evaluate_block(finally);
+                    self.scopes.pop();
+                    cleanup?;
+                    if self.control.is_none() {
+                        self.control = control;
+                    }
+                }
+
+                ret
+            },
+            "catch" | "finally" => {
+                Err(SlError::value_error(format!("'{name}' can only be used at the end of a 'try'.")))
+            },
             "load" => {
+                expect_args(name, args, 1)?;
                 let filename = match &args[0] {
                     Node::String(s) => s,
-                    _ => panic!("load only accept strings. Got {:?}", args[0]),
+                    _ => return Err(SlError::type_error(format!("load only accept strings. Got {}.", args[0].type_name()))),
                 };
 
                 let path = self.paths.last().unwrap().clone();
@@ -350,23 +740,24 @@ impl Visitor {
                     self.load_library(filename)
                 } else {
                     eprintln!("Could not load module {:?}", filename);
-                    Node::Null
+                    Ok(Node::Null)
                 }
             },
             "class" => {
+                expect_args(name, args, 1)?;
                 if self.scopes.len() > 2 {
-                    panic!("Class definition can only be done in the main scope.");
+                    return Err(SlError::value_error("Class definition can only be done in the main scope."));
                 }
 
                 let mut skipped = 1;
                 let name = match &args[0] {
-                    Node::Identifier(id) => id,
-                    _ => panic!("'class' only accept identifiers. Got {:?}", args[0]),
+                    Node::Identifier(id, _) => id,
+                    _ => return Err(SlError::type_error(format!("'class' only accept identifiers. Got {}.", args[0].type_name()))),
                 };
 
                 let mut parent = None;
                 if args.len() > 1 {
-                    if let Node::Identifier(parent_class) = &args[1] {
+                    if let Node::Identifier(parent_class, _) = &args[1] {
                         skipped += 1;
                         parent = Some(parent_class.clone());
                     }
@@ -376,24 +767,23 @@ impl Visitor {
 
                 for elem in args.iter().skip(skipped) {
                     match elem {
-                        Node::Call { name, args } => {
+                        Node::Call { name, args, .. } => {
                             if name != "let" {
-                                panic!("Can't call '{}' inside the body of a class, only 'let' and 'fun' are available.", name);
+                                return Err(SlError::value_error(format!("Can't call '{}' inside the body of a class, only 'let' and 'fun' are available.", name)));
                             }
 
-                            let field = match &args[0] {
-                                Node::Identifier(id) => id,
-                                _ => panic!("'let' expects an identifier. Got {:?}", args[0]),
-                            };
+                            expect_args(name, args, 2)?;
+                            let field = expect_identifier(name, &args[0])?;
 
-                            fields.insert(field.clone(), args[1].clone());
+                            fields.insert(field, args[1].clone());
                         },
                         Node::Function { name, .. } => {
-                            functions.insert(name.clone(), elem.clone());
-                            self.insert_functions(&name, elem.clone());
+                            let method = self.capture(elem);
+                            functions.insert(name.clone(), method.clone());
+                            self.insert_functions(name, method);
                         },
                         _ => {
-                            panic!("{:?}", elem);
+                            return Err(SlError::value_error(format!("Only 'let' and 'fun' are available inside the body of a class. Got {}.", elem.type_name())));
                         },
                     };
                 }
@@ -402,260 +792,545 @@ impl Visitor {
                     parent, fields, functions,
                 });
 
-                Node::Null
+                Ok(Node::Null)
             },
             "new" => {
+                expect_args(name, args, 1)?;
                 let classname = match &args[0] {
-                    Node::Identifier(id) => id,
-                    _ => panic!("'new' only accept identifiers. Got {:?}.", args[0]),
+                    Node::Identifier(id, _) => id,
+                    _ => return Err(SlError::type_error(format!("'new' only accept identifiers. Got {}.", args[0].type_name()))),
+                };
+                let Some(mut class) = self.classes.get(classname) else {
+                    return Err(SlError::name_error(format!("Unknown class: {classname}")));
                 };
-                let mut class = &self.classes[classname];
                 let mut fields = class.fields.clone();
 
                 while let Some(parent) = &class.parent {
-                    class = &self.classes[parent];
+                    let Some(parent_class) = self.classes.get(parent) else {
+                        return Err(SlError::name_error(format!("Unknown class: {parent}")));
+                    };
+                    class = parent_class;
                     fields.extend(class.fields.clone());
                 }
 
-                Node::Instance {
+                Ok(Node::Instance {
                     class: classname.to_string(),
                     fields,
-                }
+                })
             },
             "inc" => {
                 let mut ret = Node::Null;
                 for a in args {
                     match a {
-                        Node::Identifier(id) => {
-                            let variable = self.find_variable(&id);
+                        Node::Identifier(id, _) => {
+                            let variable = self.find_variable(id);
                             let Some(variable) = variable else {
-                                panic!("Can't increment non-existing variable '{}'.", id);
+                                return Err(SlError::name_error(format!("Can't increment non-existing variable '{}'.", id)));
                             };
                             let new_val = match variable {
-                                Node::Integer(i) => Node::Integer(i + 1),
+                                Node::Integer(i) => Node::Integer(i.checked_add(1).ok_or_else(|| overflow("inc"))?),
                                 Node::Float(f) => Node::Float(f + 1.0),
-                                _ => panic!("'inc' accepts only integers and floats variables. Got {:?}.", variable),
+                                _ => return Err(SlError::type_error(format!("'inc' accepts only integers and floats variables. Got {}.", variable.type_name()))),
                             };
 
                             ret = new_val.clone();
-                            self.update_variable(&id, new_val);
+                            self.update_variable(id, new_val);
                         },
                         Node::Integer(i) => {
-                            ret = Node::Integer(i + 1);
+                            ret = Node::Integer(i.checked_add(1).ok_or_else(|| overflow("inc"))?);
                         },
-                        _ => panic!("'inc' only accept identifiers, integers or float. Got {:?}.", args[0]),
+                        _ => return Err(SlError::type_error(format!("'inc' only accept identifiers, integers or float. Got {}.", a.type_name()))),
                     };
                 }
 
-                ret
+                Ok(ret)
             },
             "dec" => {
                 let mut ret = Node::Null;
                 for a in args {
                     match a {
-                        Node::Identifier(id) => {
-                            let variable = self.find_variable(&id);
+                        Node::Identifier(id, _) => {
+                            let variable = self.find_variable(id);
                             let Some(variable) = variable else {
-                                panic!("Can't decrement non-existing variable '{}'.", id);
+                                return Err(SlError::name_error(format!("Can't decrement non-existing variable '{}'.", id)));
                             };
                             let new_val = match variable {
-                                Node::Integer(i) => Node::Integer(i - 1),
+                                Node::Integer(i) => Node::Integer(i.checked_sub(1).ok_or_else(|| overflow("dec"))?),
                                 Node::Float(f) => Node::Float(f - 1.0),
-                                _ => panic!("'dec' accepts only integers and floats variables. Got {:?}.", variable),
+                                _ => return Err(SlError::type_error(format!("'dec' accepts only integers and floats variables. Got {}.", variable.type_name()))),
                             };
 
                             ret = new_val.clone();
-                            self.update_variable(&id, new_val);
+                            self.update_variable(id, new_val);
                         },
                         Node::Integer(i) => {
-                            ret = Node::Integer(i - 1);
+                            ret = Node::Integer(i.checked_sub(1).ok_or_else(|| overflow("dec"))?);
                         },
-                        _ => panic!("'dec' only accept identifiers, integers or float. Got {:?}.", args[0]),
+                        _ => return Err(SlError::type_error(format!("'dec' only accept identifiers, integers or float. Got {}.", a.type_name()))),
                     };
                 }
 
-                ret
+                Ok(ret)
             },
             "fiber" => {
-                panic!("UNKNOWN FIBER!!!");
+                Err(SlError::name_error("'fiber' is not supported by the interpreter."))
             },
             _ => {
-                if self.scopes.last_mut().unwrap().functions.contains_key(name) {
+                if tail {
+                    if let Some(func) = self.tail_call_target(name, args) {
+                        let Node::Function { name, params, .. } = &func else { unreachable!() };
+                        let frame = if name.is_empty() { "<lambda>".to_string() } else { name.clone() };
+                        let mut scope = Scope::new();
+                        self.bind_arguments(&frame, params, args, 0, &mut scope)?;
+                        self.tail_call = Some((func, scope));
+
+                        return Ok(Node::Null);
+                    }
+                }
+
+                if self.scopes.last().unwrap().borrow().functions.contains_key(name) {
                     self.execute_function(name, args)
-                } else if self.natives.contains_key(name) {
+                } else if self.is_native(name) && !self.shadows_native(name) {
                     self.execute_native_function(name, args)
+                } else if let Some(func) = &self.find_function(name) {
+                    self.insert_functions("lambda#1", func.clone());
+                    let ret = self.execute_function("lambda#1", args);
+                    self.scopes.last().unwrap().borrow_mut().functions.remove("lambda#1");
+
+                    ret
+                } else if let Some(var) = &self.find_variable(name) {
+                    self.insert_functions("lambda#1", var.clone());
+                    let ret = self.execute_function("lambda#1", args);
+                    self.scopes.last().unwrap().borrow_mut().functions.remove("lambda#1");
+
+                    ret
                 } else {
-                    if let Some(func) = &self.find_function(&name) {
-                        self.scopes.last_mut().unwrap().functions.insert("lambda#1".to_string(), func.clone());
-                        let ret = self.execute_function("lambda#1", args);
-                        self.scopes.last_mut().unwrap().functions.remove("lambda#1");
-
-                        ret
-                    } else if let Some(var) = &self.find_variable(&name) {
-                        self.scopes.last_mut().unwrap().functions.insert("lambda#1".to_string(), var.clone());
-                        let ret = self.execute_function("lambda#1", args);
-                        self.scopes.last_mut().unwrap().functions.remove("lambda#1");
-
-                        ret
-                    } else {
-                        panic!("Unknown function: {}", name);
-                    }
+                    Err(SlError::name_error(format!("Unknown function: {}", name)))
                 }
             },
         }
     }
 
-    fn load_library(&mut self, filename: &str) -> Node {
+    fn load_library(&mut self, filename: &str) -> SlResult<Node> {
         unsafe {
             println!("load_library1: {}", filename);
-            let lib = libloading::Library::new(format!("{}/{}.so", self.paths.last().unwrap(), filename)).unwrap();
+            let lib = match libloading::Library::new(format!("{}/{}.so", self.paths.last().unwrap(), filename)) {
+                Ok(lib) => lib,
+                Err(e) => return Err(SlError::io_error(format!("Can't load library '{filename}': {e}"))),
+            };
             println!("load_library2: {}", filename);
-            let func: libloading::Symbol<unsafe extern fn(&mut HashMap<String, Box<dyn Fn(Vec<Node>) -> Node>>) -> Node> = lib.get(b"module_init").unwrap();
+            let func: libloading::Symbol<unsafe extern "C" fn(&mut Natives) -> Node> = match lib.get(b"module_init") {
+                Ok(func) => func,
+                Err(e) => return Err(SlError::io_error(format!("Library '{filename}' has no 'module_init': {e}"))),
+            };
             println!("load_library3: {}", filename);
             let ret = func(&mut self.natives);
             println!("load_library4: {}", filename);
             self.libs.push(lib);
             println!("load_library5: {}", filename);
-            ret
+            Ok(ret)
+        }
+    }
+
+    fn expand_quasiquote(&mut self, template: &Node) -> SlResult<Node> {
+        match template {
+            Node::Call { name, args, span } if name == "unquote" => {
+                expect_args(name, args, 1).map_err(|e| e.at(*span))?;
+                self.evaluate_node(&args[0])
+            },
+            Node::Call { span, .. } => {
+                Err(SlError::value_error("'unquote-splicing' can only be used inside a list.").at(*span))
+            },
+            Node::List(items) => {
+                let mut values = vec![];
+
+                for item in items {
+                    match item {
+                        Node::Call { name, args, span } if name == "unquote-splicing" => {
+                            expect_args(name, args, 1).map_err(|e| e.at(*span))?;
+                            match self.evaluate_node(&args[0])? {
+                                Node::List(list) => values.extend(list),
+                                other => return Err(SlError::type_error(format!("'unquote-splicing' expects a list. Got {}.", other.type_name())).at(*span)),
+                            }
+                        },
+                        _ => values.push(self.expand_quasiquote(item)?),
+                    }
+                }
+
+                Ok(Node::List(values))
+            },
+            Node::Map(entries) => {
+                let mut values = vec![];
+                for (key, value) in entries {
+                    values.push((self.expand_quasiquote(key)?, self.expand_quasiquote(value)?));
+                }
+
+                Ok(Node::Map(values))
+            },
+            _ => Ok(template.clone()),
         }
     }
 
-    fn check_equality(&self, left: &Node, right: &Node) -> bool {
-        match (left, right) {
-            (Node::Integer(i), Node::Integer(j)) => i == j,
-            _ => false, 
+    /// Matches `value` against a `match` pattern, binding the names of the
+    /// pattern in the current scope. Patterns are literals, `_`, names,
+    /// lists such as `[x y & rest]` and instances such as
+    /// `(Point :x 0 y)`, `y` standing for `:y y`.
+    fn match_pattern(&mut self, pattern: &Node, value: &Node) -> SlResult<bool> {
+        let matched = match pattern {
+            Node::Identifier(name, _) if name == "_" => true,
+            Node::Identifier(name, _) if matches!(name.as_str(), "true" | "false" | "null") => {
+                let literal = self.evaluate_node(pattern)?;
+                check_equality(&literal, value)
+            },
+            Node::Identifier(name, _) => {
+                self.scopes.last().unwrap().borrow_mut().variables.insert(name.clone(), value.clone());
+                true
+            },
+            Node::Integer(_) | Node::Float(_) | Node::String(_) => check_equality(pattern, value),
+            Node::Call { name, args, .. } if name == "quote" => check_equality(&args[0], value),
+            Node::List(patterns) => {
+                let Node::List(values) = value else {
+                    return Ok(false);
+                };
+
+                let (patterns, rest) = match patterns.iter().position(|p| matches!(p, Node::Identifier(id, _) if id == "&")) {
+                    Some(i) if i + 2 == patterns.len() => (&patterns[..i], Some(&patterns[i + 1])),
+                    Some(_) => return Err(SlError::value_error("`&` must be followed by a single pattern.")),
+                    None => (&patterns[..], None),
+                };
+                if values.len() < patterns.len() || (rest.is_none() && values.len() != patterns.len()) {
+                    return Ok(false);
+                }
+
+                for (pattern, value) in patterns.iter().zip(values) {
+                    if !self.match_pattern(pattern, value)? {
+                        return Ok(false);
+                    }
+                }
+                match rest {
+                    Some(rest) => self.match_pattern(rest, &Node::List(values[patterns.len()..].to_vec()))?,
+                    None => true,
+                }
+            },
+            Node::Call { name: classname, args, .. } => {
+                if !self.classes.contains_key(classname) {
+                    return Err(SlError::name_error(format!("Unknown class: {classname}")));
+                }
+                let Node::Instance { class, fields } = value else {
+                    return Ok(false);
+                };
+                if !self.is_subclass(class, classname) {
+                    return Ok(false);
+                }
+
+                let mut args = args.iter();
+                while let Some(arg) = args.next() {
+                    let (field, pattern) = match keyword(arg) {
+                        Some(field) => match args.next() {
+                            Some(pattern) => (field.to_string(), pattern),
+                            None => return Err(SlError::value_error(format!("Keyword ':{field}' has no pattern."))),
+                        },
+                        None => (expect_identifier(classname, arg)?, arg),
+                    };
+
+                    let Some(field_value) = fields.get(&field) else {
+                        return Ok(false);
+                    };
+                    if !self.match_pattern(pattern, &field_value.clone())? {
+                        return Ok(false);
+                    }
+                }
+
+                true
+            },
+            _ => return Err(SlError::type_error(format!("A {} can't be used as a pattern.", pattern.type_name()))),
+        };
+
+        Ok(matched)
+    }
+
+    /// Whether `class` is `ancestor` or inherits from it.
+    fn is_subclass(&self, class: &str, ancestor: &str) -> bool {
+        let mut current = Some(class.to_string());
+        while let Some(name) = current {
+            if name == ancestor {
+                return true;
+            }
+            current = self.classes.get(&name).and_then(|c| c.parent.clone());
         }
+
+        false
     }
 
-    fn evaluate_list(&mut self, args: &Vec<Node>) -> Vec<Node> {
+    fn evaluate_list(&mut self, args: &Vec<Node>) -> SlResult<Vec<Node>> {
         let mut values = vec![];
 
         for node in args {
-            values.push(self.evaluate_node(&node));
+            values.push(self.evaluate_node(node)?);
         }
 
-        values
+        Ok(values)
     }
 
-    fn execute_native_function(&mut self, name: &str, args: &Vec<Node>) -> Node {
-        let args = self.evaluate_list(args);
+    fn execute_native_function(&mut self, name: &str, args: &Vec<Node>) -> SlResult<Node> {
+        let values = self.evaluate_list(args)?;
+
+        // Lists are values, so the natives changing one update the variable
+        // holding it.
+        if is_list_mutation(name, values.len()) {
+            if let (Some(Node::Identifier(varname, _)), Some(Node::List(list))) = (args.first(), values.first()) {
+                let mut list = list.clone();
+                let ret = list_mutation(name, &mut list, &values[1..])?;
+                self.update_variable(varname, Node::List(list));
+                return Ok(ret);
+            }
+        }
 
-        self.natives[name](args)
+        self.call_native(name, values)
     }
 
-    fn execute_function(&mut self, name: &str, args: &Vec<Node>) -> Node {
-        let func = self.find_function(&name).unwrap();
+    fn execute_function(&mut self, name: &str, args: &Vec<Node>) -> SlResult<Node> {
+        let Some(func) = self.find_function(name) else {
+            return Err(SlError::name_error(format!("Unknown function: {name}")));
+        };
+        if let Node::Function { name, .. } = &func {
+            if let Some(native) = name.strip_prefix("native#") {
+                return self.execute_native_function(native, args);
+            }
+        }
         let mut instance_var = None;
         let mut instance_class = String::new();
         let mut instance_fields: HashMap<String, Node> = HashMap::new();
         let mut all_instance_fields = HashMap::new();
 
         match func {
-            Node::Function { name, params, body } => {
+            Node::Function { name, params, body, env, .. } => {
                 let mut scope = Scope::new();
                 let mut f_params = params;
                 let mut f_body = body;
-
-                if args.len() > 0 {
-                    if let Node::Identifier(varname) = args[0].clone() {
-                        if let Some(var) = &self.find_variable(&varname) {
-                            if let Node::Instance { class, fields } = &var {
-                                instance_var = Some(varname);
-                                instance_class = class.clone();
-                                all_instance_fields = fields.clone();
-
-                                let mut classtype = &self.classes[class];
-                                while !classtype.functions.contains_key(&name) {
-                                    if classtype.parent.is_none() {
-                                        panic!("Class '{class}' doesn't have a function named '{name}'.");
-                                    } else {
-                                        classtype = &self.classes[&classtype.parent.clone().unwrap()];
-                                    }
-                                }
-
-                                if let Node::Function { name: _, params, body } = &classtype.functions[&name] {
-                                    f_params = params.clone();
-                                    f_body = body.clone();
+                let frame = if name.is_empty() { "<lambda>".to_string() } else { name.clone() };
+
+                if !args.is_empty() {
+                    if let Node::Identifier(varname, _) = args[0].clone() {
+                        if let Some(Node::Instance { class, fields }) = &self.find_variable(&varname) {
+                            instance_var = Some(varname);
+                            instance_class = class.clone();
+                            all_instance_fields = fields.clone();
+
+                            let mut classtype = &self.classes[class];
+                            while !classtype.functions.contains_key(&name) {
+                                match &classtype.parent {
+                                    Some(parent) => classtype = &self.classes[parent],
+                                    None => return Err(SlError::name_error(format!("Class '{class}' doesn't have a function named '{name}'."))),
                                 }
+                            }
 
-                                loop {
-                                    for (name, _) in &classtype.fields {
-                                        instance_fields.insert(name.clone(), all_instance_fields[name].clone());
-                                    }
+                            if let Node::Function { name: _, params, body, .. } = &classtype.functions[&name] {
+                                f_params = params.clone();
+                                f_body = body.clone();
+                            }
 
-                                    if classtype.parent.is_none() {
-                                        break;
-                                    }
+                            loop {
+                                for name in classtype.fields.keys() {
+                                    instance_fields.insert(name.clone(), all_instance_fields[name].clone());
+                                }
 
-                                    classtype = &self.classes[&classtype.parent.clone().unwrap()];
+                                match &classtype.parent {
+                                    Some(parent) => classtype = &self.classes[parent],
+                                    None => break,
                                 }
                             }
                         }
                     }
                 }
 
-                if instance_var.is_none() && self.natives.contains_key(&name) {
+                // A method called without its instance falls back to the
+                // native of the same name.
+                if instance_var.is_none() && self.is_method(&name) && self.is_native(&name) {
                     return self.execute_native_function(&name, args);
                 }
 
-                let offset = if instance_var.is_some() { 1 } else { 0 };
-                if args.len() > f_params.len() + offset {
-                    panic!("Too much arguments given to '{name}'.");
-                }
-
                 if instance_var.is_some() {
                     for (name, value) in &instance_fields {
-                        scope.variables.insert(name.clone(), self.evaluate_node(&value));
+                        let value = self.evaluate_node(value).map_err(|e| e.with_frame(&frame))?;
+                        scope.variables.insert(name.clone(), value);
                     }
                 }
 
-                for (i, param) in f_params.iter().enumerate() {
-                    if i < args.len() {
-                        scope.variables.insert(param.name.clone(), self.evaluate_node(&args[i + offset]));
+                let offset = if instance_var.is_some() { 1 } else { 0 };
+                self.bind_arguments(&frame, &f_params, args, offset, &mut scope)?;
+
+                let mut frame = frame;
+                let mut env = env;
+
+                // Calls in tail position are run here, after the frame that
+                // made them is gone, so tail recursion doesn't grow the stack.
+                loop {
+                    // The body runs in the scopes the function was defined in,
+                    // the caller's ones are put back afterwards.
+                    let caller_scopes = if env.0.is_empty() {
+                        None
                     } else {
-                        if let Some(def_val) = &param.default_value {
-                            scope.variables.insert(param.name.clone(), self.evaluate_node(&def_val));
-                        } else {
-                            panic!("Parameter '{name}' isn't set and has no default value.");
-                        }
-                    }
-                }
+                        Some(std::mem::replace(&mut self.scopes, env.0))
+                    };
+                    self.scopes.push(scope.shared());
 
-                self.scopes.push(scope);
+                    let ret = self.evaluate_body(f_body);
+                    let done = self.scopes.pop().unwrap();
+                    if let Some(caller_scopes) = caller_scopes {
+                        self.scopes = caller_scopes;
+                    }
 
-                let ret = self.evaluate_block(f_body);
+                    if let Some(varname) = instance_var.take() {
+                        let mut new_fields = all_instance_fields.clone();
 
-                if let Some(varname) = instance_var {
-                    let mut new_fields = all_instance_fields;
+                        instance_fields.iter().for_each(|(key, val)| {
+                            if let Some(f) = done.borrow().variables.get(key) {
+                                new_fields.insert(key.clone(), f.clone());
+                            } else {
+                                new_fields.insert(key.clone(), val.clone());
+                            }
+                        });
 
-                    instance_fields.iter().for_each(|(key, val)| {
-                        if let Some(f) = self.scopes.last().unwrap().variables.get(key) {
-                            new_fields.insert(key.clone(), f.clone());
-                        } else {
-                            new_fields.insert(key.clone(), val.clone());
-                        }
-                    });
+                        let new_var = Node::Instance { class: instance_class.clone(), fields: new_fields };
+                        self.update_variable(&varname, new_var);
+                    }
 
-                    let new_var = Node::Instance { class: instance_class.clone(), fields: new_fields };
-                    self.update_variable(&varname, new_var);
-                }
+                    let control = self.leave_frame();
 
-                self.scopes.pop();
-                self.return_value = None;
+                    let ret = ret.and_then(|ret| control.map(|_| ret)).map_err(|e| e.with_frame(&frame))?;
+                    let Some((func, next_scope)) = self.tail_call.take() else {
+                        return Ok(ret);
+                    };
+                    let Node::Function { name, body, env: next_env, .. } = func else { unreachable!() };
 
-                ret
+                    frame = if name.is_empty() { "<lambda>".to_string() } else { name };
+                    f_body = body;
+                    env = next_env;
+                    scope = next_scope;
+                }
             },
-            Node::Call { name, args } => {
+            Node::Call { name, args, .. } => {
                 self.evaluate_call(&name, &args)
             },
-            _ => panic!("{name} is not a function."),
+            _ => Err(SlError::type_error(format!("{name} is not a function."))),
+        }
+    }
+
+    /// Runs one iteration of a loop in `scope`. Returns the value of the
+    /// body and whether the loop goes on.
+    fn evaluate_iteration(&mut self, scope: Scope, body: &[Node]) -> SlResult<(Node, bool)> {
+        self.scopes.push(scope.shared());
+        let ret = self.evaluate_block(body.to_vec());
+        self.scopes.pop();
+        let ret = ret?;
+
+        let go_on = match self.control {
+            Some(Control::Break) => false,
+            Some(Control::Continue) | None => true,
+            Some(Control::Return(_)) => return Ok((ret, false)),
+        };
+        self.control = None;
+
+        Ok((ret, go_on))
+    }
+
+    /// Clears the control flow at the end of a function or a file, where
+    /// only a `return` can stop.
+    fn leave_frame(&mut self) -> SlResult<()> {
+        match self.control.take() {
+            Some(Control::Break) => Err(SlError::value_error("'break' can only be used inside a loop.")),
+            Some(Control::Continue) => Err(SlError::value_error("'continue' can only be used inside a loop.")),
+            Some(Control::Return(_)) | None => Ok(()),
+        }
+    }
+
+    /// Evaluates the arguments of a call into the scope of the callee,
+    /// `offset` skipping the instance a method is called on. Arguments are
+    /// bound by position, or by name when preceded by a `:name` keyword.
+    fn bind_arguments(&mut self, frame: &str, params: &[Param], args: &[Node], offset: usize, scope: &mut Scope) -> SlResult<()> {
+        let (params, rest) = match params.split_last() {
+            Some((last, params)) if last.rest => (params, Some(last)),
+            _ => (params, None),
+        };
+
+        let mut values: Vec<Option<Node>> = vec![None; params.len()];
+        let mut extra = vec![];
+        let mut position = 0;
+        let mut args = args.iter().skip(offset);
+
+        while let Some(arg) = args.next() {
+            let (index, arg) = if let Some(keyword) = keyword(arg) {
+                let Some(index) = params.iter().position(|p| p.name == keyword) else {
+                    return Err(SlError::name_error(format!("'{frame}' has no parameter named '{keyword}'.")));
+                };
+                let Some(arg) = args.next() else {
+                    return Err(SlError::arity_error(format!("Keyword ':{keyword}' given to '{frame}' has no value.")));
+                };
+                (index, arg)
+            } else if position < params.len() {
+                position += 1;
+                (position - 1, arg)
+            } else if rest.is_some() {
+                extra.push(self.evaluate_node(arg).map_err(|e| e.with_frame(frame))?);
+                continue;
+            } else {
+                return Err(SlError::arity_error(format!("Too much arguments given to '{frame}'.")));
+            };
+
+            if values[index].is_some() {
+                return Err(SlError::arity_error(format!("Parameter '{}' of '{frame}' is given more than once.", params[index].name)));
+            }
+            values[index] = Some(self.evaluate_node(arg).map_err(|e| e.with_frame(frame))?);
+        }
+
+        for (param, value) in params.iter().zip(values) {
+            let value = if let Some(value) = value {
+                Ok(value)
+            } else if let Some(def_val) = &param.default_value {
+                self.evaluate_node(def_val)
+            } else {
+                Err(SlError::arity_error(format!("Parameter '{}' isn't set and has no default value.", param.name)))
+            };
+            scope.variables.insert(param.name.clone(), value.map_err(|e| e.with_frame(frame))?);
+        }
+
+        if let Some(rest) = rest {
+            scope.variables.insert(rest.name.clone(), Node::List(extra));
+        }
+
+        Ok(())
+    }
+
+    /// Finds the function a call in tail position runs, as the fallback
+    /// branch of `evaluate_call` would. Natives and methods, whose instance
+    /// is written back after the call, are left to the usual path.
+    fn tail_call_target(&self, name: &str, args: &[Node]) -> Option<Node> {
+        if self.is_native(name) && !self.shadows_native(name) && !self.scopes.last().unwrap().borrow().functions.contains_key(name) {
+            return None;
+        }
+
+        let func = self.find_function(name).or_else(|| self.find_variable(name))?;
+        let Node::Function { name, .. } = &func else {
+            return None;
+        };
+        if name.starts_with("native#") || self.is_native(name) {
+            return None;
+        }
+
+        if let Some(Node::Identifier(varname, _)) = args.first() {
+            if matches!(self.find_variable(varname), Some(Node::Instance { .. })) {
+                return None;
+            }
         }
+
+        Some(func)
     }
 
     fn find_variable(&self, name: &str) -> Option<Node> {
         for scope in self.scopes.iter().rev() {
-            if scope.variables.contains_key(name) {
-                return Some(scope.variables[name].clone());
+            if let Some(value) = scope.borrow().variables.get(name) {
+                return Some(value.clone());
             }
         }
 
@@ -663,7 +1338,8 @@ impl Visitor {
     }
 
     fn update_variable(&mut self, name: &str, value: Node) {
-        for scope in self.scopes.iter_mut().rev() {
+        for scope in self.scopes.iter().rev() {
+            let mut scope = scope.borrow_mut();
             if scope.variables.contains_key(name) {
                 scope.variables.insert(name.to_string(), value);
                 return;
@@ -673,38 +1349,140 @@ impl Visitor {
 
     fn find_function(&self, name: &str) -> Option<Node> {
         for scope in self.scopes.iter().rev() {
-            if scope.functions.contains_key(name) {
-                return Some(scope.functions[name].clone());
+            if let Some(function) = scope.borrow().functions.get(name) {
+                return Some(function.clone());
             }
         }
 
         None
     }
 
+    /// Binds `value` in the current scope to a name, or to a list pattern
+    /// such as `[x y & rest]` where `_` skips an element.
+    fn bind_pattern(&mut self, pattern: &Node, value: Node) -> SlResult<()> {
+        match pattern {
+            Node::Identifier(name, _) if name == "_" => {},
+            Node::Identifier(name, _) => {
+                if self.scopes.last().unwrap().borrow().variables.contains_key(name) {
+                    return Err(SlError::name_error(format!("Variable '{name}' already exists in that scope.")));
+                }
+
+                self.scopes.last().unwrap().borrow_mut().variables.insert(name.clone(), value);
+            },
+            Node::List(patterns) => {
+                let Node::List(values) = value else {
+                    return Err(SlError::type_error(format!("Can't destructure a {} as a list.", value.type_name())));
+                };
+
+                let (patterns, rest) = match patterns.iter().position(|p| matches!(p, Node::Identifier(id, _) if id == "&")) {
+                    Some(i) if i + 2 == patterns.len() => (&patterns[..i], Some(&patterns[i + 1])),
+                    Some(_) => return Err(SlError::value_error("`&` must be followed by a single pattern.")),
+                    None => (&patterns[..], None),
+                };
+                if values.len() < patterns.len() {
+                    return Err(SlError::index_error(format!("Can't destructure a list of {} elements into {} patterns.", values.len(), patterns.len())));
+                }
+
+                let mut values = values.into_iter();
+                for pattern in patterns {
+                    self.bind_pattern(pattern, values.next().unwrap())?;
+                }
+                if let Some(rest) = rest {
+                    self.bind_pattern(rest, Node::List(values.collect()))?;
+                }
+            },
+            _ => return Err(SlError::type_error(format!("'let' expects an identifier or a list pattern. Got {}.", pattern.type_name()))),
+        }
+
+        Ok(())
+    }
+
     fn insert_functions(&mut self, name: &str, value: Node) {
-        self.scopes.last_mut().unwrap().functions.insert(name.to_string(), value);
+        self.scopes.last().unwrap().borrow_mut().functions.insert(name.to_string(), value);
+    }
+
+    /// The value a `catch` gets: the thrown value, or a map with the kind
+    /// and the message of a built-in error.
+    fn error_value(&mut self, error: &SlError) -> Node {
+        match self.thrown.take() {
+            Some(value) if error.kind == ErrorKind::Thrown => value,
+            _ => Node::Map(vec![
+                (Node::String("kind".to_string()), Node::String(error.kind.to_string())),
+                (Node::String("message".to_string()), Node::String(error.message.clone())),
+            ]),
+        }
+    }
+
+    /// Turns a function definition into a closure over the current scopes.
+    fn capture(&self, function: &Node) -> Node {
+        let mut closure = function.clone();
+        if let Node::Function { env, .. } = &mut closure {
+            *env = Env(self.scopes.clone());
+        }
+        closure
+    }
+}
+
+fn expect_args(name: &str, args: &[Node], count: usize) -> SlResult<()> {
+    if args.len() < count {
+        return Err(SlError::arity_error(format!("'{name}' expects at least {count} argument(s). Got {}.", args.len())));
+    }
+
+    Ok(())
+}
+
+/// Name of the parameter a `:name` argument binds the next one to.
+fn keyword(node: &Node) -> Option<&str> {
+    match node {
+        Node::Identifier(name, _) => name.strip_prefix(':').filter(|name| !name.is_empty()),
+        _ => None,
     }
 }
 
-fn plus_operator(args: Vec<Node>) -> Node {
+/// Symbols made by `gensym` so far.
+static GENSYMS: AtomicUsize = AtomicUsize::new(0);
+
+fn macro_name(name: &str) -> String {
+    format!("macro#{name}")
+}
+
+/// Name of the function value standing for the native `name`.
+fn native_name(name: &str) -> String {
+    format!("native#{name}")
+}
+
+/// Wraps a value so that evaluating it gives it back as is.
+fn quoted(value: Node) -> Node {
+    Node::Call { name: "quote".to_string(), args: vec![value], span: Span::default() }
+}
+
+fn expect_identifier(name: &str, node: &Node) -> SlResult<String> {
+    match node {
+        Node::Identifier(s, _) => Ok(s.clone()),
+        _ => Err(SlError::type_error(format!("'{name}' expects an identifier. Got {}.", node.type_name()))),
+    }
+}
+
+fn plus_operator(args: Vec<Node>) -> SlResult<Node> {
+    expect_args("add", &args, 1)?;
     let mut ret = args[0].clone();
 
     for n in args.iter().skip(1) {
         ret = match ret.clone() {
             Node::Integer(i) => {
                 match n {
-                    Node::Integer(j) => Node::Integer(i + j),
-                    Node::Float(f) => Node::Float((i as f32) + f),
+                    Node::Integer(j) => Node::Integer(i.checked_add(*j).ok_or_else(|| overflow("add"))?),
+                    Node::Float(f) => Node::Float((i as f64) + f),
                     Node::String(s) => Node::String(format!("{i}{s}")),
-                    _ => panic!("'add' doesn't accept {n} as operand"),
+                    _ => return Err(SlError::type_error(format!("'add' doesn't accept {} as operand", n.type_name()))),
                 }
             },
             Node::Float(f) => {
                 match n {
-                    Node::Integer(i) => Node::Float(f + (*i as f32)),
+                    Node::Integer(i) => Node::Float(f + (*i as f64)),
                     Node::Float(g) => Node::Float(f + g),
                     Node::String(s) => Node::String(format!("{f}{s}")),
-                    _ => panic!("'add' doesn't accept {n} as operand"),
+                    _ => return Err(SlError::type_error(format!("'add' doesn't accept {} as operand", n.type_name()))),
                 }
             },
             Node::String(s) => {
@@ -712,23 +1490,28 @@ fn plus_operator(args: Vec<Node>) -> Node {
                     Node::Integer(i) => Node::String(format!("{s}{i}")),
                     Node::Float(f) => Node::String(format!("{s}{f}")),
                     Node::String(t) => Node::String(format!("{s}{t}")),
-                    _ => panic!("'add' doesn't accept {n} as operand"),
+                    _ => return Err(SlError::type_error(format!("'add' doesn't accept {} as operand", n.type_name()))),
                 }
             },
-            _ => panic!("'add' doesn't accept {n} as operand"),
+            _ => return Err(SlError::type_error(format!("'add' doesn't accept {} as operand", ret.type_name()))),
         };
     }
 
-    ret
+    Ok(ret)
 }
 
-fn minus_operator(args: Vec<Node>) -> Node {
+fn overflow(name: &str) -> SlError {
+    SlError::value_error(format!("'{name}' overflowed the 64-bit integer range"))
+}
+
+fn minus_operator(args: Vec<Node>) -> SlResult<Node> {
+    expect_args("sub", &args, 1)?;
 
     if args.len() == 1 {
         match args[0] {
-            Node::Integer(i) => Node::Integer(-i),
-            Node::Float(f) => Node::Float(-f),
-            _ => panic!("'sub' doesn't accept {} as operand", args[0]),
+            Node::Integer(i) => Ok(Node::Integer(i.checked_neg().ok_or_else(|| overflow("sub"))?)),
+            Node::Float(f) => Ok(Node::Float(-f)),
+            _ => Err(SlError::type_error(format!("'sub' doesn't accept {} as operand", args[0].type_name()))),
         }
     } else {
         let mut ret = args[0].clone();
@@ -737,149 +1520,159 @@ fn minus_operator(args: Vec<Node>) -> Node {
             ret = match ret {
                 Node::Integer(i) => {
                     match n {
-                        Node::Integer(j) => Node::Integer(i - j),
-                        Node::Float(f) => Node::Float((i as f32) - f),
-                        Node::String(..) => panic!("Can't substract a string from an int"),
-                        _ => panic!("'sub' doesn't accept {n} as operand"),
+                        Node::Integer(j) => Node::Integer(i.checked_sub(*j).ok_or_else(|| overflow("sub"))?),
+                        Node::Float(f) => Node::Float((i as f64) - f),
+                        Node::String(..) => return Err(SlError::type_error("Can't substract a string from an int")),
+                        _ => return Err(SlError::type_error(format!("'sub' doesn't accept {} as operand", n.type_name()))),
                     }
                 },
                 Node::Float(f) => {
                     match n {
-                        Node::Integer(i) => Node::Float(f - (*i as f32)),
+                        Node::Integer(i) => Node::Float(f - (*i as f64)),
                         Node::Float(g) => Node::Float(f - g),
-                        Node::String(..) => panic!("Can't substract a string from a float"),
-                        _ => panic!("'sub' doesn't accept {n} as operand"),
+                        Node::String(..) => return Err(SlError::type_error("Can't substract a string from a float")),
+                        _ => return Err(SlError::type_error(format!("'sub' doesn't accept {} as operand", n.type_name()))),
                     }
                 },
-                _ => panic!("'sub' doesn't accept {n} as operand"),
+                _ => return Err(SlError::type_error(format!("'sub' doesn't accept {} as operand", ret.type_name()))),
             };
         }
 
-        ret
+        Ok(ret)
     }
 }
 
-fn mult_operator(args: Vec<Node>) -> Node {
+fn mult_operator(args: Vec<Node>) -> SlResult<Node> {
+    expect_args("mul", &args, 1)?;
     let mut ret = args[0].clone();
 
     for n in args.iter().skip(1) {
         ret = match ret.clone() {
             Node::Integer(i) => {
                 match n {
-                    Node::Integer(j) => Node::Integer(i * j),
-                    Node::Float(f) => Node::Float((i as f32) * f),
-                    Node::String(..) => panic!("Can't multiply an int and a string"),
-                    _ => panic!("'mul' doesn't accept {n} as operand"),
+                    Node::Integer(j) => Node::Integer(i.checked_mul(*j).ok_or_else(|| overflow("mul"))?),
+                    Node::Float(f) => Node::Float((i as f64) * f),
+                    Node::String(..) => return Err(SlError::type_error("Can't multiply an int and a string")),
+                    _ => return Err(SlError::type_error(format!("'mul' doesn't accept {} as operand", n.type_name()))),
                 }
             },
             Node::Float(f) => {
                 match n {
-                    Node::Integer(i) => Node::Float(f * (*i as f32)),
+                    Node::Integer(i) => Node::Float(f * (*i as f64)),
                     Node::Float(g) => Node::Float(f * g),
-                    Node::String(..) => panic!("Can't multiply a float and a string"),
-                    _ => panic!("'mul' doesn't accept {n} as operand"),
+                    Node::String(..) => return Err(SlError::type_error("Can't multiply a float and a string")),
+                    _ => return Err(SlError::type_error(format!("'mul' doesn't accept {} as operand", n.type_name()))),
                 }
             },
             Node::String(s) => {
                 match n {
-                    Node::Integer(i) => Node::String(s.repeat(*i as usize)),
-                    Node::Float(..) => panic!("Can't multiply a string and a float"),
-                    Node::String(..) => panic!("Can't multiply two strings together"),
-                    _ => panic!("'mul' doesn't accept {n} as operand"),
+                    Node::Integer(i) => Node::String(s.repeat((*i).max(0) as usize)),
+                    Node::Float(..) => return Err(SlError::type_error("Can't multiply a string and a float")),
+                    Node::String(..) => return Err(SlError::type_error("Can't multiply two strings together")),
+                    _ => return Err(SlError::type_error(format!("'mul' doesn't accept {} as operand", n.type_name()))),
                 }
             },
-            _ => panic!("'mul' doesn't accept {n} as operand"),
+            _ => return Err(SlError::type_error(format!("'mul' doesn't accept {} as operand", ret.type_name()))),
         };
     }
 
-    ret
+    Ok(ret)
 }
 
-fn div_operator(args: Vec<Node>) -> Node {
+fn div_operator(args: Vec<Node>) -> SlResult<Node> {
+    expect_args("div", &args, 1)?;
     let mut ret = args[0].clone();
 
     for n in args.iter().skip(1) {
         ret = match ret.clone() {
             Node::Integer(i) => {
                 match n {
-                    Node::Integer(j) => Node::Integer(i / j),
-                    Node::Float(f) => Node::Float((i as f32) / f),
-                    Node::String(..) => panic!("Can't divide an int and a string"),
-                    _ => panic!("'div' doesn't accept {n} as operand"),
+                    Node::Integer(0) => return Err(SlError::value_error("Division by zero")),
+                    Node::Integer(j) => Node::Integer(i.checked_div(*j).ok_or_else(|| overflow("div"))?),
+                    Node::Float(f) => Node::Float((i as f64) / f),
+                    Node::String(..) => return Err(SlError::type_error("Can't divide an int and a string")),
+                    _ => return Err(SlError::type_error(format!("'div' doesn't accept {} as operand", n.type_name()))),
                 }
             },
             Node::Float(f) => {
                 match n {
-                    Node::Integer(i) => Node::Float(f / (*i as f32)),
+                    Node::Integer(i) => Node::Float(f / (*i as f64)),
                     Node::Float(g) => Node::Float(f / g),
-                    Node::String(..) => panic!("Can't divide a float and a string"),
-                    _ => panic!("'div' doesn't accept {n} as operand"),
+                    Node::String(..) => return Err(SlError::type_error("Can't divide a float and a string")),
+                    _ => return Err(SlError::type_error(format!("'div' doesn't accept {} as operand", n.type_name()))),
                 }
             },
             Node::String(..) => {
                 match n {
-                    Node::Integer(..) => panic!("Can't divide a string and an int"),
-                    Node::Float(..) => panic!("Can't divide a string and a float"),
-                    Node::String(..) => panic!("Can't divide two strings together"),
-                    _ => panic!("'div' doesn't accept {n} as operand"),
+                    Node::Integer(..) => return Err(SlError::type_error("Can't divide a string and an int")),
+                    Node::Float(..) => return Err(SlError::type_error("Can't divide a string and a float")),
+                    Node::String(..) => return Err(SlError::type_error("Can't divide two strings together")),
+                    _ => return Err(SlError::type_error(format!("'div' doesn't accept {} as operand", n.type_name()))),
                 }
             },
-            _ => panic!("'div' doesn't accept {n} as operand"),
+            _ => return Err(SlError::type_error(format!("'div' doesn't accept {} as operand", ret.type_name()))),
         };
     }
 
-    ret
+    Ok(ret)
 }
 
 fn cmp_binary_operator<I, F, S>(name: &str,
                                 op_int: I,
                                 op_float: F,
                                 op_str: S,
-                                args: Vec<Node>) -> Node
+                                args: Vec<Node>) -> SlResult<Node>
     where
-        I: Fn(i32, i32) -> bool,
-        F: Fn(f32, f32) -> bool,
+        I: Fn(i64, i64) -> bool,
+        F: Fn(f64, f64) -> bool,
         S: Fn(String, String) -> bool,
 {
+    if args.len() != 2 {
+        return Err(SlError::arity_error(format!("'{name}' expects 2 arguments. Got {}.", args.len())));
+    }
+
     let left = args[0].clone();
     let right = args[1].clone();
 
-    if name == "eq" {
-        if std::mem::discriminant(&left) != std::mem::discriminant(&right) {
-            return Node::Bool(false);
-        }
+    if name == "eq" && std::mem::discriminant(&left) != std::mem::discriminant(&right) {
+        return Ok(Node::Bool(false));
     }
 
     match left {
         Node::Integer(i) => {
             match right {
-                Node::Integer(j) => Node::Bool(op_int(i, j)),
-                Node::Float(f) => Node::Bool(op_float(i as f32, f)),
-                Node::String(..) => panic!("Can't apply '{name}' to an int and a string"),
-                _ => panic!("operator '{name}' doesn't accept {right} as operand"),
+                Node::Integer(j) => Ok(Node::Bool(op_int(i, j))),
+                Node::Float(f) => Ok(Node::Bool(op_float(i as f64, f))),
+                Node::String(..) => Err(SlError::type_error(format!("Can't apply '{name}' to an int and a string"))),
+                _ => Err(SlError::type_error(format!("operator '{name}' doesn't accept {} as operand", right.type_name()))),
             }
         },
         Node::Float(f) => {
             match right {
-                Node::Integer(i) => Node::Bool(op_float(f, i as f32)),
-                Node::Float(g) => Node::Bool(op_float(f, g)),
-                Node::String(..) => panic!("Can't apply '{name}' to a float and a string"),
-                _ => panic!("operator '{name}' doesn't accept {right} as operand"),
+                Node::Integer(i) => Ok(Node::Bool(op_float(f, i as f64))),
+                Node::Float(g) => Ok(Node::Bool(op_float(f, g))),
+                Node::String(..) => Err(SlError::type_error(format!("Can't apply '{name}' to a float and a string"))),
+                _ => Err(SlError::type_error(format!("operator '{name}' doesn't accept {} as operand", right.type_name()))),
             }
         },
         Node::String(s) => {
             match right {
-                Node::Integer(..) => panic!("Can't apply '{name}' to a string and an int"),
-                Node::Float(..) => panic!("Can't apply '{name}' to a string and a float"),
-                Node::String(t) => Node::Bool(op_str(s, t)),
-                _ => panic!("operator '{name}' doesn't accept {right} as operand"),
+                Node::Integer(..) => Err(SlError::type_error(format!("Can't apply '{name}' to a string and an int"))),
+                Node::Float(..) => Err(SlError::type_error(format!("Can't apply '{name}' to a string and a float"))),
+                Node::String(t) => Ok(Node::Bool(op_str(s, t))),
+                _ => Err(SlError::type_error(format!("operator '{name}' doesn't accept {} as operand", right.type_name()))),
+            }
+        },
+        Node::Symbol(s) if name == "eq" || name == "neq" => {
+            match right {
+                Node::Symbol(t) => Ok(Node::Bool(op_str(s, t))),
+                _ => Ok(Node::Bool(name == "neq")),
             }
         },
-        _ => panic!("operator '{name}' doesn't accept {left} as operand"),
+        _ => Err(SlError::type_error(format!("operator '{name}' doesn't accept {} as operand", left.type_name()))),
     }
 }
-
-fn lt_operator(args: Vec<Node>) -> Node {
+fn lt_operator(args: Vec<Node>) -> SlResult<Node> {
     cmp_binary_operator("lt",
                         |x, y| x < y,
                         |x, y| x < y,
@@ -887,7 +1680,7 @@ fn lt_operator(args: Vec<Node>) -> Node {
                         args)
 }
 
-fn le_operator(args: Vec<Node>) -> Node {
+fn le_operator(args: Vec<Node>) -> SlResult<Node> {
     cmp_binary_operator("le",
                         |x, y| x <= y,
                         |x, y| x <= y,
@@ -895,7 +1688,7 @@ fn le_operator(args: Vec<Node>) -> Node {
                         args)
 }
 
-fn gt_operator(args: Vec<Node>) -> Node {
+fn gt_operator(args: Vec<Node>) -> SlResult<Node> {
     cmp_binary_operator("gt",
                         |x, y| x > y,
                         |x, y| x > y,
@@ -903,7 +1696,7 @@ fn gt_operator(args: Vec<Node>) -> Node {
                         args)
 }
 
-fn ge_operator(args: Vec<Node>) -> Node {
+fn ge_operator(args: Vec<Node>) -> SlResult<Node> {
     cmp_binary_operator("ge",
                         |x, y| x >= y,
                         |x, y| x >= y,
@@ -911,7 +1704,7 @@ fn ge_operator(args: Vec<Node>) -> Node {
                         args)
 }
 
-fn eq_operator(args: Vec<Node>) -> Node {
+fn eq_operator(args: Vec<Node>) -> SlResult<Node> {
     cmp_binary_operator("eq",
                         |x, y| x == y,
                         |x, y| x == y,
@@ -919,7 +1712,7 @@ fn eq_operator(args: Vec<Node>) -> Node {
                         args)
 }
 
-fn neq_operator(args: Vec<Node>) -> Node {
+fn neq_operator(args: Vec<Node>) -> SlResult<Node> {
     cmp_binary_operator("neq",
                         |x, y| x != y,
                         |x, y| x != y,
@@ -927,35 +1720,43 @@ fn neq_operator(args: Vec<Node>) -> Node {
                         args)
 }
 
-fn random(args: Vec<Node>) -> Node {
+fn random(args: Vec<Node>) -> SlResult<Node> {
     let mut rng = rand::thread_rng();
 
     match args.len() {
-        0 => Node::Float(rng.gen::<f32>()),
+        0 => Ok(Node::Float(rng.gen::<f64>())),
         1 => {
             let Node::Integer(max) = args[0] else {
-                panic!("'random' expects only integer arguments. Got {:?}.", args[0]);
+                return Err(SlError::type_error(format!("'random' expects only integer arguments. Got {}.", args[0].type_name())));
             };
 
-            let val: i32 = rng.gen_range(0..max);
-            Node::Integer(val)
+            if max <= 0 {
+                return Err(SlError::value_error(format!("'random' expects a positive upper bound. Got {max}.")));
+            }
+
+            let val: i64 = rng.gen_range(0..max);
+            Ok(Node::Integer(val))
         },
         2 => {
             let Node::Integer(min) = args[0] else {
-                panic!("'random' expects only integer arguments. Got {:?}.", args[0]);
+                return Err(SlError::type_error(format!("'random' expects only integer arguments. Got {}.", args[0].type_name())));
             };
             let Node::Integer(max) = args[1] else {
-                panic!("'random' expects only integer arguments. Got {:?}.", args[0]);
+                return Err(SlError::type_error(format!("'random' expects only integer arguments. Got {}.", args[1].type_name())));
             };
 
-            let val: i32 = rng.gen_range(min..max);
-            Node::Integer(val)
+            if min >= max {
+                return Err(SlError::value_error(format!("'random' expects a non-empty range. Got {min}..{max}.")));
+            }
+
+            let val: i64 = rng.gen_range(min..max);
+            Ok(Node::Integer(val))
         },
-        _ => panic!("'random' expects between 0 and 2 arguments. Got {}.", args.len()),
+        _ => Err(SlError::arity_error(format!("'random' expects between 0 and 2 arguments. Got {}.", args.len()))),
     }
 }
 
-fn load_maths_module(natives: &mut HashMap<String, Box<dyn Fn(Vec<Node>) -> Node>>) {
+fn load_maths_module(natives: &mut Natives) {
     natives.insert("add".to_string(), Box::new(plus_operator));
     natives.insert("sub".to_string(), Box::new(minus_operator));
     natives.insert("mul".to_string(), Box::new(mult_operator));
@@ -970,189 +1771,1034 @@ fn load_maths_module(natives: &mut HashMap<String, Box<dyn Fn(Vec<Node>) -> Node
     natives.insert("random".to_string(), Box::new(random));
 }
 
-fn list_size(args: Vec<Node>) -> Node {
+/// Structural equality, as used by `match` and to compare map keys.
+fn check_equality(left: &Node, right: &Node) -> bool {
+    match (left, right) {
+        (Node::Integer(i), Node::Integer(j)) => i == j,
+        (Node::Float(f), Node::Float(g)) => f == g,
+        (Node::Integer(i), Node::Float(f)) | (Node::Float(f), Node::Integer(i)) => *i as f64 == *f,
+        (Node::String(s), Node::String(t)) | (Node::Symbol(s), Node::Symbol(t)) => s == t,
+        (Node::Bool(a), Node::Bool(b)) => a == b,
+        (Node::Null, Node::Null) => true,
+        (Node::List(a), Node::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(l, r)| check_equality(l, r))
+        },
+        (Node::Map(a), Node::Map(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, value)| {
+                map_position(b, key).is_some_and(|i| check_equality(value, &b[i].1))
+            })
+        },
+        _ => false,
+    }
+}
+
+fn list_size(args: Vec<Node>) -> SlResult<Node> {
     if args.len() != 1 {
-        panic!("size expects 1 argument. Got {}.", args.len());
+        return Err(SlError::arity_error(format!("size expects 1 argument. Got {}.", args.len())));
     }
 
     match &args[0] {
-        Node::List(l) => Node::Integer(l.len() as i32),
-        Node::Null => Node::Integer(0),
-        _ => Node::Integer(1),
+        Node::List(l) => Ok(Node::Integer(l.len() as i64)),
+        Node::Null => Ok(Node::Integer(0)),
+        _ => Ok(Node::Integer(1)),
     }
 }
 
-fn list_get(args: Vec<Node>) -> Node {
+fn list_get(args: Vec<Node>) -> SlResult<Node> {
+    if args.len() == 3 {
+        return list_mutation_value("nth", args);
+    }
     if args.len() != 2 {
-        panic!("nth expects 2 argument. Got {}.", args.len());
+        return Err(SlError::arity_error(format!("nth expects 2 or 3 arguments. Got {}.", args.len())));
     }
 
     let Node::List(list) = &args[0] else {
-        panic!("nth only accepts list.");
+        return Err(SlError::type_error(format!("nth only accepts list. Got {}.", args[0].type_name())));
     };
 
     let Node::Integer(index) = &args[1] else {
-        panic!("nth only accepts integer indices.");
+        return Err(SlError::type_error(format!("nth only accepts integer indices. Got {}.", args[1].type_name())));
     };
-    let index = *index as usize;
 
-    if list.len() > index {
-        list[index].clone()
+    if *index >= 0 && list.len() > *index as usize {
+        Ok(list[*index as usize].clone())
     } else {
-        panic!("nth out-of-bound access. list size: {}, index provided: {}", list.len(), index);
+        Err(SlError::index_error(format!("nth out-of-bound access. list size: {}, index provided: {}", list.len(), index)))
+    }
+}
+
+/// `(range end)`, `(range start end)` or `(range start end step)`, the
+/// end being excluded.
+fn range(args: Vec<Node>) -> SlResult<Node> {
+    let mut bounds = vec![];
+    for arg in &args {
+        let Node::Integer(i) = arg else {
+            return Err(SlError::type_error(format!("range only accepts integers. Got {}.", arg.type_name())));
+        };
+        bounds.push(*i);
+    }
+
+    let (start, end, step) = match bounds[..] {
+        [end] => (0, end, 1),
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step),
+        _ => return Err(SlError::arity_error(format!("range expects 1 to 3 arguments. Got {}.", args.len()))),
+    };
+    if step == 0 {
+        return Err(SlError::value_error("range step can't be 0."));
+    }
+
+    let mut list = vec![];
+    let mut i = start;
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        list.push(Node::Integer(i));
+        let Some(next) = i.checked_add(step) else {
+            break;
+        };
+        i = next;
+    }
+
+    Ok(Node::List(list))
+}
+
+fn expect_list<'a>(name: &str, node: &'a Node) -> SlResult<&'a Vec<Node>> {
+    match node {
+        Node::List(list) => Ok(list),
+        _ => Err(SlError::type_error(format!("{name} only accepts list. Got {}.", node.type_name()))),
+    }
+}
+
+/// An index in `0..len`, or in `0..=len` when `end` is allowed.
+fn expect_index(name: &str, node: &Node, len: usize, end: bool) -> SlResult<usize> {
+    let Node::Integer(index) = node else {
+        return Err(SlError::type_error(format!("{name} only accepts integer indices. Got {}.", node.type_name())));
+    };
+
+    match usize::try_from(*index) {
+        Ok(i) if i < len || (end && i == len) => Ok(i),
+        _ => Err(SlError::index_error(format!("{name} out-of-bound access. list size: {len}, index provided: {index}"))),
+    }
+}
+
+/// Whether the native `name` called with `count` arguments changes its
+/// first argument, a list.
+fn is_list_mutation(name: &str, count: usize) -> bool {
+    matches!(name, "push" | "pop" | "set-nth" | "insert" | "remove-at") || (name == "nth" && count == 3)
+}
+
+/// Changes `list` in place and returns the element added, replaced or
+/// removed. `args` are the arguments after the list.
+fn list_mutation(name: &str, list: &mut Vec<Node>, args: &[Node]) -> SlResult<Node> {
+    let expected = match name {
+        "pop" => 0,
+        "push" | "remove-at" => 1,
+        _ => 2,
+    };
+    if args.len() != expected {
+        return Err(SlError::arity_error(format!("{name} expects {} argument(s). Got {}.", expected + 1, args.len() + 1)));
+    }
+
+    match name {
+        "push" => {
+            list.push(args[0].clone());
+            Ok(args[0].clone())
+        },
+        "pop" => list.pop().ok_or_else(|| SlError::index_error("pop on an empty list.")),
+        "insert" => {
+            let index = expect_index(name, &args[0], list.len(), true)?;
+            list.insert(index, args[1].clone());
+            Ok(args[1].clone())
+        },
+        "remove-at" => {
+            let index = expect_index(name, &args[0], list.len(), false)?;
+            Ok(list.remove(index))
+        },
+        _ => {
+            let index = expect_index(name, &args[0], list.len(), false)?;
+            list[index] = args[1].clone();
+            Ok(args[1].clone())
+        },
+    }
+}
+
+/// A list mutation called on a value rather than on a variable. Lists are
+/// values, so the change would be lost.
+fn list_mutation_value(name: &str, args: Vec<Node>) -> SlResult<Node> {
+    let Some(list) = args.first() else {
+        return Err(SlError::arity_error(format!("{name} expects a list. Got no argument.")));
+    };
+    expect_list(name, list)?;
+    Err(SlError::value_error(format!("{name} changes a list held in a variable, not a value.")))
+}
+
+/// `(slice list start)` or `(slice list start end)`, the end being
+/// excluded.
+fn list_slice(args: Vec<Node>) -> SlResult<Node> {
+    if !(2..=3).contains(&args.len()) {
+        return Err(SlError::arity_error(format!("slice expects 2 or 3 arguments. Got {}.", args.len())));
+    }
+
+    let list = expect_list("slice", &args[0])?;
+    let start = expect_index("slice", &args[1], list.len(), true)?;
+    let end = match args.get(2) {
+        Some(end) => expect_index("slice", end, list.len(), true)?,
+        None => list.len(),
+    };
+    if start > end {
+        return Err(SlError::index_error(format!("slice starts after its end. start: {start}, end: {end}")));
     }
+
+    Ok(Node::List(list[start..end].to_vec()))
 }
 
-fn load_list_module(natives: &mut HashMap<String, Box<dyn Fn(Vec<Node>) -> Node>>) {
+fn list_concat(args: Vec<Node>) -> SlResult<Node> {
+    let mut result = vec![];
+    for arg in &args {
+        result.extend(expect_list("concat", arg)?.iter().cloned());
+    }
+
+    Ok(Node::List(result))
+}
+
+fn list_reverse(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("reverse", &args, 1)?;
+    let list = expect_list("reverse", &args[0])?;
+    Ok(Node::List(list.iter().rev().cloned().collect()))
+}
+
+/// The index of the first element equal to the value, or -1.
+fn list_index_of(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("index-of", &args, 2)?;
+    let list = expect_list("index-of", &args[0])?;
+    match list.iter().position(|item| check_equality(item, &args[1])) {
+        Some(i) => Ok(Node::Integer(i as i64)),
+        None => Ok(Node::Integer(-1)),
+    }
+}
+
+fn list_contains(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("contains", &args, 2)?;
+    let list = expect_list("contains", &args[0])?;
+    Ok(Node::Bool(list.iter().any(|item| check_equality(item, &args[1]))))
+}
+
+fn load_list_module(natives: &mut Natives) {
     natives.insert("size".to_string(), Box::new(list_size));
     natives.insert("nth".to_string(), Box::new(list_get));
+    natives.insert("range".to_string(), Box::new(range));
+    for name in ["push", "pop", "set-nth", "insert", "remove-at"] {
+        natives.insert(name.to_string(), Box::new(move |args| list_mutation_value(name, args)));
+    }
+    natives.insert("slice".to_string(), Box::new(list_slice));
+    natives.insert("concat".to_string(), Box::new(list_concat));
+    natives.insert("reverse".to_string(), Box::new(list_reverse));
+    natives.insert("index-of".to_string(), Box::new(list_index_of));
+    natives.insert("contains".to_string(), Box::new(list_contains));
+    natives.insert("sort".to_string(), Box::new(list_sort));
+    natives.insert("zip".to_string(), Box::new(list_zip));
+}
+
+fn map_position(entries: &[(Node, Node)], key: &Node) -> Option<usize> {
+    entries.iter().position(|(k, _)| check_equality(k, key))
+}
+
+fn map_insert(entries: &mut Vec<(Node, Node)>, key: Node, value: Node) {
+    match map_position(entries, &key) {
+        Some(i) => entries[i].1 = value,
+        None => entries.push((key, value)),
+    }
+}
+
+fn expect_map<'a>(name: &str, node: &'a Node) -> SlResult<&'a Vec<(Node, Node)>> {
+    match node {
+        Node::Map(entries) => Ok(entries),
+        _ => Err(SlError::type_error(format!("{name} only accepts map. Got {}.", node.type_name()))),
+    }
+}
+
+fn expect_arity(name: &str, args: &[Node], count: usize) -> SlResult<()> {
+    if args.len() != count {
+        return Err(SlError::arity_error(format!("{name} expects {count} argument(s). Got {}.", args.len())));
+    }
+    Ok(())
 }
 
-fn is_null(args: Vec<Node>) -> Node {
+/// `(map key value ...)`, the keys being compared like with `eq`.
+fn map_new(args: Vec<Node>) -> SlResult<Node> {
+    if !args.len().is_multiple_of(2) {
+        return Err(SlError::arity_error(format!("map expects pairs of key and value. Got {} argument(s).", args.len())));
+    }
+
+    let mut entries = vec![];
+    let mut args = args.into_iter();
+    while let (Some(key), Some(value)) = (args.next(), args.next()) {
+        map_insert(&mut entries, key, value);
+    }
+
+    Ok(Node::Map(entries))
+}
+
+/// `(map-get map key)` or `(map-get map key default)`, the default being
+/// null when not given.
+fn map_get(args: Vec<Node>) -> SlResult<Node> {
+    if !(2..=3).contains(&args.len()) {
+        return Err(SlError::arity_error(format!("map-get expects 2 or 3 arguments. Got {}.", args.len())));
+    }
+
+    let entries = expect_map("map-get", &args[0])?;
+    match map_position(entries, &args[1]) {
+        Some(i) => Ok(entries[i].1.clone()),
+        None => Ok(args.get(2).cloned().unwrap_or(Node::Null)),
+    }
+}
+
+/// Returns a copy of the map with `key` set to `value`.
+fn map_set(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("map-set", &args, 3)?;
+    let mut entries = expect_map("map-set", &args[0])?.clone();
+    let mut args = args.into_iter().skip(1);
+    let (Some(key), Some(value)) = (args.next(), args.next()) else { unreachable!() };
+    map_insert(&mut entries, key, value);
+
+    Ok(Node::Map(entries))
+}
+
+/// Returns a copy of the map without `key`.
+fn map_remove(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("map-remove", &args, 2)?;
+    let mut entries = expect_map("map-remove", &args[0])?.clone();
+    if let Some(i) = map_position(&entries, &args[1]) {
+        entries.remove(i);
+    }
+
+    Ok(Node::Map(entries))
+}
+
+fn map_has_key(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("has-key", &args, 2)?;
+    let entries = expect_map("has-key", &args[0])?;
+    Ok(Node::Bool(map_position(entries, &args[1]).is_some()))
+}
+
+fn map_keys(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("keys", &args, 1)?;
+    let entries = expect_map("keys", &args[0])?;
+    Ok(Node::List(entries.iter().map(|(key, _)| key.clone()).collect()))
+}
+
+fn map_values(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("values", &args, 1)?;
+    let entries = expect_map("values", &args[0])?;
+    Ok(Node::List(entries.iter().map(|(_, value)| value.clone()).collect()))
+}
+
+fn map_size(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("map-size", &args, 1)?;
+    let entries = expect_map("map-size", &args[0])?;
+    Ok(Node::Integer(entries.len() as i64))
+}
+
+fn load_map_module(natives: &mut Natives) {
+    // `map` is a calling native, as it also maps a function over a list.
+    natives.insert("map-get".to_string(), Box::new(map_get));
+    natives.insert("map-set".to_string(), Box::new(map_set));
+    natives.insert("map-remove".to_string(), Box::new(map_remove));
+    natives.insert("has-key".to_string(), Box::new(map_has_key));
+    natives.insert("keys".to_string(), Box::new(map_keys));
+    natives.insert("values".to_string(), Box::new(map_values));
+    natives.insert("map-size".to_string(), Box::new(map_size));
+}
+
+fn expect_function<'a>(name: &str, node: &'a Node) -> SlResult<&'a Node> {
+    match node {
+        Node::Function { .. } => Ok(node),
+        _ => Err(SlError::type_error(format!("{name} expects a function. Got {}.", node.type_name()))),
+    }
+}
+
+/// Splits the arguments of `(name function list)`.
+fn function_and_list(name: &str, args: Vec<Node>) -> SlResult<(Node, Vec<Node>)> {
+    expect_arity(name, &args, 2)?;
+    let mut args = args.into_iter();
+    let (Some(function), Some(list)) = (args.next(), args.next()) else { unreachable!() };
+    expect_function(name, &function)?;
+    let Node::List(list) = list else {
+        return Err(SlError::type_error(format!("{name} only accepts list. Got {}.", list.type_name())));
+    };
+
+    Ok((function, list))
+}
+
+fn call_predicate(visitor: &mut Visitor, name: &str, function: &Node, item: Node) -> SlResult<bool> {
+    match visitor.call_function(function, vec![item])? {
+        Node::Bool(b) => Ok(b),
+        other => Err(SlError::type_error(format!("{name} expects the function to return a bool. Got {}.", other.type_name()))),
+    }
+}
+
+/// Orders numbers and strings, as `sort` does.
+fn compare_nodes(name: &str, left: &Node, right: &Node) -> SlResult<std::cmp::Ordering> {
+    let ordering = match (left, right) {
+        (Node::Integer(i), Node::Integer(j)) => Some(i.cmp(j)),
+        (Node::Integer(i), Node::Float(f)) => (*i as f64).partial_cmp(f),
+        (Node::Float(f), Node::Integer(i)) => f.partial_cmp(&(*i as f64)),
+        (Node::Float(f), Node::Float(g)) => f.partial_cmp(g),
+        (Node::String(s), Node::String(t)) => Some(s.cmp(t)),
+        _ => None,
+    };
+
+    ordering.ok_or_else(|| SlError::type_error(format!("{name} can't compare a {} with a {}.", left.type_name(), right.type_name())))
+}
+
+/// Sorts `items` by their `keys`, keeping the order of equal ones.
+fn sort_by_keys(name: &str, items: Vec<Node>, keys: Vec<Node>) -> SlResult<Node> {
+    let mut pairs: Vec<(Node, Node)> = keys.into_iter().zip(items).collect();
+    let mut error = None;
+    pairs.sort_by(|(a, _), (b, _)| {
+        compare_nodes(name, a, b).unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            std::cmp::Ordering::Equal
+        })
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(Node::List(pairs.into_iter().map(|(_, item)| item).collect())),
+    }
+}
+
+/// `map` is both the higher-order function and the map constructor, told
+/// apart by the first argument: `(map function list)` when it is a function,
+/// `(map key value ...)` otherwise. A map whose first key is a function is
+/// built with `map-set`.
+fn functional_map(visitor: &mut Visitor, args: Vec<Node>) -> SlResult<Node> {
+    if !matches!(args.first(), Some(Node::Function { .. })) {
+        return map_new(args);
+    }
+
+    let (function, list) = function_and_list("map", args)?;
+    let mut result = vec![];
+    for item in list {
+        result.push(visitor.call_function(&function, vec![item])?);
+    }
+
+    Ok(Node::List(result))
+}
+
+fn functional_filter(visitor: &mut Visitor, args: Vec<Node>) -> SlResult<Node> {
+    let (function, list) = function_and_list("filter", args)?;
+    let mut result = vec![];
+    for item in list {
+        if call_predicate(visitor, "filter", &function, item.clone())? {
+            result.push(item);
+        }
+    }
+
+    Ok(Node::List(result))
+}
+
+/// `(reduce function list)` or `(reduce function initial list)`, the
+/// first element being the initial value when none is given.
+fn functional_reduce(visitor: &mut Visitor, args: Vec<Node>) -> SlResult<Node> {
+    let (function, initial, list) = match args.len() {
+        2 => {
+            let (function, list) = function_and_list("reduce", args)?;
+            let mut list = list.into_iter();
+            let Some(initial) = list.next() else {
+                return Err(SlError::value_error("reduce of an empty list with no initial value."));
+            };
+            (function, initial, list.collect())
+        },
+        3 => {
+            let mut args = args;
+            let list = args.pop().unwrap();
+            let initial = args.pop().unwrap();
+            let (function, list) = function_and_list("reduce", vec![args.pop().unwrap(), list])?;
+            (function, initial, list)
+        },
+        _ => return Err(SlError::arity_error(format!("reduce expects 2 or 3 arguments. Got {}.", args.len()))),
+    };
+
+    let mut accumulator = initial;
+    for item in list {
+        accumulator = visitor.call_function(&function, vec![accumulator, item])?;
+    }
+
+    Ok(accumulator)
+}
+
+fn list_sort(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("sort", &args, 1)?;
+    let list = expect_list("sort", &args[0])?.clone();
+    sort_by_keys("sort", list.clone(), list)
+}
+
+/// Sorts a list by the keys the function gives for its elements.
+fn functional_sort_by(visitor: &mut Visitor, args: Vec<Node>) -> SlResult<Node> {
+    let (function, list) = function_and_list("sort-by", args)?;
+    let mut keys = vec![];
+    for item in &list {
+        keys.push(visitor.call_function(&function, vec![item.clone()])?);
+    }
+
+    sort_by_keys("sort-by", list, keys)
+}
+
+/// The first element the function returns true for, or null. Given a
+/// string, the index of a string in it, see `string_find`.
+fn functional_find(visitor: &mut Visitor, args: Vec<Node>) -> SlResult<Node> {
+    if matches!(args.first(), Some(Node::String(_))) {
+        return string_find(args);
+    }
+
+    let (function, list) = function_and_list("find", args)?;
+    for item in list {
+        if call_predicate(visitor, "find", &function, item.clone())? {
+            return Ok(item);
+        }
+    }
+
+    Ok(Node::Null)
+}
+
+fn functional_any(visitor: &mut Visitor, args: Vec<Node>) -> SlResult<Node> {
+    let (function, list) = function_and_list("any", args)?;
+    for item in list {
+        if call_predicate(visitor, "any", &function, item)? {
+            return Ok(Node::Bool(true));
+        }
+    }
+
+    Ok(Node::Bool(false))
+}
+
+fn functional_all(visitor: &mut Visitor, args: Vec<Node>) -> SlResult<Node> {
+    let (function, list) = function_and_list("all", args)?;
+    for item in list {
+        if !call_predicate(visitor, "all", &function, item)? {
+            return Ok(Node::Bool(false));
+        }
+    }
+
+    Ok(Node::Bool(true))
+}
+
+/// Lists of the n-th elements of each list, as long as the shortest one.
+fn list_zip(args: Vec<Node>) -> SlResult<Node> {
+    let mut lists = vec![];
+    for arg in &args {
+        lists.push(expect_list("zip", arg)?);
+    }
+
+    let len = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+    Ok(Node::List((0..len).map(|i| Node::List(lists.iter().map(|list| list[i].clone()).collect())).collect()))
+}
+
+fn load_functional_module(natives: &mut CallingNatives) {
+    natives.insert("map".to_string(), functional_map);
+    natives.insert("filter".to_string(), functional_filter);
+    natives.insert("reduce".to_string(), functional_reduce);
+    natives.insert("sort-by".to_string(), functional_sort_by);
+    natives.insert("find".to_string(), functional_find);
+    natives.insert("any".to_string(), functional_any);
+    natives.insert("all".to_string(), functional_all);
+}
+
+fn expect_string<'a>(name: &str, node: &'a Node) -> SlResult<&'a str> {
+    match node {
+        Node::String(s) => Ok(s),
+        _ => Err(SlError::type_error(format!("{name} only accepts string. Got {}.", node.type_name()))),
+    }
+}
+
+/// A character index in `0..len`, or in `0..=len` when `end` is allowed.
+/// Strings are indexed by Unicode scalar values, not bytes.
+fn expect_char_index(name: &str, node: &Node, len: usize, end: bool) -> SlResult<usize> {
+    let Node::Integer(index) = node else {
+        return Err(SlError::type_error(format!("{name} only accepts integer indices. Got {}.", node.type_name())));
+    };
+
+    match usize::try_from(*index) {
+        Ok(i) if i < len || (end && i == len) => Ok(i),
+        _ => Err(SlError::index_error(format!("{name} out-of-bound access. string length: {len}, index provided: {index}"))),
+    }
+}
+
+fn string_len(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("str-len", &args, 1)?;
+    Ok(Node::Integer(expect_string("str-len", &args[0])?.chars().count() as i64))
+}
+
+/// `(substr string start)` or `(substr string start end)`, the end being
+/// excluded.
+fn string_substr(args: Vec<Node>) -> SlResult<Node> {
+    if !(2..=3).contains(&args.len()) {
+        return Err(SlError::arity_error(format!("substr expects 2 or 3 arguments. Got {}.", args.len())));
+    }
+
+    let string = expect_string("substr", &args[0])?;
+    let len = string.chars().count();
+    let start = expect_char_index("substr", &args[1], len, true)?;
+    let end = match args.get(2) {
+        Some(end) => expect_char_index("substr", end, len, true)?,
+        None => len,
+    };
+    if start > end {
+        return Err(SlError::index_error(format!("substr starts after its end. start: {start}, end: {end}")));
+    }
+
+    Ok(Node::String(string.chars().skip(start).take(end - start).collect()))
+}
+
+/// Splits around a separator, or into characters when it is empty.
+fn string_split(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("split", &args, 2)?;
+    let string = expect_string("split", &args[0])?;
+    let separator = expect_string("split", &args[1])?;
+
+    let parts = if separator.is_empty() {
+        string.chars().map(|c| Node::String(c.to_string())).collect()
+    } else {
+        string.split(separator).map(|part| Node::String(part.to_string())).collect()
+    };
+
+    Ok(Node::List(parts))
+}
+
+/// `(join list)` or `(join list separator)`, the elements being written as
+/// `write` does.
+fn string_join(args: Vec<Node>) -> SlResult<Node> {
+    if !(1..=2).contains(&args.len()) {
+        return Err(SlError::arity_error(format!("join expects 1 or 2 arguments. Got {}.", args.len())));
+    }
+
+    let list = expect_list("join", &args[0])?;
+    let separator = match args.get(1) {
+        Some(separator) => expect_string("join", separator)?,
+        None => "",
+    };
+
+    Ok(Node::String(list.iter().map(display).collect::<Vec<_>>().join(separator)))
+}
+
+/// The character index of the first occurrence of `needle`, or -1.
+fn string_find(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("find", &args, 2)?;
+    let string = expect_string("find", &args[0])?;
+    let needle = expect_string("find", &args[1])?;
+
+    match string.find(needle) {
+        Some(byte) => Ok(Node::Integer(string[..byte].chars().count() as i64)),
+        None => Ok(Node::Integer(-1)),
+    }
+}
+
+/// Replaces every occurrence of a non-empty string.
+fn string_replace(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("replace", &args, 3)?;
+    let string = expect_string("replace", &args[0])?;
+    let from = expect_string("replace", &args[1])?;
+    let to = expect_string("replace", &args[2])?;
+    if from.is_empty() {
+        return Err(SlError::value_error("replace can't replace an empty string."));
+    }
+
+    Ok(Node::String(string.replace(from, to)))
+}
+
+fn string_upper(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("upper", &args, 1)?;
+    Ok(Node::String(expect_string("upper", &args[0])?.to_uppercase()))
+}
+
+fn string_lower(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("lower", &args, 1)?;
+    Ok(Node::String(expect_string("lower", &args[0])?.to_lowercase()))
+}
+
+/// Removes the Unicode white space at both ends.
+fn string_trim(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("trim", &args, 1)?;
+    Ok(Node::String(expect_string("trim", &args[0])?.trim().to_string()))
+}
+
+fn string_starts_with(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("starts-with", &args, 2)?;
+    let string = expect_string("starts-with", &args[0])?;
+    Ok(Node::Bool(string.starts_with(expect_string("starts-with", &args[1])?)))
+}
+
+fn string_ends_with(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("ends-with", &args, 2)?;
+    let string = expect_string("ends-with", &args[0])?;
+    Ok(Node::Bool(string.ends_with(expect_string("ends-with", &args[1])?)))
+}
+
+/// The character at an index, as a string of one character.
+fn string_char_at(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("char-at", &args, 2)?;
+    let string = expect_string("char-at", &args[0])?;
+    let index = expect_char_index("char-at", &args[1], string.chars().count(), false)?;
+    Ok(Node::String(string.chars().nth(index).unwrap().to_string()))
+}
+
+/// Parses a string, or truncates a float, into an integer. Strings which
+/// aren't integers give null.
+fn string_to_int(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("to-int", &args, 1)?;
+    match &args[0] {
+        Node::String(s) => Ok(s.parse::<i64>().map_or(Node::Null, Node::Integer)),
+        Node::Integer(i) => Ok(Node::Integer(*i)),
+        Node::Float(f) => Ok(Node::Integer(*f as i64)),
+        other => Err(SlError::type_error(format!("to-int can't convert a {}.", other.type_name()))),
+    }
+}
+
+/// Parses a string, or converts an integer, into a float. Strings which
+/// aren't numbers give null.
+fn string_to_float(args: Vec<Node>) -> SlResult<Node> {
+    expect_arity("to-float", &args, 1)?;
+    match &args[0] {
+        Node::String(s) => Ok(s.parse::<f64>().map_or(Node::Null, Node::Float)),
+        Node::Integer(i) => Ok(Node::Float(*i as f64)),
+        Node::Float(f) => Ok(Node::Float(*f)),
+        other => Err(SlError::type_error(format!("to-float can't convert a {}.", other.type_name()))),
+    }
+}
+
+/// A `{index:<fill><align>0<width>.<precision>}` placeholder of `format`,
+/// every part being optional. `0` pads numbers with zeros after their sign.
+struct Placeholder {
+    index: Option<usize>,
+    fill: char,
+    align: Option<char>,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+fn parse_placeholder(spec: &str) -> SlResult<Placeholder> {
+    let invalid = || SlError::value_error(format!("format has an invalid placeholder: {{{spec}}}"));
+    let number = |digits: &str| digits.parse::<usize>().map_err(|_| invalid());
+
+    let (index, options) = spec.split_once(':').unwrap_or((spec, ""));
+    let index = if index.is_empty() { None } else { Some(number(index)?) };
+
+    let mut placeholder = Placeholder { index, fill: ' ', align: None, zero: false, width: 0, precision: None };
+    let mut chars = options.chars();
+    let rest = match (chars.next(), chars.next()) {
+        (Some(fill), Some(align @ ('<' | '^' | '>'))) => {
+            placeholder.fill = fill;
+            placeholder.align = Some(align);
+            chars.as_str()
+        },
+        (Some(align @ ('<' | '^' | '>')), _) => {
+            placeholder.align = Some(align);
+            &options[1..]
+        },
+        _ => options,
+    };
+    let rest = match rest.strip_prefix('0') {
+        Some(rest) => {
+            placeholder.zero = true;
+            rest
+        },
+        None => rest,
+    };
+
+    let (width, precision) = rest.split_once('.').map_or((rest, None), |(w, p)| (w, Some(p)));
+    if !width.is_empty() {
+        placeholder.width = number(width)?;
+    }
+    if let Some(precision) = precision {
+        placeholder.precision = Some(number(precision)?);
+    }
+
+    Ok(placeholder)
+}
+
+/// Formats one argument. A precision rounds numbers, integers included, and
+/// cuts anything else to that many characters.
+fn format_value(value: &Node, placeholder: &Placeholder) -> String {
+    let text = match (value, placeholder.precision) {
+        (Node::Integer(i), Some(precision)) => format!("{:.precision$}", *i as f64),
+        (Node::Float(f), Some(precision)) => format!("{f:.precision$}"),
+        (_, Some(precision)) => display(value).chars().take(precision).collect(),
+        (_, None) => display(value),
+    };
+
+    let len = text.chars().count();
+    if len >= placeholder.width {
+        return text;
+    }
+
+    // Numbers are aligned to the right by default, as in Rust.
+    let padding = placeholder.width - len;
+    let numeric = matches!(value, Node::Integer(_) | Node::Float(_));
+    if placeholder.zero && numeric {
+        let (sign, digits) = text.split_at(if text.starts_with('-') { 1 } else { 0 });
+        return format!("{sign}{}{digits}", "0".repeat(padding));
+    }
+    let (left, right) = match placeholder.align {
+        Some('>') => (padding, 0),
+        Some('^') => (padding / 2, padding - padding / 2),
+        Some(_) => (0, padding),
+        None if numeric => (padding, 0),
+        None => (0, padding),
+    };
+    let fill = |count| placeholder.fill.to_string().repeat(count);
+
+    format!("{}{text}{}", fill(left), fill(right))
+}
+
+/// `(format "{} is {:>5.2}" a b)`. `{}` takes the next argument and `{0}` a
+/// given one, `{{` and `}}` are literal braces.
+fn string_format(args: Vec<Node>) -> SlResult<Node> {
+    let Some((template, values)) = args.split_first() else {
+        return Err(SlError::arity_error("format expects at least 1 argument. Got 0."));
+    };
+    let template = expect_string("format", template)?;
+
+    let mut output = String::new();
+    let mut next = 0;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                output.push('{');
+            },
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                output.push('}');
+            },
+            '{' => {
+                let Some((spec, rest)) = chars.as_str().split_once('}') else {
+                    return Err(SlError::value_error("format has an unclosed placeholder."));
+                };
+                let placeholder = parse_placeholder(spec)?;
+                chars = rest.chars();
+
+                let index = placeholder.index.unwrap_or_else(|| {
+                    next += 1;
+                    next - 1
+                });
+                let Some(value) = values.get(index) else {
+                    return Err(SlError::index_error(format!("format has no argument {index}. Got {} argument(s).", values.len())));
+                };
+                output.push_str(&format_value(value, &placeholder));
+            },
+            '}' => return Err(SlError::value_error("format has an unmatched '}'.")),
+            c => output.push(c),
+        }
+    }
+
+    Ok(Node::String(output))
+}
+
+fn load_string_module(natives: &mut Natives) {
+    natives.insert("str-len".to_string(), Box::new(string_len));
+    natives.insert("substr".to_string(), Box::new(string_substr));
+    natives.insert("split".to_string(), Box::new(string_split));
+    natives.insert("join".to_string(), Box::new(string_join));
+    // `find` is a calling native, as it also finds in a list.
+    natives.insert("replace".to_string(), Box::new(string_replace));
+    natives.insert("upper".to_string(), Box::new(string_upper));
+    natives.insert("lower".to_string(), Box::new(string_lower));
+    natives.insert("trim".to_string(), Box::new(string_trim));
+    natives.insert("starts-with".to_string(), Box::new(string_starts_with));
+    natives.insert("ends-with".to_string(), Box::new(string_ends_with));
+    natives.insert("char-at".to_string(), Box::new(string_char_at));
+    natives.insert("to-int".to_string(), Box::new(string_to_int));
+    natives.insert("to-float".to_string(), Box::new(string_to_float));
+    natives.insert("format".to_string(), Box::new(string_format));
+}
+
+fn is_null(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
         let Node::Null = a else {
-            return Node::Bool(false);
+            return Ok(Node::Bool(false));
         };
     }
-    Node::Bool(args.len() > 0)
+    Ok(Node::Bool(!args.is_empty()))
 }
 
-fn is_int(args: Vec<Node>) -> Node {
+fn is_int(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
         let Node::Integer(_) = a else {
-            return Node::Bool(false);
+            return Ok(Node::Bool(false));
         };
     }
-    Node::Bool(args.len() > 0)
+    Ok(Node::Bool(!args.is_empty()))
 }
 
-fn is_float(args: Vec<Node>) -> Node {
+fn is_float(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
         let Node::Float(_) = a else {
-            return Node::Bool(false);
+            return Ok(Node::Bool(false));
         };
     }
-    Node::Bool(args.len() > 0)
+    Ok(Node::Bool(!args.is_empty()))
 }
 
-fn is_string(args: Vec<Node>) -> Node {
+fn is_string(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
         let Node::String(_) = a else {
-            return Node::Bool(false);
+            return Ok(Node::Bool(false));
         };
     }
-    Node::Bool(args.len() > 0)
+    Ok(Node::Bool(!args.is_empty()))
 }
 
-fn is_bool(args: Vec<Node>) -> Node {
+fn is_bool(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
         let Node::Bool(_) = a else {
-            return Node::Bool(false);
+            return Ok(Node::Bool(false));
         };
     }
-    Node::Bool(args.len() > 0)
+    Ok(Node::Bool(!args.is_empty()))
 }
 
-fn is_list(args: Vec<Node>) -> Node {
+fn is_list(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
         let Node::List(_) = a else {
-            return Node::Bool(false);
+            return Ok(Node::Bool(false));
         };
     }
-    Node::Bool(args.len() > 0)
+    Ok(Node::Bool(!args.is_empty()))
 }
 
-fn is_instance(args: Vec<Node>) -> Node {
+fn is_map(args: Vec<Node>) -> SlResult<Node> {
+    for a in &args {
+        let Node::Map(_) = a else {
+            return Ok(Node::Bool(false));
+        };
+    }
+    Ok(Node::Bool(!args.is_empty()))
+}
+
+fn is_symbol(args: Vec<Node>) -> SlResult<Node> {
+    for a in &args {
+        let Node::Symbol(_) = a else {
+            return Ok(Node::Bool(false));
+        };
+    }
+    Ok(Node::Bool(!args.is_empty()))
+}
+
+fn is_instance(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
         let Node::Instance { .. } = a else {
-            return Node::Bool(false);
+            return Ok(Node::Bool(false));
         };
     }
-    Node::Bool(args.len() > 0)
+    Ok(Node::Bool(!args.is_empty()))
 }
 
-fn is_function(args: Vec<Node>) -> Node {
+fn is_function(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
         let Node::Function { .. } = a else {
-            return Node::Bool(false);
+            return Ok(Node::Bool(false));
         };
     }
-    Node::Bool(args.len() > 0)
+    Ok(Node::Bool(!args.is_empty()))
 }
 
-fn load_type_module(natives: &mut HashMap<String, Box<dyn Fn(Vec<Node>) -> Node>>) {
+fn load_type_module(natives: &mut Natives) {
     natives.insert("is-null".to_string(), Box::new(is_null));
     natives.insert("is-int".to_string(), Box::new(is_int));
     natives.insert("is-float".to_string(), Box::new(is_float));
     natives.insert("is-string".to_string(), Box::new(is_string));
     natives.insert("is-bool".to_string(), Box::new(is_bool));
     natives.insert("is-list".to_string(), Box::new(is_list));
+    natives.insert("is-map".to_string(), Box::new(is_map));
+    natives.insert("is-symbol".to_string(), Box::new(is_symbol));
     natives.insert("is-instance".to_string(), Box::new(is_instance));
     natives.insert("is-function".to_string(), Box::new(is_function));
 }
 
-fn print(args: Vec<Node>) -> Node {
-    write(args);
-    println!("");
-    Node::Null
+fn print(args: Vec<Node>) -> SlResult<Node> {
+    write(args)?;
+    println!();
+    Ok(Node::Null)
 }
 
-fn write(args: Vec<Node>) -> Node {
+fn write(args: Vec<Node>) -> SlResult<Node> {
     for a in &args {
-        match a {
-            Node::Integer(i) => print!("{i}"),
-            Node::Float(f) => print!("{f}"),
-            Node::Bool(b) => print!("{b}"),
-            Node::String(s) => print!("{}", unescaper::unescape(s).unwrap()),
-            Node::Null => print!("null"),
-            _ => print!("<error>"),
-        };
+        print!("{}", display(a));
     }
     std::io::stdout().flush().unwrap();
 
-    Node::Null
+    Ok(Node::Null)
 }
 
-fn read(_args: Vec<Node>) -> Node {
-    let mut ret = String::new();
+/// How `write` shows a value. Lists and maps are shown like the C++ runtime
+/// does, `[ 1 2 ]` and `{ key value }`.
+fn display(node: &Node) -> String {
+    match node {
+        Node::Integer(i) => i.to_string(),
+        Node::Float(f) => f.to_string(),
+        Node::Bool(b) => b.to_string(),
+        Node::Symbol(s) | Node::String(s) => s.clone(),
+        Node::Null => "null".to_string(),
+        Node::List(items) => {
+            let mut output = "[ ".to_string();
+            for item in items {
+                output.push_str(&display(item));
+                output.push(' ');
+            }
+            output.push(']');
+            output
+        },
+        Node::Map(entries) => {
+            let mut output = "{ ".to_string();
+            for (key, value) in entries {
+                output.push_str(&format!("{} {} ", display(key), display(value)));
+            }
+            output.push('}');
+            output
+        },
+        _ => "<error>".to_string(),
+    }
+}
 
-    std::io::stdin()
-        .read_line(&mut ret)
-        .expect("Failed to read line");
+fn read_line() -> SlResult<String> {
+    let mut ret = String::new();
 
-    Node::String(ret.trim().to_string())
+    match std::io::stdin().read_line(&mut ret) {
+        Ok(_) => Ok(ret),
+        Err(e) => Err(SlError::io_error(format!("Failed to read line: {e}"))),
+    }
 }
 
-fn read_int(_args: Vec<Node>) -> Node {
-    let mut ret = String::new();
+fn read(_args: Vec<Node>) -> SlResult<Node> {
+    let ret = read_line()?;
+
+    Ok(Node::String(ret.trim().to_string()))
+}
 
-    std::io::stdin()
-        .read_line(&mut ret)
-        .expect("Failed to read line");
+fn read_int(_args: Vec<Node>) -> SlResult<Node> {
+    let ret = read_line()?;
 
-    match ret.trim().parse::<i32>() {
-        Ok(i) => Node::Integer(i),
+    match ret.trim().parse::<i64>() {
+        Ok(i) => Ok(Node::Integer(i)),
         Err(e) => {
             println!("{:?}", e);
-            Node::Null
+            Ok(Node::Null)
         },
     }
 }
 
-fn read_float(_args: Vec<Node>) -> Node {
-    let mut ret = String::new();
-
-    std::io::stdin()
-        .read_line(&mut ret)
-        .expect("Failed to read line");
+fn read_float(_args: Vec<Node>) -> SlResult<Node> {
+    let ret = read_line()?;
 
-    match ret.parse::<f32>() {
-        Ok(i) => Node::Float(i),
-        Err(_) => Node::Null,
+    match ret.trim().parse::<f64>() {
+        Ok(i) => Ok(Node::Float(i)),
+        Err(_) => Ok(Node::Null),
     }
 }
 
-fn load_io_module(natives: &mut HashMap<String, Box<dyn Fn(Vec<Node>) -> Node>>) {
+fn load_io_module(natives: &mut Natives) {
     natives.insert("print".to_string(), Box::new(print));
     natives.insert("write".to_string(), Box::new(write));
     natives.insert("read".to_string(), Box::new(read));
@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::Visitor;
+use crate::span::Span;
+use crate::{Env, Node, Param};
+
+/// Nested expansions allowed before a macro is taken as endlessly expanding
+/// to itself.
+const MAX_DEPTH: usize = 256;
+
+/// Takes the top-level `defmacro`s out of a file and replaces the calls to
+/// them by their expansion. A macro can be used by the forms following its
+/// definition, including later macros. Macros are run by the interpreter,
+/// taking their arguments as data (calls as lists, identifiers as symbols)
+/// and returning the code to use instead, which is expanded again.
+pub fn expand_macros(nodes: Vec<Node>) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    let mut expander = Expander::default();
+    let mut expanded = vec![];
+    let mut diagnostics = vec![];
+
+    for node in nodes {
+        match node {
+            Node::Call { name, args, span } if name == "defmacro" => {
+                match expander.define(&args[0], span) {
+                    Ok(()) => {},
+                    Err(d) => diagnostics.push(d),
+                }
+            },
+            _ => match expander.expand(&node, 0) {
+                Ok(node) => expanded.push(node),
+                Err(d) => diagnostics.push(d),
+            },
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(expanded)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+#[derive(Default)]
+struct Expander {
+    macros: HashSet<String>,
+    /// Only created once a file defines a macro.
+    visitor: Option<Visitor>,
+}
+
+impl Expander {
+    fn define(&mut self, function: &Node, span: Span) -> Result<(), Diagnostic> {
+        let Node::Function { name, .. } = function else { unreachable!() };
+        if name.is_empty() {
+            return Err(Diagnostic::new("`defmacro` expects a name", span));
+        }
+
+        // The body can use the macros defined before.
+        let function = self.expand(function, 0)?;
+        let visitor = self.visitor.get_or_insert_with(Visitor::new);
+        visitor.define_macro(&function)
+            .map_err(|e| Diagnostic::new(format!("can't define macro '{name}': {}: {}", e.kind, e.message), span))?;
+        self.macros.insert(name.clone());
+
+        Ok(())
+    }
+
+    fn expand(&mut self, node: &Node, depth: usize) -> Result<Node, Diagnostic> {
+        let node = match node {
+            Node::Call { name, span, .. } if name == "defmacro" => {
+                return Err(Diagnostic::new("`defmacro` can only be used at top level", *span));
+            },
+            // Quoted code is data, left as written.
+            Node::Call { name, .. } if name == "quote" || name == "quasiquote" => node.clone(),
+            Node::Call { name, args, span } if self.macros.contains(name) => {
+                if depth >= MAX_DEPTH {
+                    return Err(Diagnostic::new(format!("the expansion of macro '{name}' doesn't end"), *span));
+                }
+
+                let visitor = self.visitor.as_mut().unwrap();
+                let expansion = visitor.expand_macro(name, args.iter().map(to_data).collect())
+                    .map_err(|e| Diagnostic::new(format!("in the expansion of macro '{name}': {}: {}", e.kind, e.message), *span))?;
+                let code = to_code(&expansion, *span)
+                    .map_err(|message| Diagnostic::new(format!("macro '{name}' expands to invalid code: {message}"), *span))?;
+
+                return self.expand(&code, depth + 1);
+            },
+            Node::Call { name, args, span } => Node::Call {
+                name: name.clone(),
+                args: self.expand_all(args, depth)?,
+                span: *span,
+            },
+            Node::Function { name, params, body, env, span } => {
+                let mut expanded = vec![];
+                for p in params {
+                    expanded.push(Param {
+                        name: p.name.clone(),
+                        default_value: match &p.default_value {
+                            Some(value) => Some(self.expand(value, depth)?),
+                            None => None,
+                        },
+                        rest: p.rest,
+                    });
+                }
+
+                Node::Function {
+                    name: name.clone(),
+                    params: expanded,
+                    body: self.expand_all(body, depth)?,
+                    env: env.clone(),
+                    span: *span,
+                }
+            },
+            Node::List(items) => Node::List(self.expand_all(items, depth)?),
+            Node::Map(entries) => {
+                let mut expanded = vec![];
+                for (key, value) in entries {
+                    expanded.push((self.expand(key, depth)?, self.expand(value, depth)?));
+                }
+                Node::Map(expanded)
+            },
+            _ => node.clone(),
+        };
+
+        Ok(node)
+    }
+
+    fn expand_all(&mut self, nodes: &[Node], depth: usize) -> Result<Vec<Node>, Diagnostic> {
+        nodes.iter().map(|n| self.expand(n, depth)).collect()
+    }
+}
+
+/// Code as a macro sees it: `(name args...)` lists of symbols, the way
+/// `quote` would read it.
+fn to_data(node: &Node) -> Node {
+    match node {
+        Node::Call { name, args, .. } => {
+            let mut items = vec![Node::Symbol(name.clone())];
+            if let ("let", Some(Node::Map(bindings))) = (name.as_str(), args.first()) {
+                let bindings = bindings.iter().map(|(p, v)| Node::List(vec![to_data(p), to_data(v)])).collect();
+                items.push(Node::List(bindings));
+                items.extend(args[1..].iter().map(to_data));
+                return Node::List(items);
+            }
+            match name.as_str() {
+                // Their argument already is data.
+                "quote" | "quasiquote" => items.extend(args.iter().cloned()),
+                _ => items.extend(args.iter().map(to_data)),
+            }
+            Node::List(items)
+        },
+        Node::Function { name, params, body, .. } => {
+            let mut items = vec![Node::Symbol("fun".to_string())];
+            if !name.is_empty() {
+                items.push(Node::Symbol(name.clone()));
+            }
+
+            let mut data_params = vec![];
+            for p in params {
+                if p.rest {
+                    data_params.push(Node::Symbol("&rest".to_string()));
+                }
+                match &p.default_value {
+                    Some(value) => data_params.push(Node::List(vec![Node::Symbol(p.name.clone()), to_data(value)])),
+                    None => data_params.push(Node::Symbol(p.name.clone())),
+                }
+            }
+            items.push(Node::List(data_params));
+            items.extend(body.iter().map(to_data));
+
+            Node::List(items)
+        },
+        Node::Identifier(name, _) => Node::Symbol(name.clone()),
+        Node::List(items) => Node::List(items.iter().map(to_data).collect()),
+        Node::Map(entries) => Node::Map(entries.iter().map(|(k, v)| (to_data(k), to_data(v))).collect()),
+        _ => node.clone(),
+    }
+}
+
+/// Turns the data a macro returned back into code, the reverse of
+/// `to_data`. Lists starting with a symbol are calls, the other ones are
+/// list literals.
+fn to_code(node: &Node, span: Span) -> Result<Node, String> {
+    let code = match node {
+        Node::List(items) => match items.split_first() {
+            Some((Node::Symbol(name), args)) => call_to_code(name, args, span)?,
+            _ => Node::List(all_to_code(items, span)?),
+        },
+        Node::Symbol(name) => Node::Identifier(name.clone(), span),
+        Node::Map(entries) => {
+            let mut code = vec![];
+            for (key, value) in entries {
+                code.push((to_code(key, span)?, to_code(value, span)?));
+            }
+            Node::Map(code)
+        },
+        Node::Function { .. } | Node::Instance { .. } => {
+            return Err(format!("a {} is not code", node.type_name()));
+        },
+        _ => node.clone(),
+    };
+
+    Ok(code)
+}
+
+fn call_to_code(name: &str, args: &[Node], span: Span) -> Result<Node, String> {
+    let args = match name {
+        "fun" => return function_to_code(args, span),
+        "quote" => args.to_vec(),
+        "quasiquote" => args.iter().map(|a| unquotes_to_code(a, span)).collect::<Result<_, _>>()?,
+        // `(let ((a 1)) ...)` as written in a template, the parser reads
+        // the bindings as a map. A list pattern of pairs looks the same
+        // and is taken as bindings too.
+        "let" if is_bindings(args.first()) => {
+            let Some(Node::List(bindings)) = args.first() else { unreachable!() };
+            let mut code = vec![];
+            for binding in bindings {
+                let Node::List(pair) = binding else { unreachable!() };
+                code.push((to_code(&pair[0], span)?, to_code(&pair[1], span)?));
+            }
+            let mut args = all_to_code(&args[1..], span)?;
+            args.insert(0, Node::Map(code));
+            args
+        },
+        // Clauses are lists of code, not calls.
+        "cond" => args.iter().map(|clause| match clause {
+            Node::List(items) => Ok(Node::List(all_to_code(items, span)?)),
+            _ => to_code(clause, span),
+        }).collect::<Result<_, _>>()?,
+        "match" if !args.is_empty() => {
+            let mut code = vec![to_code(&args[0], span)?];
+            for clause in &args[1..] {
+                code.push(match clause {
+                    Node::List(items) if !items.is_empty() => {
+                        let mut items_code = vec![pattern_to_code(&items[0], span)?];
+                        items_code.extend(all_to_code(&items[1..], span)?);
+                        Node::List(items_code)
+                    },
+                    _ => to_code(clause, span)?,
+                });
+            }
+            code
+        },
+        _ => all_to_code(args, span)?,
+    };
+
+    Ok(Node::Call { name: name.to_string(), args, span })
+}
+
+/// A pattern of `match` in a template. The data of `[a b]` and `(point a b)`
+/// is the same, so only a list with a `:field` is taken as a class pattern.
+fn pattern_to_code(node: &Node, span: Span) -> Result<Node, String> {
+    match node {
+        Node::List(items) => match items.split_first() {
+            Some((Node::Symbol(name), fields)) if fields.iter().any(|f| matches!(f, Node::Symbol(f) if f.starts_with(':'))) => {
+                let mut args = vec![];
+                let mut fields = fields.iter();
+                while let Some(field) = fields.next() {
+                    args.push(to_code(field, span)?);
+                    if matches!(field, Node::Symbol(f) if f.starts_with(':')) {
+                        if let Some(pattern) = fields.next() {
+                            args.push(pattern_to_code(pattern, span)?);
+                        }
+                    }
+                }
+                Ok(Node::Call { name: name.clone(), args, span })
+            },
+            _ => Ok(Node::List(items.iter().map(|p| pattern_to_code(p, span)).collect::<Result<_, _>>()?)),
+        },
+        _ => to_code(node, span),
+    }
+}
+
+fn is_bindings(node: Option<&Node>) -> bool {
+    match node {
+        Some(Node::List(bindings)) => !bindings.is_empty() && bindings.iter().all(|b| matches!(b, Node::List(pair) if pair.len() == 2)),
+        _ => false,
+    }
+}
+
+/// Keeps a quasiquote template as data, except for what it unquotes.
+fn unquotes_to_code(node: &Node, span: Span) -> Result<Node, String> {
+    match node {
+        Node::List(items) => match items.as_slice() {
+            [Node::Symbol(name), arg] if name == "unquote" || name == "unquote-splicing" => {
+                Ok(Node::Call { name: name.clone(), args: vec![to_code(arg, span)?], span })
+            },
+            _ => Ok(Node::List(items.iter().map(|i| unquotes_to_code(i, span)).collect::<Result<_, _>>()?)),
+        },
+        _ => Ok(node.clone()),
+    }
+}
+
+fn function_to_code(args: &[Node], span: Span) -> Result<Node, String> {
+    let (name, args) = match args.split_first() {
+        Some((Node::Symbol(name), args)) => (name.clone(), args),
+        _ => (String::new(), args),
+    };
+    let Some((Node::List(data_params), body)) = args.split_first() else {
+        return Err("`fun` expects a list of parameters".to_string());
+    };
+
+    let mut params = vec![];
+    let mut data_params = data_params.iter();
+    while let Some(param) = data_params.next() {
+        match param {
+            Node::Symbol(rest) if rest == "&rest" => {
+                let (Some(Node::Symbol(name)), None) = (data_params.next(), data_params.next()) else {
+                    return Err("`&rest` must be followed by a single parameter name".to_string());
+                };
+                params.push(Param { name: name.clone(), default_value: None, rest: true });
+            },
+            Node::Symbol(name) => params.push(Param { name: name.clone(), default_value: None, rest: false }),
+            Node::List(items) => match items.as_slice() {
+                [Node::Symbol(name), value] => params.push(Param {
+                    name: name.clone(),
+                    default_value: Some(to_code(value, span)?),
+                    rest: false,
+                }),
+                _ => return Err("parameters with a default value are written (name value)".to_string()),
+            },
+            _ => return Err(format!("a parameter can't be a {}", param.type_name())),
+        }
+    }
+
+    Ok(Node::Function {
+        name,
+        params,
+        body: all_to_code(body, span)?,
+        env: Env::default(),
+        span,
+    })
+}
+
+fn all_to_code(nodes: &[Node], span: Span) -> Result<Vec<Node>, String> {
+    nodes.iter().map(|n| to_code(n, span)).collect()
+}
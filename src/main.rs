@@ -1,10 +1,17 @@
 use std::path::Path;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 mod node;
 use node::*;
 
+mod error;
+mod span;
+mod diagnostics;
+
 mod parser;
+mod macros;
+mod cst;
+mod formatter;
 
 mod interpreter;
 use interpreter::*;
@@ -16,9 +23,14 @@ use emitter::*;
 /// Compiles simple list .sl files
 #[derive(Parser, Debug)]
 #[command(author = None, version = None, about = None, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Input file
-    input: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input file, after `--` when it is named like a subcommand (`-- fmt`)
+    #[arg(required = true)]
+    input: Option<String>,
 
     /// Compile
     #[arg(short, long)]
@@ -33,19 +45,54 @@ struct Args {
     qbe: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rewrites files in the canonical indentation style
+    Fmt {
+        /// Files to format
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// Only list the files that are not formatted, and fail if any
+        #[arg(long)]
+        check: bool,
+    },
+}
+
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
 
+    if let Some(Command::Fmt { files, check }) = &args.command {
+        if !formatter::format_files(files, *check) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let input = args.input.unwrap();
     if args.compile {
-        generator::generate(&args.input);
+        generator::generate(&input);
     } else if args.qbe {
-        compiler::generate(&args.input);
+        compiler::generate(&input);
     } else if args.emit {
-        emit(&args.input);
+        emit(&input);
     } else {
+        // The interpreter adds the extension too, but the file has to exist
+        // to be canonicalized.
+        let input = if input.ends_with(".sl") { input } else { format!("{input}.sl") };
+        let path = match Path::new(&input).canonicalize() {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("io error: {input}: {e}");
+                std::process::exit(1);
+            },
+        };
+
         let mut visitor = Visitor::new();
-        let path = Path::new(&args.input).canonicalize().unwrap();
-        visitor.interpret(path.to_str().unwrap());
+        if let Err(e) = visitor.interpret(path.to_str().unwrap()) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
     }
 
     Ok(())
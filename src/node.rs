@@ -1,10 +1,37 @@
 use std::collections::HashMap;
 use std::fmt;
+use crate::interpreter::ScopeRef;
+use crate::span::Span;
 
 #[derive(Debug, Clone)]
 pub struct Param {
     pub name: String,
     pub default_value: Option<Node>,
+    /// Set for the parameter following `&rest`, which gets the list of the
+    /// remaining arguments.
+    pub rest: bool,
+}
+
+/// Parts of a `match` clause, `(pattern :when guard body...)` where the
+/// guard is optional.
+pub fn match_clause(clause: &[Node]) -> Option<(&Node, Option<&Node>, &[Node])> {
+    match clause {
+        [pattern, Node::Identifier(when, _), guard, body @ ..] if when == ":when" => Some((pattern, Some(guard), body)),
+        [pattern, body @ ..] => Some((pattern, None, body)),
+        [] => None,
+    }
+}
+
+/// Scopes a function closes over, outermost first. It is empty for the
+/// functions of the AST, the interpreter fills it when it evaluates them.
+#[derive(Clone, Default)]
+pub struct Env(pub Vec<ScopeRef>);
+
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The scopes usually contain the function itself.
+        write!(f, "Env({} scopes)", self.0.len())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -13,6 +40,8 @@ pub enum Node {
         name: String,
         params: Vec<Param>,
         body: Vec<Node>,
+        env: Env,
+        span: Span,
     },
     Instance {
         class: String,
@@ -21,25 +50,56 @@ pub enum Node {
     Call {
         name: String,
         args: Vec<Node>,
+        span: Span,
     },
-    Integer(i32),
-    Float(f32),
+    Integer(i64),
+    Float(f64),
     String(String),
-    Identifier(String),
+    Identifier(String, Span),
+    Symbol(String),
     Bool(bool),
     List(Vec<Node>),
+    Map(Vec<(Node, Node)>),
     Null,
 }
 
+impl Node {
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Function { span, .. } => *span,
+            Node::Call { span, .. } => *span,
+            Node::Identifier(_, span) => *span,
+            _ => Span::default(),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Node::Function { .. } => "function",
+            Node::Instance { .. } => "instance",
+            Node::Call { .. } => "call",
+            Node::Integer(_) => "int",
+            Node::Float(_) => "float",
+            Node::String(_) => "string",
+            Node::Identifier(..) => "identifier",
+            Node::Symbol(_) => "symbol",
+            Node::Bool(_) => "bool",
+            Node::List(_) => "list",
+            Node::Map(_) => "map",
+            Node::Null => "null",
+        }
+    }
+}
+
 impl fmt::Display for Node {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Node::Integer(i) => write!(f, "Value({i})"),
-            Node::Float(f2) => write!(f, "Value({f2})"),
+            Node::Integer(i) => write!(f, "Value({})", crate::generator::cpp_integer(*i)),
+            Node::Float(f2) => write!(f, "Value({f2:?})"),
             Node::Bool(b) => write!(f, "Value({b})"),
-            Node::String(s) => write!(f, "Value(\"{s}\")"),
+            Node::String(s) => write!(f, "Value(\"{}\"s)", crate::generator::escape_string(s)),
             Node::Null => write!(f, "Value()"),
-            Node::Identifier(id) => write!(f, "Value({id})"),
+            Node::Identifier(id, _) => write!(f, "Value({id})"),
             _ => panic!("switch: {:?}", self),
         }
     }
@@ -1,126 +1,558 @@
 use crate::*;
+use crate::diagnostics::Diagnostic;
+use crate::span::Span;
 use pest::iterators::Pair;
 use pest::iterators::Pairs;
 
-pub fn parse_block(pairs: Pairs<Rule>) -> std::io::Result<Vec<Node>> {
+pub type ParseResult<T> = Result<T, Diagnostic>;
+
+pub fn parse_block(pairs: Pairs<Rule>, file: usize) -> Result<Vec<Node>, Vec<Diagnostic>> {
     let mut nodes = vec![];
+    let mut diagnostics = vec![];
 
     for pair in pairs {
         if pair.as_rule() == Rule::sexp {
-            nodes.push(parse_root_expression(pair)?);
+            match parse_root_expression(pair, file) {
+                Ok(node) => nodes.push(node),
+                Err(d) => diagnostics.push(d),
+            }
         } else if pair.as_rule() != Rule::EOI {
             panic!("{:?} / {:?}", pair, pair.as_rule());
         }
     }
 
-    Ok(nodes)
+    if diagnostics.is_empty() {
+        Ok(nodes)
+    } else {
+        Err(diagnostics)
+    }
 }
 
-pub fn parse_root_expression(pair: Pair<Rule>) -> std::io::Result<Node> {
+pub fn parse_root_expression(pair: Pair<Rule>, file: usize) -> ParseResult<Node> {
+    let span = Span::from_pest(file, pair.as_span());
     let inner: Vec<_> = pair.into_inner().collect();
 
-    let node = match inner[0].as_rule() {
-        Rule::identifier => parse_identifier(inner)?,
-        _ => panic!("{:?}", inner[0]),
-    };
-
-    Ok(node)
+    match inner.first() {
+        Some(first) if first.as_rule() != Rule::identifier => {
+            Err(Diagnostic::new(format!("expected a function name, found `{}`", first.as_str()), span))
+        },
+        _ => parse_identifier(inner, span, file),
+    }
 }
 
-pub fn parse_identifier(elements: Vec<Pair<Rule>>) -> std::io::Result<Node> {
+pub fn parse_identifier(elements: Vec<Pair<Rule>>, span: Span, file: usize) -> ParseResult<Node> {
+    if elements.is_empty() {
+        return Err(Diagnostic::new("expected a function name, found `()`", span));
+    }
+
     let node = match elements[0].as_str() {
-        "fun" => parse_function(elements)?,
-        _ => parse_function_call(elements)?,
+        "fun" => parse_function(elements, span, file)?,
+        "quote" | "quasiquote" => parse_quote(elements, span, file)?,
+        "cond" | "match" => parse_clauses(elements, span, file)?,
+        "switch" => parse_switch(elements, span, file)?,
+        "let" => parse_let(elements, span, file)?,
+        // Read like a named function, which the expansion pass then takes out.
+        "defmacro" => Node::Call {
+            name: "defmacro".to_string(),
+            args: vec![parse_function(elements, span, file)?],
+            span,
+        },
+        _ => parse_function_call(elements, span, file)?,
     };
 
     Ok(node)
 }
 
-pub fn parse_function(elements: Vec<Pair<Rule>>) -> std::io::Result<Node> {
-    let name = match elements[1].as_rule() {
-        Rule::identifier => elements[1].as_str().to_string(),
+pub fn parse_function(elements: Vec<Pair<Rule>>, span: Span, file: usize) -> ParseResult<Node> {
+    let keyword = elements[0].as_str();
+    let name = match elements.get(1) {
+        Some(elem) if elem.as_rule() == Rule::identifier => elem.as_str().to_string(),
         _ => "".to_string(),
     };
 
     let param_start = if name.len() > 0 { 2 } else { 1 };
 
-    let parameters: Vec<_> = elements[param_start].clone().into_inner().collect();
-    let params = parse_parameters(parameters)?;
+    let parameters: Vec<_> = match elements.get(param_start) {
+        Some(elem) if elem.as_rule() == Rule::sexp => elem.clone().into_inner().collect(),
+        _ => return Err(Diagnostic::new(format!("`{keyword}` expects a parameter list"), span)),
+    };
+    let params = parse_parameters(parameters, file)?;
 
     let mut body = vec![];
     for elem in elements.iter().skip(param_start+1) {
-        body.push(parse_expression(elem)?);
+        body.push(parse_expression(elem, file)?);
     }
 
     Ok(Node::Function {
         name,
         params,
         body,
+        env: Env::default(),
+        span,
     })
 }
 
-pub fn parse_function_call(elements: Vec<Pair<Rule>>) -> std::io::Result<Node> {
+pub fn parse_function_call(elements: Vec<Pair<Rule>>, span: Span, file: usize) -> ParseResult<Node> {
     let name = elements[0].as_str().to_string();
 
     let mut args = vec![];
 
     for elem in elements.iter().skip(1) {
-        args.push(parse_expression(elem)?);
+        args.push(parse_expression(elem, file)?);
+    }
+
+    Ok(Node::Call {
+        name,
+        args,
+        span,
+    })
+}
+
+/// `cond` and `match` clauses start with an expression or a pattern rather
+/// than a name, so they are read as lists:
+/// `(cond ((lt x 0) "negative") (else "positive"))`. The value a `match`
+/// looks at comes before its clauses.
+pub fn parse_clauses(elements: Vec<Pair<Rule>>, span: Span, file: usize) -> ParseResult<Node> {
+    let name = elements[0].as_str().to_string();
+    let first_clause = if name == "match" { 2 } else { 1 };
+
+    let mut args = vec![];
+    for (i, elem) in elements.iter().enumerate().skip(1) {
+        if i >= first_clause && elem.as_rule() == Rule::sexp {
+            let mut clause = vec![];
+            for item in elem.clone().into_inner() {
+                clause.push(parse_expression(&item, file)?);
+            }
+            if name == "match" {
+                check_match_clause(&clause, Span::from_pest(file, elem.as_span()))?;
+            }
+            args.push(Node::List(clause));
+        } else if i >= first_clause && name == "match" {
+            return Err(Diagnostic::new("`match` clauses are written (pattern body...)", Span::from_pest(file, elem.as_span())));
+        } else {
+            args.push(parse_expression(elem, file)?);
+        }
+    }
+
+    if name == "match" && args.is_empty() {
+        return Err(Diagnostic::new("`match` expects a value to match", span));
     }
 
     Ok(Node::Call {
         name,
         args,
+        span,
+    })
+}
+
+fn check_match_clause(clause: &[Node], span: Span) -> ParseResult<()> {
+    match clause {
+        [] => Err(Diagnostic::new("`match` clauses are written (pattern body...)", span)),
+        [_, Node::Identifier(guard, _)] if guard == ":when" => {
+            Err(Diagnostic::new("`:when` must be followed by a guard", span))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// `(switch x (case 1 "one") "other")` is read as a `match` whose literal
+/// cases are patterns, lists of them included. The other cases, and the
+/// elements of a list that aren't literals, are compared with `eq` to the
+/// value they stand for, bound under a name no source can use.
+pub fn parse_switch(elements: Vec<Pair<Rule>>, span: Span, file: usize) -> ParseResult<Node> {
+    let Node::Call { args, .. } = parse_function_call(elements, span, file)? else { unreachable!() };
+    let Some((value, cases)) = args.split_first() else {
+        return Err(Diagnostic::new("`switch` expects a value to compare", span));
+    };
+
+    let mut clauses = vec![];
+    for (i, case) in cases.iter().enumerate() {
+        match case {
+            Node::Call { name, args, span } if name == "case" => {
+                let [expected, body] = args.as_slice() else {
+                    return Err(Diagnostic::new(format!("`case` expects 2 elements, found {}", args.len()), *span));
+                };
+
+                let mut guards = vec![];
+                let pattern = switch_pattern(expected, *span, &mut guards);
+                let clause = match guards.len() {
+                    0 => vec![pattern, body.clone()],
+                    1 => vec![pattern, Node::Identifier(":when".to_string(), *span), guards.remove(0), body.clone()],
+                    _ => {
+                        let guard = Node::Call { name: "and".to_string(), args: guards, span: *span };
+                        vec![pattern, Node::Identifier(":when".to_string(), *span), guard, body.clone()]
+                    },
+                };
+                clauses.push(Node::List(clause));
+            },
+            default if i + 1 == cases.len() => {
+                clauses.push(Node::List(vec![Node::Identifier("_".to_string(), span), default.clone()]));
+            },
+            _ => {
+                let at = if case.span().is_known() { case.span() } else { span };
+                return Err(Diagnostic::new("`switch` expects `case` statements, and a default value last", at));
+            },
+        }
+    }
+
+    let mut args = vec![value.clone()];
+    args.extend(clauses);
+
+    Ok(Node::Call {
+        name: "match".to_string(),
+        args,
+        span,
+    })
+}
+
+/// The pattern a `switch` case is matched with, pushing in `guards` the
+/// comparisons of the values that can't be patterns.
+fn switch_pattern(expected: &Node, span: Span, guards: &mut Vec<Node>) -> Node {
+    match expected {
+        Node::Integer(_) | Node::Float(_) | Node::String(_) => expected.clone(),
+        Node::List(items) => Node::List(items.iter().map(|item| switch_pattern(item, span, guards)).collect()),
+        _ => {
+            let bound = Node::Identifier(format!("switch#{}", guards.len()), span);
+            guards.push(Node::Call { name: "eq".to_string(), args: vec![bound.clone(), expected.clone()], span });
+            bound
+        },
+    }
+}
+
+/// `(let ((a 1) (b 2)) body...)` keeps its bindings as a map from the
+/// names, or patterns, to their values. The other `let`s are plain calls.
+pub fn parse_let(elements: Vec<Pair<Rule>>, span: Span, file: usize) -> ParseResult<Node> {
+    if elements.len() < 2 || elements[1].as_rule() != Rule::sexp {
+        return parse_function_call(elements, span, file);
+    }
+
+    let mut bindings = vec![];
+    for binding in elements[1].clone().into_inner() {
+        let items: Vec<_> = binding.clone().into_inner().collect();
+        if binding.as_rule() != Rule::sexp || items.len() != 2 {
+            return Err(Diagnostic::new("`let` bindings are written (name value)", Span::from_pest(file, binding.as_span())));
+        }
+        bindings.push((parse_expression(&items[0], file)?, parse_expression(&items[1], file)?));
+    }
+
+    let mut args = vec![Node::Map(bindings)];
+    for elem in elements.iter().skip(2) {
+        args.push(parse_expression(elem, file)?);
+    }
+
+    Ok(Node::Call {
+        name: "let".to_string(),
+        args,
+        span,
     })
 }
 
-pub fn parse_parameters(parameters: Vec<Pair<Rule>>) -> std::io::Result<Vec<Param>> {
+pub fn parse_quote(elements: Vec<Pair<Rule>>, span: Span, file: usize) -> ParseResult<Node> {
+    let name = elements[0].as_str().to_string();
+    let quasi = name == "quasiquote";
+
+    let mut args = vec![];
+    for elem in elements.iter().skip(1) {
+        args.push(parse_datum(elem, file, quasi)?);
+    }
+
+    Ok(Node::Call {
+        name,
+        args,
+        span,
+    })
+}
+
+/// Parses a quoted element as data: lists become `Node::List` and
+/// identifiers become `Node::Symbol`. Inside a quasiquote, `unquote` and
+/// `unquote-splicing` forms are kept as calls to be evaluated.
+pub fn parse_datum(pair: &Pair<Rule>, file: usize, quasi: bool) -> ParseResult<Node> {
+    let node = match pair.as_rule() {
+        Rule::identifier => Node::Symbol(pair.as_str().to_string()),
+        Rule::sexp => {
+            let inner: Vec<_> = pair.clone().into_inner().collect();
+            let is_unquote = !inner.is_empty()
+                && inner[0].as_rule() == Rule::identifier
+                && matches!(inner[0].as_str(), "unquote" | "unquote-splicing");
+
+            if quasi && is_unquote {
+                parse_function_call(inner, Span::from_pest(file, pair.as_span()), file)?
+            } else {
+                let mut items = vec![];
+                for elem in &inner {
+                    items.push(parse_datum(elem, file, quasi)?);
+                }
+                Node::List(items)
+            }
+        },
+        Rule::vector => {
+            let mut items = vec![];
+            for elem in pair.clone().into_inner() {
+                items.push(parse_datum(&elem, file, quasi)?);
+            }
+            Node::List(items)
+        },
+        Rule::map => {
+            let mut items = vec![];
+            for elem in pair.clone().into_inner() {
+                items.push(parse_datum(&elem, file, quasi)?);
+            }
+            Node::Map(pair_up(items, pair, file)?)
+        },
+        Rule::unquote | Rule::unquote_splicing if quasi => parse_expression(pair, file)?,
+        Rule::quote | Rule::quasiquote | Rule::unquote | Rule::unquote_splicing => {
+            let name = quote_name(pair.as_rule()).to_string();
+            let inner = pair.clone().into_inner().next().unwrap();
+            Node::List(vec![Node::Symbol(name), parse_datum(&inner, file, quasi)?])
+        },
+        _ => parse_expression(pair, file)?,
+    };
+
+    Ok(node)
+}
+
+fn quote_name(rule: Rule) -> &'static str {
+    match rule {
+        Rule::quote => "quote",
+        Rule::quasiquote => "quasiquote",
+        Rule::unquote => "unquote",
+        _ => "unquote-splicing",
+    }
+}
+
+pub fn parse_parameters(parameters: Vec<Pair<Rule>>, file: usize) -> ParseResult<Vec<Param>> {
     let mut params = vec![];
-    for elem in &parameters {
+    for (i, elem) in parameters.iter().enumerate() {
         match elem.as_rule() {
             Rule::sexp => {
                 let data: Vec<_> = elem.clone().into_inner().collect();
+                let [name, default_value] = data.as_slice() else {
+                    return Err(Diagnostic::new(
+                        "a parameter with a default value is written (name value)",
+                        Span::from_pest(file, elem.as_span()),
+                    ));
+                };
+                if name.as_rule() != Rule::identifier {
+                    return Err(Diagnostic::new(
+                        format!("expected a parameter name, found `{}`", name.as_str()),
+                        Span::from_pest(file, name.as_span()),
+                    ));
+                }
+
                 params.push(Param {
-                    name: data[0].as_str().to_string(),
-                    default_value: Some(parse_expression(&data[1])?),
+                    name: name.as_str().to_string(),
+                    default_value: Some(parse_expression(default_value, file)?),
+                    rest: false,
                 });
             },
-            _ => {
+            Rule::identifier if elem.as_str() == "&rest" => {
+                let name = match &parameters[i + 1..] {
+                    [name] if name.as_rule() == Rule::identifier => name.as_str().to_string(),
+                    _ => return Err(Diagnostic::new(
+                        "`&rest` must be followed by a single parameter name",
+                        Span::from_pest(file, elem.as_span()),
+                    )),
+                };
+
+                params.push(Param {
+                    name,
+                    default_value: None,
+                    rest: true,
+                });
+                break;
+            },
+            Rule::identifier => {
                 params.push(Param {
                     name: elem.as_str().to_string(),
                     default_value: None,
+                    rest: false,
                 });
             },
+            _ => return Err(Diagnostic::new(
+                format!("expected a parameter name, found `{}`", elem.as_str()),
+                Span::from_pest(file, elem.as_span()),
+            )),
         };
     }
 
     Ok(params)
 }
 
-pub fn parse_expression(pair: &Pair<Rule>) -> std::io::Result<Node> {
+pub fn parse_expression(pair: &Pair<Rule>, file: usize) -> ParseResult<Node> {
     let node = match pair.as_rule() {
-        Rule::number => {
-            let i = pair.as_str().parse::<i32>().unwrap();
-            Node::Integer(i)
-        },
-        Rule::float => {
-            let f = pair.as_str().parse::<f32>().unwrap();
-            Node::Float(f)
-        },
+        Rule::number => parse_integer(pair, file)?,
+        Rule::float => parse_float(pair, file)?,
         Rule::sexp => {
+            let span = Span::from_pest(file, pair.as_span());
             let inner: Vec<_> = pair.clone().into_inner().collect();
-            parse_identifier(inner)?
+            parse_identifier(inner, span, file)?
         },
         Rule::identifier => {
-            Node::Identifier(pair.as_str().to_string())
+            Node::Identifier(pair.as_str().to_string(), Span::from_pest(file, pair.as_span()))
         },
-        Rule::string => {
-            let mut s = pair.as_str()[1..].to_string();
-            s.pop();
-            Node::String(s)
+        Rule::quote | Rule::quasiquote | Rule::unquote | Rule::unquote_splicing => {
+            let inner = pair.clone().into_inner().next().unwrap();
+            let arg = match pair.as_rule() {
+                Rule::quote => parse_datum(&inner, file, false)?,
+                Rule::quasiquote => parse_datum(&inner, file, true)?,
+                _ => parse_expression(&inner, file)?,
+            };
+
+            Node::Call {
+                name: quote_name(pair.as_rule()).to_string(),
+                args: vec![arg],
+                span: Span::from_pest(file, pair.as_span()),
+            }
+        },
+        Rule::vector => {
+            let mut items = vec![];
+            for elem in pair.clone().into_inner() {
+                items.push(parse_expression(&elem, file)?);
+            }
+            Node::List(items)
         },
+        Rule::map => {
+            let mut items = vec![];
+            for elem in pair.clone().into_inner() {
+                items.push(parse_expression(&elem, file)?);
+            }
+            Node::Map(pair_up(items, pair, file)?)
+        },
+        Rule::string => parse_string(pair, file)?,
+        Rule::character => parse_character(pair, file)?,
         _ => panic!("parse_expression: {:?}", pair),
     };
 
     Ok(node)
 }
+
+/// Groups the elements of a `{k v ...}` literal into its entries.
+fn pair_up(items: Vec<Node>, pair: &Pair<Rule>, file: usize) -> ParseResult<Vec<(Node, Node)>> {
+    if !items.len().is_multiple_of(2) {
+        return Err(Diagnostic::new(
+            format!("map literal needs a value for every key, found {} elements", items.len()),
+            Span::from_pest(file, pair.as_span()),
+        ));
+    }
+
+    let mut entries = vec![];
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        entries.push((key, value));
+    }
+
+    Ok(entries)
+}
+
+/// Decodes the escape sequences of a string literal.
+fn parse_string(pair: &Pair<Rule>, file: usize) -> ParseResult<Node> {
+    let text = pair.as_str();
+    let mut s = String::new();
+    let mut chars = text[1..text.len() - 1].chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => s.push('\n'),
+            Some('t') => s.push('\t'),
+            Some('r') => s.push('\r'),
+            Some('b') => s.push('\u{8}'),
+            Some('f') => s.push('\u{c}'),
+            Some('0') => s.push('\0'),
+            Some('u') => {
+                let rest = chars.as_str();
+                let (digits, len) = match rest.strip_prefix('{') {
+                    Some(braced) => {
+                        let end = braced.find('}').unwrap();
+                        (&braced[..end], end + 2)
+                    },
+                    None => (&rest[..4], 4),
+                };
+
+                let Some(c) = u32::from_str_radix(digits, 16).ok().and_then(char::from_u32) else {
+                    return Err(Diagnostic::new(
+                        format!("invalid unicode escape `\\u{{{digits}}}`"),
+                        Span::from_pest(file, pair.as_span()),
+                    ));
+                };
+                s.push(c);
+                chars = rest[len..].chars();
+            },
+            Some(c) => s.push(c),
+            None => {},
+        }
+    }
+
+    Ok(Node::String(s))
+}
+
+/// Character literals, `#\a`, `#\(` or `#\space`, are strings of a single
+/// character.
+fn parse_character(pair: &Pair<Rule>, file: usize) -> ParseResult<Node> {
+    let name = &pair.as_str()[2..];
+
+    let c = match name {
+        "space" => ' ',
+        "newline" => '\n',
+        "tab" => '\t',
+        "return" => '\r',
+        "nul" => '\0',
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(Diagnostic::new(
+                    format!("unknown character name `{name}`"),
+                    Span::from_pest(file, pair.as_span()),
+                )),
+            }
+        },
+    };
+
+    Ok(Node::String(c.to_string()))
+}
+
+/// Parses decimal, `0x`, `0b` and `0o` integers, with optional `_`
+/// separators.
+fn parse_integer(pair: &Pair<Rule>, file: usize) -> ParseResult<Node> {
+    let text = pair.as_str().replace('_', "");
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.as_str()),
+    };
+
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        _ => (10, digits),
+    };
+
+    let value = i128::from_str_radix(digits, radix).ok()
+        .map(|v| if negative { -v } else { v })
+        .and_then(|v| i64::try_from(v).ok());
+
+    match value {
+        Some(i) => Ok(Node::Integer(i)),
+        None => Err(Diagnostic::new(
+            format!("integer literal out of range: `{}` does not fit in 64 bits", pair.as_str()),
+            Span::from_pest(file, pair.as_span()),
+        )),
+    }
+}
+
+fn parse_float(pair: &Pair<Rule>, file: usize) -> ParseResult<Node> {
+    let text = pair.as_str().replace('_', "");
+
+    match text.parse::<f64>() {
+        Ok(f) if f.is_finite() => Ok(Node::Float(f)),
+        _ => Err(Diagnostic::new(
+            format!("float literal out of range: `{}` does not fit in 64 bits", pair.as_str()),
+            Span::from_pest(file, pair.as_span()),
+        )),
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::Mutex;
+
+struct SourceFile {
+    name: String,
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+// Every parsed file is registered here so any backend can turn a `Span`
+// back into a file name and a source line.
+static SOURCES: Mutex<Vec<SourceFile>> = Mutex::new(Vec::new());
+
+/// Location of a node in its source file. `file` is the id returned by
+/// `add_source`, 0 means the node has no known location. `line` and
+/// `column` are 1-based, `start` and `end` are byte offsets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub fn add_source(name: &str, text: &str) -> usize {
+    let mut line_starts = vec![0];
+    for (i, c) in text.char_indices() {
+        if c == '\n' {
+            line_starts.push(i + 1);
+        }
+    }
+
+    let mut sources = SOURCES.lock().unwrap();
+    sources.push(SourceFile {
+        name: name.to_string(),
+        text: text.to_string(),
+        line_starts,
+    });
+
+    sources.len()
+}
+
+impl Span {
+    pub fn new(file: usize, start: usize, end: usize) -> Self {
+        let sources = SOURCES.lock().unwrap();
+        let Some(source) = sources.get(file.wrapping_sub(1)) else {
+            return Self::default();
+        };
+
+        let line = match source.line_starts.binary_search(&start) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let line_start = source.line_starts[line];
+        let column = source.text[line_start..start].chars().count() + 1;
+
+        Self {
+            file,
+            line: line + 1,
+            column,
+            start,
+            end,
+        }
+    }
+
+    pub fn from_pest(file: usize, span: pest::Span) -> Self {
+        Self::new(file, span.start(), span.end())
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.file != 0
+    }
+
+    /// Renders the `--> file:line:col` header followed by the source line
+    /// with the spanned text underlined.
+    pub fn render(&self) -> String {
+        let sources = SOURCES.lock().unwrap();
+        let Some(source) = sources.get(self.file.wrapping_sub(1)) else {
+            return String::new();
+        };
+
+        let line_start = source.line_starts[self.line - 1];
+        let line_end = source.line_starts.get(self.line).copied().unwrap_or(source.text.len());
+        let text = source.text[line_start..line_end].trim_end_matches(['\n', '\r']);
+
+        let end = self.end.min(line_start + text.len()).max(self.start);
+        let width = source.text[self.start..end].chars().count().max(1);
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        let mut output = format!("{gutter}--> {}:{}:{}\n", source.name, self.line, self.column);
+        output.push_str(&format!("{gutter} |\n"));
+        output.push_str(&format!("{} | {}\n", self.line, text.replace('\t', " ")));
+        output.push_str(&format!("{gutter} | {}{}", " ".repeat(self.column - 1), "^".repeat(width)));
+
+        output
+    }
+}
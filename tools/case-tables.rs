@@ -0,0 +1,116 @@
+// Generates include/simplelisp-case.h, the case tables of the C++ runtime,
+// from the ones of Rust's std so that `upper` and `lower` give the same
+// strings as in the interpreter:
+//
+//     rustc -O tools/case-tables.rs -o case-tables
+//     ./case-tables > include/simplelisp-case.h
+
+fn chars() -> impl Iterator<Item = char> { (0u32..=0x10FFFF).filter_map(char::from_u32) }
+
+// (first, last, delta, stride)
+fn ranges(pairs: &[(u32, u32)]) -> Vec<(u32, u32, i64, u32)> {
+    let mut out: Vec<(u32, u32, i64, u32)> = vec![];
+    for &(c, m) in pairs {
+        let delta = m as i64 - c as i64;
+        if let Some(last) = out.last_mut() {
+            let (first, end, d, stride) = *last;
+            if d == delta {
+                if end == first && (c - end == 1 || c - end == 2) {
+                    *last = (first, c, d, c - end);
+                    continue;
+                }
+                if end != first && c - end == stride {
+                    last.1 = c;
+                    continue;
+                }
+            }
+        }
+        out.push((c, c, delta, 1));
+    }
+    out
+}
+
+fn sets(codes: &[u32]) -> Vec<(u32, u32)> {
+    let mut out: Vec<(u32, u32)> = vec![];
+    for &c in codes {
+        match out.last_mut() {
+            Some(last) if last.1 + 1 == c => last.1 = c,
+            _ => out.push((c, c)),
+        }
+    }
+    out
+}
+
+fn hex(c: u32) -> String { format!("0x{c:04X}") }
+
+fn u32lit(s: &str) -> String {
+    let mut out = String::from("U\"");
+    for c in s.chars() { out.push_str(&format!("\\U{:08X}", c as u32)); }
+    out.push('"');
+    out
+}
+
+fn mapping(name: &str, f: fn(char) -> String) {
+    let mut simple = vec![];
+    let mut special = vec![];
+    for c in chars() {
+        let m = f(c);
+        let mut it = m.chars();
+        let first = it.next().unwrap();
+        if it.next().is_none() {
+            if first != c { simple.push((c as u32, first as u32)); }
+        } else {
+            special.push((c as u32, m));
+        }
+    }
+    let r = ranges(&simple);
+    println!("inline constexpr CaseRange {name}_ranges[] = {{");
+    for (first, last, delta, stride) in r {
+        println!("    {{ {}, {}, {delta}, {stride} }},", hex(first), hex(last));
+    }
+    println!("}};\n");
+    println!("inline constexpr SpecialCase {name}_special[] = {{");
+    for (c, m) in special {
+        println!("    {{ {}, {} }},", hex(c as u32), u32lit(&m));
+    }
+    println!("}};\n");
+}
+
+fn set(name: &str, codes: &[u32]) {
+    let s = sets(codes);
+    println!("inline constexpr CodeRange {name}[] = {{");
+    for (first, last) in s { println!("    {{ {}, {} }},", hex(first), hex(last)); }
+    println!("}};");
+}
+
+fn main() {
+    let (major, minor, _) = char::UNICODE_VERSION;
+    println!("// Generated by tools/case-tables.rs from Unicode {major}.{minor}, don't edit.\n");
+    println!("#include <cstdint>\n");
+    println!("// Maps the code points from `first` to `last`, every `stride`, by adding `delta`.");
+    println!("struct CaseRange\n{{\n    char32_t first;\n    char32_t last;\n    int32_t delta;\n    uint32_t stride;\n}};\n");
+    println!("// A code point mapped to several ones, such as ß to SS.");
+    println!("struct SpecialCase\n{{\n    char32_t code;\n    const char32_t* mapping;\n}};\n");
+    println!("struct CodeRange\n{{\n    char32_t first;\n    char32_t last;\n}};\n");
+    mapping("upper_case", |c| c.to_uppercase().collect());
+    mapping("lower_case", |c| c.to_lowercase().collect());
+
+    // Probes of the final sigma rule of `str::to_lowercase`, see
+    // `change_case`.
+    let mut ignorable = vec![];
+    let mut cased = vec![];
+    for c in chars() {
+        if c == 'Σ' {
+            cased.push(c as u32);
+            continue;
+        }
+        let p1 = format!("AΣ{c}").to_lowercase().contains('σ');
+        let p2 = format!("AΣ{c}A").to_lowercase().contains('σ');
+        if p1 { cased.push(c as u32); } else if p2 { ignorable.push(c as u32); }
+    }
+    println!("// The code points skipped, and the ones looked for, around a Σ to know");
+    println!("// whether it ends a word and is lowered to ς.");
+    set("case_ignorable", &ignorable);
+    println!();
+    set("cased", &cased);
+}